@@ -0,0 +1,170 @@
+//! A cache of the files that are generated from the books of the libraries, such as
+//! thumbnails and rewritten EPUB files. The files of a book are kept in a directory of
+//! their own, and are named after the version of the book they were generated from, so
+//! that they're regenerated when the book is modified.
+//!
+//! The cached files are served as they are, so the cache's directory must only be
+//! writable by the user that seshat runs as. See [`create_dir`].
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use eyre::{Context as _, bail};
+use tokio::fs;
+
+use crate::{library::Library, utils::hash_str};
+
+/// Returns the default directory of the cache, which is `$XDG_CACHE_HOME/seshat`, or
+/// `~/.cache/seshat`. If there's no home directory, it's in the system's temporary
+/// directory, where another user may have created it first, which [`create_dir`] refuses.
+pub fn default_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("seshat")
+}
+
+/// Creates the directory of the cache, which only the current user may access, if it
+/// doesn't exist. It's refused if it's owned by another user, or if others may write to
+/// it, since they could plant the files that are served from it.
+pub fn create_dir(dir: &Path) -> eyre::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    builder
+        .create(dir)
+        .wrap_err_with(|| format!("Failed to create the cache directory at {dir:?}"))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt as _;
+
+        let metadata = std::fs::metadata(dir)?;
+        // SAFETY: `geteuid` has no preconditions and always succeeds.
+        let uid = unsafe { libc::geteuid() };
+
+        if metadata.uid() != uid {
+            bail!("The cache directory at {dir:?} is owned by another user");
+        }
+
+        if metadata.mode() & 0o022 != 0 {
+            bail!("The cache directory at {dir:?} is writable by other users");
+        }
+    }
+
+    Ok(())
+}
+
+pub struct DiskCache {
+    dir: PathBuf,
+    /// Makes the names of the temporary files unique.
+    tmp_counter: AtomicU64,
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            tmp_counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the path of the book's cached file with the name, which `generate` writes
+    /// to the given path if it's not cached yet. Once it's generated, the other files of
+    /// the book, which were generated from its previous versions, are removed.
+    pub async fn get(
+        &self,
+        lib: &Library,
+        book_id: i64,
+        file_name: &str,
+        generate: impl FnOnce(&Path) -> crate::Result<()> + Send + 'static,
+    ) -> crate::Result<PathBuf> {
+        let dir = self
+            .dir
+            .join(hash_str(lib.name()))
+            .join(book_id.to_string());
+        let path = dir.join(file_name);
+
+        if fs::try_exists(&path).await? {
+            return Ok(path);
+        }
+
+        fs::create_dir_all(&dir).await?;
+
+        let tmp_path = dir.join(format!(
+            ".tmp-{}",
+            self.tmp_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        tokio::task::spawn_blocking({
+            let tmp_path = tmp_path.clone();
+
+            move || {
+                let generated = generate(&tmp_path);
+
+                if generated.is_err() {
+                    let _ = std::fs::remove_file(&tmp_path);
+                }
+
+                generated
+            }
+        })
+        .await??;
+
+        // Renaming is atomic, so concurrent requests never serve a partially written file.
+        fs::rename(&tmp_path, &path).await?;
+
+        let mut entries = fs::read_dir(&dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let is_tmp = entry.file_name().to_string_lossy().starts_with(".tmp-");
+
+            if !is_tmp && entry.path() != path {
+                let _ = fs::remove_file(entry.path()).await;
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory that is removed when it's dropped.
+    struct TestDir(PathBuf);
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_private_dirs() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir =
+            TestDir(std::env::temp_dir().join(format!("seshat-disk-cache-{}", std::process::id())));
+        let cache_dir = dir.0.join("cache");
+
+        create_dir(&cache_dir).unwrap();
+        let mode = std::fs::metadata(&cache_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // Existing directories are reused, unless others may write to them.
+        create_dir(&cache_dir).unwrap();
+
+        std::fs::set_permissions(&cache_dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(create_dir(&cache_dir).is_err());
+    }
+}
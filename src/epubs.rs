@@ -0,0 +1,860 @@
+//! Embeds the metadata of books into their EPUB files when they're downloaded, like
+//! Calibre's content server does. The metadata of an EPUB is only updated by Calibre when
+//! the book is converted or polished, so it often lags behind edits that were made since.
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write as _},
+    path::{Path, PathBuf},
+};
+
+use percent_encoding::percent_decode_str;
+use quick_xml::{
+    NsReader, Reader, Writer,
+    events::{BytesStart, BytesText, Event},
+    name::{Namespace, PrefixDeclaration, ResolveResult},
+    writer::ElementWriter,
+};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    disk_cache::DiskCache,
+    library::{Data, FullBook, Library},
+    utils::hash_str,
+};
+
+/// The ID and the path of the cover that's added to EPUBs without one, relative to the
+/// OPF.
+const ADDED_COVER_ID: &str = "seshat-cover";
+const ADDED_COVER_HREF: &str = "seshat-cover.jpg";
+
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const OPF_NAMESPACE: &str = "http://www.idpf.org/2007/opf";
+
+/// Rewrites EPUB files with the current metadata of their books and caches them on disk.
+pub struct Epubs {
+    cache: DiskCache,
+}
+
+impl Epubs {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self {
+            cache: DiskCache::new(cache_dir.join("epubs")),
+        }
+    }
+
+    /// Returns the path of a copy of the book's EPUB file, whose title, authors, series,
+    /// tags, description and cover are the book's. It's generated if it's not cached yet,
+    /// or if the book was modified since it was cached.
+    pub async fn get(&self, lib: &Library, book: FullBook, data: &Data) -> crate::Result<PathBuf> {
+        let file_name = format!(
+            "{}-{}.epub",
+            book.last_modified_at.unix_timestamp(),
+            hash_str(&data.file_name)
+        );
+        let epub_path = lib.root_path().join(book.file_path(data));
+        let cover_path = book
+            .has_cover
+            .then(|| lib.root_path().join(&*book.path).join("cover.jpg"));
+
+        self.cache
+            .get(lib, book.id, &file_name, move |path| {
+                Ok(embed_metadata(
+                    &epub_path,
+                    path,
+                    &book,
+                    cover_path.as_deref(),
+                )?)
+            })
+            .await
+    }
+}
+
+/// How the cover of the EPUB is changed.
+enum CoverChange {
+    /// The book doesn't have a cover in the library, so the EPUB's one is kept.
+    Keep,
+    /// The image of the manifest item with the ID is replaced.
+    Replace(String),
+    /// The EPUB doesn't have a cover, so one is added.
+    Add,
+}
+
+/// Copies the EPUB at `src` to `dst`, replacing its OPF with one that describes the book,
+/// and its cover with the given one.
+fn embed_metadata(
+    src: &Path,
+    dst: &Path,
+    book: &FullBook,
+    cover_path: Option<&Path>,
+) -> io::Result<()> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(src)?))?;
+
+    let opf_path = opf_path(&mut archive)?;
+    let opf_dir = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let opf = read_to_string(&mut archive, &opf_path)?;
+
+    // A missing cover file shouldn't prevent the rest of the metadata from being embedded.
+    let cover = cover_path.and_then(|path| std::fs::read(path).ok());
+    let (cover_change, cover_entry) = match (&cover, find_cover(&opf).map_err(io::Error::other)?) {
+        (None, _) => (CoverChange::Keep, None),
+        (Some(_), Some((id, href))) => (
+            CoverChange::Replace(id),
+            Some(join_zip_path(
+                opf_dir,
+                &percent_decode_str(&href).decode_utf8_lossy(),
+            )),
+        ),
+        (Some(_), None) => (
+            CoverChange::Add,
+            Some(join_zip_path(opf_dir, ADDED_COVER_HREF)),
+        ),
+    };
+
+    let new_opf = rewrite_opf(&opf, book, &cover_change).map_err(io::Error::other)?;
+
+    let mut writer = ZipWriter::new(BufWriter::new(File::create(dst)?));
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    // JPEG images don't compress any further.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut cover_written = false;
+
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let name = file.name().to_owned();
+
+        if name == opf_path {
+            drop(file);
+            writer.start_file(name, deflated)?;
+            writer.write_all(&new_opf)?;
+        } else if let (Some(cover), Some(cover_entry)) = (&cover, &cover_entry)
+            && name == *cover_entry
+        {
+            drop(file);
+            writer.start_file(name, stored)?;
+            writer.write_all(cover)?;
+            cover_written = true;
+        } else {
+            // The entries are copied without being recompressed, and in the same order, so
+            // the uncompressed `mimetype` entry stays the first one.
+            writer.raw_copy_file(file)?;
+        }
+    }
+
+    if let (Some(cover), Some(cover_entry), false) = (&cover, cover_entry, cover_written) {
+        writer.start_file(cover_entry, stored)?;
+        writer.write_all(cover)?;
+    }
+
+    writer.finish()?.flush()
+}
+
+/// Returns the path of the OPF file in the archive, as given by `META-INF/container.xml`.
+fn opf_path<R: Read + Seek>(archive: &mut ZipArchive<R>) -> io::Result<String> {
+    let container = read_to_string(archive, "META-INF/container.xml")?;
+    let mut reader = Reader::from_str(&container);
+
+    loop {
+        match reader.read_event().map_err(io::Error::other)? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"rootfile" => {
+                if let Some(path) = attribute(&e, "full-path") {
+                    return Ok(path.into_owned());
+                }
+            }
+            Event::Eof => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the EPUB doesn't have a rootfile",
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_to_string<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> io::Result<String> {
+    let mut content = String::new();
+    archive.by_name(name)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Joins a path of the archive with a relative one, resolving its `.` and `..` segments,
+/// as the entries of the archive are named by their normalized paths.
+fn join_zip_path(dir: &str, path: &str) -> String {
+    let mut segments = dir
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    segments.join("/")
+}
+
+/// Returns the value of the attribute, if it's present and valid.
+fn attribute<'a>(e: &'a BytesStart, name: &str) -> Option<Cow<'a, str>> {
+    e.try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attr| attr.unescape_value().ok())
+}
+
+/// Finds the ID and the path of the cover image in the OPF. EPUB 3 marks it with the
+/// `cover-image` property, and EPUB 2 with a `<meta name="cover">` element.
+fn find_cover(opf: &str) -> quick_xml::Result<Option<(String, String)>> {
+    let mut reader = Reader::from_str(opf);
+    let mut cover_id = None;
+    let mut items = vec![];
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"meta" if attribute(&e, "name").as_deref() == Some("cover") => {
+                    cover_id = attribute(&e, "content").map(Cow::into_owned);
+                }
+                b"item" => {
+                    let (Some(id), Some(href)) = (attribute(&e, "id"), attribute(&e, "href"))
+                    else {
+                        continue;
+                    };
+
+                    let is_cover = attribute(&e, "properties")
+                        .is_some_and(|props| props.split_whitespace().any(|p| p == "cover-image"));
+
+                    if is_cover {
+                        return Ok(Some((id.into_owned(), href.into_owned())));
+                    }
+
+                    items.push((id.into_owned(), href.into_owned()));
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(cover_id.and_then(|cover_id| items.into_iter().find(|(id, _)| *id == cover_id)))
+}
+
+/// Returns the OPF with the metadata of the book. The elements that describe the title,
+/// authors, series, tags and description are replaced, and the rest are kept.
+fn rewrite_opf(opf: &str, book: &FullBook, cover: &CoverChange) -> quick_xml::Result<Vec<u8>> {
+    let contributors = find_contributors(opf)?;
+    let mut reader = NsReader::from_str(opf);
+    let mut writer = Writer::new(Vec::with_capacity(opf.len() + 1024));
+    let mut is_epub3 = false;
+    let mut in_metadata = false;
+    // The prefix that the Dublin Core namespace is bound to, which the written elements
+    // use.
+    let mut dc_prefix = None;
+    // The IDs of the removed elements, whose refinements must be removed too.
+    let mut removed_ids = HashSet::new();
+    // The whitespace before the next element, which is removed along with it, and the
+    // whitespace that indents the elements of the metadata.
+    let mut space = None;
+    let mut indent = String::from("\n");
+
+    loop {
+        let event = reader.read_event()?;
+
+        if let Event::Text(text) = &event
+            && text.iter().all(u8::is_ascii_whitespace)
+        {
+            if let Some(space) = space.replace(event) {
+                writer.write_event(space)?;
+            }
+
+            continue;
+        }
+
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let local_name = e.local_name();
+
+                match local_name.as_ref() {
+                    b"package" => {
+                        is_epub3 = attribute(e, "version").is_some_and(|v| v.starts_with('3'));
+                        dc_prefix = bound_prefix(e, DC_NAMESPACE).or(dc_prefix);
+                    }
+                    b"metadata" => {
+                        in_metadata = matches!(event, Event::Start(_));
+                        dc_prefix = bound_prefix(e, DC_NAMESPACE).or(dc_prefix);
+
+                        if in_metadata && dc_prefix.is_none() {
+                            let mut metadata = e.clone();
+                            metadata.push_attribute(("xmlns:dc", DC_NAMESPACE));
+                            dc_prefix = Some(String::from("dc"));
+
+                            if let Some(space) = space.take() {
+                                writer.write_event(space)?;
+                            }
+
+                            writer.write_event(Event::Start(metadata))?;
+                            continue;
+                        }
+                    }
+                    _ if in_metadata && is_replaced(&reader, e, &contributors, &removed_ids) => {
+                        if let Some(id) = attribute(e, "id") {
+                            removed_ids.insert(format!("#{id}"));
+                        }
+
+                        if let Event::Start(e) = &event {
+                            reader.read_to_end(e.to_end().name())?;
+                        }
+
+                        space = None;
+                        continue;
+                    }
+                    _ if in_metadata => {
+                        if let Some(Event::Text(text)) = &space {
+                            indent = String::from_utf8_lossy(text).into_owned();
+                        }
+                    }
+                    b"item" => {
+                        if let CoverChange::Replace(cover_id) = cover
+                            && attribute(e, "id").as_deref() == Some(cover_id)
+                        {
+                            let mut item = e.clone();
+                            item.clear_attributes().extend_attributes(
+                                e.attributes()
+                                    .flatten()
+                                    .filter(|attr| attr.key.as_ref() != b"media-type"),
+                            );
+                            item.push_attribute(("media-type", "image/jpeg"));
+
+                            if let Some(space) = space.take() {
+                                writer.write_event(space)?;
+                            }
+
+                            writer.write_event(match event {
+                                Event::Start(_) => Event::Start(item),
+                                _ => Event::Empty(item),
+                            })?;
+                            continue;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"metadata" => {
+                    in_metadata = false;
+                    let dc_prefix = dc_prefix.as_deref().unwrap_or("dc");
+                    write_metadata(&mut writer, &indent, dc_prefix, book, is_epub3, cover)?;
+                }
+                b"manifest" if matches!(cover, CoverChange::Add) => {
+                    writer.write_event(Event::Text(BytesText::from_escaped(indent.as_str())))?;
+
+                    let mut item = writer.create_element("item").with_attributes([
+                        ("id", ADDED_COVER_ID),
+                        ("href", ADDED_COVER_HREF),
+                        ("media-type", "image/jpeg"),
+                    ]);
+
+                    if is_epub3 {
+                        item = item.with_attribute(("properties", "cover-image"));
+                    }
+
+                    item.write_empty()?;
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+
+        if let Some(space) = space.take() {
+            writer.write_event(space)?;
+        }
+
+        writer.write_event(event)?;
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Returns the prefix that the element binds to the namespace, if any.
+fn bound_prefix(e: &BytesStart, namespace: &str) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find_map(|attr| match attr.key.as_namespace_binding()? {
+            PrefixDeclaration::Named(prefix) if *attr.value == *namespace.as_bytes() => {
+                Some(String::from_utf8_lossy(prefix).into_owned())
+            }
+            _ => None,
+        })
+}
+
+/// Finds the EPUB 3 creators whose role isn't `aut`, such as editors and illustrators,
+/// and returns their IDs as they're referred to, e.g. `#editor`. Their roles are given by
+/// elements that follow them, so they must be found beforehand.
+fn find_contributors(opf: &str) -> quick_xml::Result<HashSet<String>> {
+    let mut reader = Reader::from_str(opf);
+    let mut contributors = HashSet::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e)
+                if e.local_name().as_ref() == b"meta"
+                    && attribute(&e, "property").as_deref() == Some("role") =>
+            {
+                let refines = attribute(&e, "refines").map(Cow::into_owned);
+                let role = reader.read_text(e.name())?;
+
+                if let Some(refines) = refines
+                    && role.trim() != "aut"
+                {
+                    contributors.insert(refines);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(contributors)
+}
+
+/// Returns `true` if the element of the OPF's metadata describes what's replaced by the
+/// book's metadata. Only the creators that are authors are replaced, as the book doesn't
+/// list the other contributors.
+fn is_replaced(
+    reader: &NsReader<&[u8]>,
+    e: &BytesStart,
+    contributors: &HashSet<String>,
+    removed_ids: &HashSet<String>,
+) -> bool {
+    let (namespace, local_name) = reader.resolve_element(e.name());
+
+    if namespace == ResolveResult::Bound(Namespace(DC_NAMESPACE.as_bytes())) {
+        return match local_name.as_ref() {
+            b"title" | b"subject" | b"description" => true,
+            b"creator" => is_author(reader, e, contributors),
+            _ => false,
+        };
+    }
+
+    if local_name.as_ref() != b"meta" {
+        return false;
+    }
+
+    attribute(e, "refines").is_some_and(|refines| removed_ids.contains(refines.as_ref()))
+        || attribute(e, "property").as_deref() == Some("belongs-to-collection")
+        || matches!(
+            attribute(e, "name").as_deref(),
+            Some("calibre:series" | "calibre:series_index")
+        )
+}
+
+/// Returns `true` if the creator is an author, i.e. its role is `aut` or it has none.
+/// EPUB 2 gives the role with the `opf:role` attribute, and EPUB 3 with a refinement.
+fn is_author(reader: &NsReader<&[u8]>, e: &BytesStart, contributors: &HashSet<String>) -> bool {
+    let role = e.attributes().flatten().find_map(|attr| {
+        let (namespace, local_name) = reader.resolve_attribute(attr.key);
+
+        (namespace == ResolveResult::Bound(Namespace(OPF_NAMESPACE.as_bytes()))
+            && local_name.as_ref() == b"role")
+            .then(|| attr.unescape_value().ok())
+            .flatten()
+    });
+
+    role.is_none_or(|role| role.trim() == "aut")
+        && attribute(e, "id").is_none_or(|id| !contributors.contains(&format!("#{id}")))
+}
+
+/// Writes the elements that describe the book, each on its own line with the indentation.
+/// The Dublin Core elements are written with the prefix.
+fn write_metadata(
+    writer: &mut Writer<Vec<u8>>,
+    indent: &str,
+    dc_prefix: &str,
+    book: &FullBook,
+    is_epub3: bool,
+    cover: &CoverChange,
+) -> io::Result<()> {
+    let dc = |name: &str| format!("{dc_prefix}:{name}");
+
+    element(writer, indent, dc("title"))?.write_text_content(BytesText::new(&book.title))?;
+
+    for (i, author) in book.authors.iter().enumerate() {
+        let id = format!("seshat-author-{i}");
+        let creator = element(writer, indent, dc("creator"))?;
+
+        if is_epub3 {
+            creator
+                .with_attribute(("id", id.as_str()))
+                .write_text_content(BytesText::new(&author.name))?;
+            element(writer, indent, "meta")?
+                .with_attributes([
+                    ("refines", format!("#{id}").as_str()),
+                    ("property", "role"),
+                    ("scheme", "marc:relators"),
+                ])
+                .write_text_content(BytesText::new("aut"))?;
+        } else {
+            creator.write_text_content(BytesText::new(&author.name))?;
+        }
+    }
+
+    for tag in &book.tags {
+        element(writer, indent, dc("subject"))?.write_text_content(BytesText::new(tag))?;
+    }
+
+    if let Some(description) = &book.content {
+        element(writer, indent, dc("description"))?
+            .write_text_content(BytesText::new(description))?;
+    }
+
+    if let Some(series) = &book.series {
+        let index = series.index.to_string();
+
+        // Calibre's own elements, which most readers understand.
+        element(writer, indent, "meta")?
+            .with_attributes([
+                ("name", "calibre:series"),
+                ("content", series.name.as_str()),
+            ])
+            .write_empty()?;
+        element(writer, indent, "meta")?
+            .with_attributes([
+                ("name", "calibre:series_index"),
+                ("content", index.as_str()),
+            ])
+            .write_empty()?;
+
+        if is_epub3 {
+            element(writer, indent, "meta")?
+                .with_attributes([
+                    ("property", "belongs-to-collection"),
+                    ("id", "seshat-series"),
+                ])
+                .write_text_content(BytesText::new(&series.name))?;
+            element(writer, indent, "meta")?
+                .with_attributes([
+                    ("refines", "#seshat-series"),
+                    ("property", "collection-type"),
+                ])
+                .write_text_content(BytesText::new("series"))?;
+            element(writer, indent, "meta")?
+                .with_attributes([
+                    ("refines", "#seshat-series"),
+                    ("property", "group-position"),
+                ])
+                .write_text_content(BytesText::new(&index))?;
+        }
+    }
+
+    if let CoverChange::Add = cover {
+        element(writer, indent, "meta")?
+            .with_attributes([("name", "cover"), ("content", ADDED_COVER_ID)])
+            .write_empty()?;
+    }
+
+    Ok(())
+}
+
+/// Starts writing an element of the metadata on its own line.
+fn element<'a>(
+    writer: &'a mut Writer<Vec<u8>>,
+    indent: &str,
+    name: impl Into<Cow<'a, str>>,
+) -> io::Result<ElementWriter<'a, Vec<u8>>> {
+    writer.write_event(Event::Text(BytesText::from_escaped(indent)))?;
+    Ok(writer.create_element(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::library::BookAuthor;
+
+    fn book() -> FullBook {
+        FullBook {
+            id: 1,
+            uuid: None,
+            title: "New Title".into(),
+            author_sort: None,
+            added_at: None,
+            published_at: None,
+            last_modified_at: OffsetDateTime::UNIX_EPOCH,
+            path: "".into(),
+            has_cover: false,
+            series: None,
+            publisher: None,
+            rating: None,
+            identifiers: vec![],
+            authors: vec![BookAuthor {
+                id: 1,
+                name: "New Author".into(),
+            }],
+            languages: vec![],
+            tags: vec![],
+            content: None,
+            data: vec![],
+            custom_values: vec![],
+        }
+    }
+
+    fn rewrite(opf: &str) -> String {
+        String::from_utf8(rewrite_opf(opf, &book(), &CoverChange::Keep).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keeps_the_contributors_of_epub2_books() {
+        let opf = rewrite(
+            r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Old Title</dc:title>
+    <dc:creator opf:role="aut">Old Author</dc:creator>
+    <dc:creator>Another Old Author</dc:creator>
+    <dc:creator opf:role="edt">An Editor</dc:creator>
+    <dc:language>en</dc:language>
+  </metadata>
+</package>"#,
+        );
+
+        for old in ["Old Title", "Old Author"] {
+            assert!(!opf.contains(old), "{old} wasn't replaced in {opf}");
+        }
+
+        for kept in [
+            r#"<dc:creator opf:role="edt">An Editor</dc:creator>"#,
+            "<dc:language>en</dc:language>",
+            "<dc:title>New Title</dc:title>",
+            "<dc:creator>New Author</dc:creator>",
+        ] {
+            assert!(opf.contains(kept), "{kept} is missing from {opf}");
+        }
+    }
+
+    #[test]
+    fn keeps_the_contributors_of_epub3_books() {
+        let opf = rewrite(
+            r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:creator id="author">Old Author</dc:creator>
+    <meta refines="#author" property="role" scheme="marc:relators">aut</meta>
+    <dc:creator id="illustrator">An Illustrator</dc:creator>
+    <meta refines="#illustrator" property="role" scheme="marc:relators">ill</meta>
+  </metadata>
+</package>"##,
+        );
+
+        assert!(!opf.contains("Old Author"));
+        assert!(!opf.contains(r##"refines="#author""##));
+
+        for kept in [
+            r#"<dc:creator id="illustrator">An Illustrator</dc:creator>"#,
+            r##"<meta refines="#illustrator" property="role" scheme="marc:relators">ill</meta>"##,
+            r#"<dc:creator id="seshat-author-0">New Author</dc:creator>"#,
+        ] {
+            assert!(opf.contains(kept), "{kept} is missing from {opf}");
+        }
+    }
+
+    #[test]
+    fn resolves_the_dublin_core_namespace() {
+        let opf = rewrite(
+            r#"<package xmlns="http://www.idpf.org/2007/opf" xmlns:purl="http://purl.org/dc/elements/1.1/" version="2.0">
+  <metadata xmlns:dc="https://example.com/not-dublin-core">
+    <purl:title>Old Title</purl:title>
+    <dc:title>Not a Title</dc:title>
+  </metadata>
+</package>"#,
+        );
+
+        assert!(!opf.contains("Old Title"));
+        assert!(opf.contains("<dc:title>Not a Title</dc:title>"));
+        assert!(opf.contains("<purl:title>New Title</purl:title>"));
+
+        // The namespace is bound if the OPF doesn't bind it.
+        let opf = rewrite(
+            r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata>
+  </metadata>
+</package>"#,
+        );
+
+        assert!(opf.contains(r#"<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">"#));
+        assert!(opf.contains("<dc:title>New Title</dc:title>"));
+    }
+
+    #[test]
+    fn joins_zip_paths() {
+        assert_eq!(join_zip_path("", "cover.jpg"), "cover.jpg");
+        assert_eq!(join_zip_path("OEBPS", "./cover.jpg"), "OEBPS/cover.jpg");
+        assert_eq!(
+            join_zip_path("OEBPS/Text", "../Images/cover.jpg"),
+            "OEBPS/Images/cover.jpg"
+        );
+        assert_eq!(join_zip_path("OEBPS", "../../cover.jpg"), "cover.jpg");
+    }
+
+    /// A directory with an EPUB file, a cover and the rewritten EPUB, which is removed
+    /// when it's dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("seshat-epubs-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("cover.jpg"), "new cover").unwrap();
+
+            Self(path)
+        }
+
+        /// Writes an EPUB whose OPF is at `OEBPS/content.opf`, with the manifest items,
+        /// and the files.
+        fn write_epub(&self, items: &str, files: &[(&str, &str)]) {
+            let mut writer = ZipWriter::new(File::create(self.0.join("book.epub")).unwrap());
+            let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+            let deflated =
+                SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+            writer.start_file("mimetype", stored).unwrap();
+            writer.write_all(b"application/epub+zip").unwrap();
+            writer
+                .start_file("META-INF/container.xml", deflated)
+                .unwrap();
+            writer
+                .write_all(
+                    br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#,
+                )
+                .unwrap();
+            writer.start_file("OEBPS/content.opf", deflated).unwrap();
+            writer
+                .write_all(
+                    format!(
+                        r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Old Title</dc:title>
+  </metadata>
+  <manifest>
+    {items}
+  </manifest>
+</package>"#
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+
+            for (name, content) in files {
+                writer.start_file(*name, deflated).unwrap();
+                writer.write_all(content.as_bytes()).unwrap();
+            }
+
+            writer.finish().unwrap();
+        }
+
+        /// Embeds the metadata and the cover, and returns the entries of the rewritten
+        /// EPUB, along with their contents, in order.
+        fn embed_metadata(&self) -> Vec<(String, CompressionMethod, String)> {
+            let dst = self.0.join("rewritten.epub");
+            embed_metadata(
+                &self.0.join("book.epub"),
+                &dst,
+                &book(),
+                Some(&self.0.join("cover.jpg")),
+            )
+            .unwrap();
+
+            let mut archive = ZipArchive::new(File::open(dst).unwrap()).unwrap();
+
+            (0..archive.len())
+                .map(|i| {
+                    let mut file = archive.by_index(i).unwrap();
+                    let mut content = String::new();
+                    file.read_to_string(&mut content).unwrap();
+
+                    (file.name().to_owned(), file.compression(), content)
+                })
+                .collect()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn names(entries: &[(String, CompressionMethod, String)]) -> Vec<&str> {
+        entries.iter().map(|(name, ..)| name.as_str()).collect()
+    }
+
+    fn content<'a>(entries: &'a [(String, CompressionMethod, String)], name: &str) -> &'a str {
+        &entries.iter().find(|(n, ..)| n == name).unwrap().2
+    }
+
+    #[test]
+    fn replaces_covers() {
+        let dir = TestDir::new("replaced-cover");
+        dir.write_epub(
+            r#"<item id="cover" href="../Images/cover.png" media-type="image/png" properties="cover-image"/>"#,
+            &[("Images/cover.png", "old cover")],
+        );
+
+        let entries = dir.embed_metadata();
+        assert_eq!(
+            names(&entries),
+            [
+                "mimetype",
+                "META-INF/container.xml",
+                "OEBPS/content.opf",
+                "Images/cover.png"
+            ]
+        );
+        assert_eq!(entries[0].1, CompressionMethod::Stored);
+        assert_eq!(content(&entries, "Images/cover.png"), "new cover");
+
+        let opf = content(&entries, "OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>New Title</dc:title>"), "{opf}");
+        assert!(opf.contains(r#"media-type="image/jpeg""#), "{opf}");
+    }
+
+    #[test]
+    fn adds_covers() {
+        let dir = TestDir::new("added-cover");
+        dir.write_epub(
+            r#"<item id="text" href="text.xhtml" media-type="application/xhtml+xml"/>"#,
+            &[("OEBPS/text.xhtml", "<html/>")],
+        );
+
+        let entries = dir.embed_metadata();
+        assert_eq!(
+            names(&entries),
+            [
+                "mimetype",
+                "META-INF/container.xml",
+                "OEBPS/content.opf",
+                "OEBPS/text.xhtml",
+                "OEBPS/seshat-cover.jpg"
+            ]
+        );
+        assert_eq!(entries[0].1, CompressionMethod::Stored);
+        assert_eq!(content(&entries, "OEBPS/seshat-cover.jpg"), "new cover");
+
+        let opf = content(&entries, "OEBPS/content.opf");
+        assert!(opf.contains(r#"href="seshat-cover.jpg""#), "{opf}");
+        assert!(opf.contains("cover-image"), "{opf}");
+    }
+}
@@ -2,7 +2,10 @@ use std::io;
 
 use actix_web::{
     HttpResponse, ResponseError,
-    http::{StatusCode, header::ContentType},
+    http::{
+        StatusCode,
+        header::{self, ContentType},
+    },
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -10,6 +13,51 @@ pub enum AppError {
     #[error("The library could not be found")]
     LibraryNotFound,
 
+    #[error("The book could not be found")]
+    BookNotFound,
+
+    #[error("The author could not be found")]
+    AuthorNotFound,
+
+    #[error("The series could not be found")]
+    SeriesNotFound,
+
+    #[error("The tag could not be found")]
+    TagNotFound,
+
+    #[error("The publisher could not be found")]
+    PublisherNotFound,
+
+    #[error("The language could not be found")]
+    LanguageNotFound,
+
+    #[error("The custom column could not be found")]
+    CustomColumnNotFound,
+
+    #[error("The value of the custom column could not be found")]
+    CustomColumnValueNotFound,
+
+    #[error("The virtual library could not be found")]
+    VirtualLibraryNotFound,
+
+    #[error("The saved search could not be found")]
+    SavedSearchNotFound,
+
+    #[error("The Kobo device could not be found")]
+    KoboDeviceNotFound,
+
+    #[error("Authentication is required")]
+    Unauthorized,
+
+    #[error("The link is invalid or has expired")]
+    InvalidSignature,
+
+    #[error("The library's books can't be searched by their text")]
+    FullTextSearchUnavailable,
+
+    #[error("Invalid query: {0}")]
+    InvalidQuery(#[from] crate::library::query::QueryError),
+
     #[cfg_attr(not(debug_assertions), error("Failed to serialize XML response"))]
     #[cfg_attr(debug_assertions, error("Failed to serialize XML response: {0}"))]
     XmlSerialization(#[from] quick_xml::SeError),
@@ -18,6 +66,10 @@ pub enum AppError {
     #[cfg_attr(debug_assertions, error("serde_urlencoded error: {0}"))]
     UrlEncoding(#[from] serde_urlencoded::ser::Error),
 
+    #[cfg_attr(not(debug_assertions), error("Internal server error"))]
+    #[cfg_attr(debug_assertions, error("image error: {0}"))]
+    Image(#[from] image::ImageError),
+
     #[cfg_attr(not(debug_assertions), error("Internal server error"))]
     #[cfg_attr(debug_assertions, error("rusqilite error: {0}"))]
     Db(#[from] async_sqlite::Error),
@@ -38,7 +90,22 @@ impl ResponseError for AppError {
         use AppError::*;
 
         match self {
-            LibraryNotFound => StatusCode::NOT_FOUND,
+            LibraryNotFound
+            | BookNotFound
+            | AuthorNotFound
+            | SeriesNotFound
+            | TagNotFound
+            | PublisherNotFound
+            | LanguageNotFound
+            | CustomColumnNotFound
+            | CustomColumnValueNotFound
+            | VirtualLibraryNotFound
+            | SavedSearchNotFound
+            | KoboDeviceNotFound
+            | FullTextSearchUnavailable => StatusCode::NOT_FOUND,
+            InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Unauthorized => StatusCode::UNAUTHORIZED,
+            InvalidSignature => StatusCode::FORBIDDEN,
             Io(cause) => match cause.kind() {
                 io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => StatusCode::NOT_FOUND,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
@@ -49,8 +116,16 @@ impl ResponseError for AppError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::plaintext())
+        let mut res = HttpResponse::build(self.status_code());
+
+        if let Self::Unauthorized = self {
+            res.insert_header((
+                header::WWW_AUTHENTICATE,
+                r#"Basic realm="seshat", charset="UTF-8""#,
+            ));
+        }
+
+        res.insert_header(ContentType::plaintext())
             .body(self.to_string())
     }
 }
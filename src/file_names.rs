@@ -0,0 +1,330 @@
+use std::str::FromStr;
+
+use actix_web::http::header::{
+    Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
+
+use crate::library::{Data, FullBook};
+
+/// A template of the names that book files are downloaded as, e.g.
+/// `{author_sort} - {series} {series_index} - {title}.{ext}`. Fields are enclosed in
+/// braces, and the ones without a value, such as the series of a standalone book, are
+/// left empty.
+#[derive(Debug, Clone)]
+pub struct FileNameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// The fields that can be used in a [`FileNameTemplate`].
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Id,
+    Title,
+    Author,
+    Authors,
+    AuthorSort,
+    Series,
+    SeriesIndex,
+    Publisher,
+    Year,
+    Ext,
+}
+
+impl Field {
+    const ALL: [Self; 10] = [
+        Self::Id,
+        Self::Title,
+        Self::Author,
+        Self::Authors,
+        Self::AuthorSort,
+        Self::Series,
+        Self::SeriesIndex,
+        Self::Publisher,
+        Self::Year,
+        Self::Ext,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Title => "title",
+            Self::Author => "author",
+            Self::Authors => "authors",
+            Self::AuthorSort => "author_sort",
+            Self::Series => "series",
+            Self::SeriesIndex => "series_index",
+            Self::Publisher => "publisher",
+            Self::Year => "year",
+            Self::Ext => "ext",
+        }
+    }
+
+    fn value(self, book: &FullBook, data: &Data) -> String {
+        match self {
+            Self::Id => book.id.to_string(),
+            Self::Title => book.title.to_string(),
+            Self::Author => book
+                .authors
+                .first()
+                .map(|author| author.name.to_string())
+                .unwrap_or_default(),
+            Self::Authors => book
+                .authors
+                .iter()
+                .map(|author| author.name.as_str())
+                .collect::<Vec<_>>()
+                .join(" & "),
+            Self::AuthorSort => match &book.author_sort {
+                Some(author_sort) => author_sort.to_string(),
+                None => Self::Authors.value(book, data),
+            },
+            Self::Series => book
+                .series
+                .as_ref()
+                .map(|series| series.name.to_string())
+                .unwrap_or_default(),
+            Self::SeriesIndex => book
+                .series
+                .as_ref()
+                .map(|series| series.index.to_string())
+                .unwrap_or_default(),
+            Self::Publisher => book.publisher.as_deref().unwrap_or_default().to_owned(),
+            // Calibre stores the year 101 when the publication date is unknown.
+            Self::Year => book
+                .published_at
+                .filter(|date| date.year() > 101)
+                .map(|date| date.year().to_string())
+                .unwrap_or_default(),
+            Self::Ext => data.format.to_string(),
+        }
+    }
+}
+
+impl FromStr for FileNameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+
+            let Some(len) = rest[start..].find('}') else {
+                return Err("unclosed '{'".into());
+            };
+            let name = &rest[start + 1..start + len];
+            let Some(field) = Field::ALL.into_iter().find(|field| field.name() == name) else {
+                return Err(format!(
+                    "unknown field {{{name}}}, expected one of {}",
+                    Field::ALL
+                        .iter()
+                        .map(|field| format!("{{{}}}", field.name()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            };
+
+            parts.push(Part::Field(field));
+            rest = &rest[start + len + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+
+        if !parts.iter().any(|part| matches!(part, Part::Field(_))) {
+            return Err("the template must contain at least one field".into());
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+impl FileNameTemplate {
+    /// Renders the name of the book's file in the given format.
+    ///
+    /// Characters that aren't allowed in file names are replaced with underscores, and
+    /// the ` - ` separated segments that end up empty are dropped, so that a template
+    /// such as `{author} - {series} - {title}` doesn't leave a dangling separator for
+    /// books without a series.
+    pub fn render(&self, book: &FullBook, data: &Data) -> String {
+        let mut name = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Field(field) => name.push_str(&sanitize(&field.value(book, data))),
+            }
+        }
+
+        let name = name
+            .split(" - ")
+            .map(|segment| segment.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>()
+            .join(" - ");
+        // Names starting with a dot are hidden on Unix-like systems.
+        let name = name.trim_start_matches(['.', ' ']);
+
+        if name.is_empty() {
+            return format!("{}.{}", data.file_name, data.format);
+        }
+
+        name.to_owned()
+    }
+}
+
+/// Replaces the characters that aren't allowed in the file names of common file systems.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Returns a `Content-Disposition` header that saves the response as a file with the
+/// given name. Names with non-ASCII characters are sent in the RFC 5987 `filename*`
+/// parameter, along with an ASCII fallback for the clients that don't support it.
+pub fn attachment(file_name: &str) -> ContentDisposition {
+    let mut parameters = vec![];
+
+    if file_name.is_ascii() {
+        parameters.push(DispositionParam::Filename(file_name.to_owned()));
+    } else {
+        let fallback = file_name
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect();
+
+        parameters.push(DispositionParam::Filename(fallback));
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".into()),
+            language_tag: None,
+            value: file_name.as_bytes().to_vec(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month, OffsetDateTime};
+
+    use super::*;
+    use crate::library::{BookAuthor, BookSeries};
+
+    fn dracula(published_in: i32) -> (FullBook, Data) {
+        let published_at = Date::from_calendar_date(published_in, Month::May, 26)
+            .unwrap()
+            .midnight()
+            .assume_utc();
+        let book = FullBook {
+            id: 6,
+            uuid: None,
+            title: "Dracula: A Novel".into(),
+            author_sort: Some("Stoker, Bram".into()),
+            added_at: None,
+            published_at: Some(published_at),
+            last_modified_at: OffsetDateTime::UNIX_EPOCH,
+            path: "Bram Stoker/Dracula (6)".into(),
+            has_cover: false,
+            series: None,
+            publisher: None,
+            rating: None,
+            identifiers: vec![],
+            authors: vec![BookAuthor {
+                id: 5,
+                name: "Bram Stoker".into(),
+            }],
+            languages: vec![],
+            tags: vec![],
+            content: None,
+            data: vec![],
+            custom_values: vec![],
+        };
+        let data = Data {
+            file_name: "Dracula - Bram Stoker".into(),
+            file_size: 0,
+            format: "epub".into(),
+            book_id: 6,
+        };
+
+        (book, data)
+    }
+
+    fn render(template: &str, book: &FullBook, data: &Data) -> String {
+        template
+            .parse::<FileNameTemplate>()
+            .unwrap()
+            .render(book, data)
+    }
+
+    #[test]
+    fn renders_fields() {
+        let (mut book, data) = dracula(1897);
+        book.series = Some(BookSeries {
+            id: 1,
+            name: "Gothic Novels".into(),
+            index: 2.0,
+        });
+
+        assert_eq!(
+            render("{title} - {authors}.{ext}", &book, &data),
+            "Dracula_ A Novel - Bram Stoker.epub"
+        );
+        assert_eq!(
+            render(
+                "{author_sort} - {series} {series_index} - {title} ({year}).{ext}",
+                &book,
+                &data
+            ),
+            "Stoker, Bram - Gothic Novels 2 - Dracula_ A Novel (1897).epub"
+        );
+    }
+
+    #[test]
+    fn leaves_out_fields_without_a_value() {
+        // Calibre stores the year 101 when the publication date is unknown.
+        let (book, data) = dracula(101);
+
+        assert_eq!(
+            render(
+                "{series} {series_index} - {year} - {id}.{ext}",
+                &book,
+                &data
+            ),
+            "6.epub"
+        );
+        assert_eq!(
+            render("{publisher}{year}", &book, &data),
+            "Dracula - Bram Stoker.epub"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in ["{titel}.{ext}", "{title.{ext}", "book.epub"] {
+            assert!(
+                template.parse::<FileNameTemplate>().is_err(),
+                "{template} was parsed"
+            );
+        }
+    }
+}
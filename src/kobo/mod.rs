@@ -0,0 +1,434 @@
+//! Storage for the emulation of the Kobo store's sync API, which keeps track of the books
+//! that were synced to each Kobo device, and of their reading states, in a SQLite
+//! database that seshat owns.
+//!
+//! Devices are configured with `--kobo-device`, and are identified by a token that's part
+//! of their API endpoint. Each device syncs the books of one library, optionally narrowed
+//! down by a query, e.g. a shelf or a virtual library.
+
+mod sql;
+
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+use async_sqlite::{
+    JournalMode, Pool, PoolBuilder,
+    rusqlite::{OptionalExtension as _, params},
+};
+use compact_str::CompactString;
+use eyre::{bail, eyre};
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+
+use crate::{
+    library::{BookFilter, BookVersion, Libraries},
+    utils::CompactStringSql,
+};
+
+/// A device, as given by the `--kobo-device TOKEN:LIBRARY[:QUERY]` argument.
+#[derive(Debug, Clone)]
+pub struct KoboDeviceArg {
+    pub token: CompactString,
+    pub lib_name: CompactString,
+    pub query: Option<CompactString>,
+}
+
+impl FromStr for KoboDeviceArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+
+        let (Some(token), Some(lib_name)) = (parts.next(), parts.next()) else {
+            return Err("expected TOKEN:LIBRARY[:QUERY]".into());
+        };
+
+        if token.is_empty()
+            || !token
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~'))
+        {
+            return Err(
+                "the token must only contain letters, digits, '-', '_', '.' and '~'".into(),
+            );
+        }
+
+        Ok(Self {
+            token: token.into(),
+            lib_name: lib_name.into(),
+            query: parts.next().filter(|q| !q.is_empty()).map(Into::into),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct KoboDevice {
+    /// The position of the device's `--kobo-device` argument, starting from 1, which
+    /// identifies it in logs, since its token is a credential.
+    pub number: usize,
+    pub lib_name: CompactString,
+    /// The books that are synced to the device. Only books with an EPUB or KEPUB file are
+    /// included, as Kobo devices can't read the other formats Calibre supports.
+    pub filter: BookFilter,
+}
+
+/// A change to the books of a device, since its last sync.
+#[derive(Debug)]
+pub enum SyncChange {
+    /// The book with the given ID wasn't synced before.
+    New(i64),
+    /// The book with the given ID was modified since it was synced.
+    Changed(i64),
+    /// The book with the given UUID was synced, but it's no longer in the library, or it
+    /// no longer matches the device's query.
+    Removed(CompactString),
+}
+
+pub struct Kobo {
+    db: Pool,
+    devices: HashMap<CompactString, KoboDevice>,
+}
+
+impl Kobo {
+    /// Opens the database at the given path, which is created if it doesn't exist.
+    pub async fn open(
+        path: PathBuf,
+        devices: &[KoboDeviceArg],
+        libraries: &Libraries,
+    ) -> eyre::Result<Self> {
+        let devices = devices
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                let number = i + 1;
+                let Some(lib) = libraries.get(&arg.lib_name, None) else {
+                    bail!(
+                        "The library \"{}\" of the Kobo device #{number} doesn't exist",
+                        arg.lib_name,
+                    );
+                };
+
+                let formats = BookFilter::Or(vec![
+                    BookFilter::Format("epub".into()),
+                    BookFilter::Format("kepub".into()),
+                ]);
+                let filter = match &arg.query {
+                    Some(query) => BookFilter::And(vec![
+                        lib.parse_query(query).map_err(|err| {
+                            eyre!("Invalid query of the Kobo device #{number}: {err}")
+                        })?,
+                        formats,
+                    ]),
+                    None => formats,
+                };
+
+                Ok((
+                    arg.token.clone(),
+                    KoboDevice {
+                        number,
+                        lib_name: arg.lib_name.clone(),
+                        filter,
+                    },
+                ))
+            })
+            .collect::<eyre::Result<_>>()?;
+
+        let db = PoolBuilder::new()
+            .path(&path)
+            .journal_mode(JournalMode::Wal)
+            .open()
+            .await?;
+
+        db.conn(|conn| conn.execute_batch(sql::CREATE_SCHEMA))
+            .await?;
+        debug!("Opened the Kobo sync database at {path:?}");
+
+        Ok(Self { db, devices })
+    }
+
+    pub fn device(&self, token: &str) -> Option<&KoboDevice> {
+        self.devices.get(token)
+    }
+
+    /// Compares the books that should be on the device with the ones that were synced to
+    /// it, and returns up to `limit` changes, which are then considered synced. The
+    /// second value is `true` if there are more changes to sync.
+    ///
+    /// A full sync forgets what was synced to the device before, so every book is new.
+    pub async fn sync(
+        &self,
+        token: &str,
+        versions: Vec<BookVersion>,
+        full: bool,
+        limit: usize,
+    ) -> crate::Result<(Vec<SyncChange>, bool)> {
+        let token = token.to_owned();
+
+        Ok(self
+            .db
+            .conn(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+
+                if full {
+                    tx.execute(sql::DELETE_SYNCED_BOOKS, [&token])?;
+                }
+
+                let mut synced = tx
+                    .prepare_cached(sql::RETRIEVE_SYNCED_BOOKS)?
+                    .query_map([&token], |row| {
+                        Ok((row.get::<_, CompactStringSql>(0)?.0, row.get::<_, i64>(1)?))
+                    })?
+                    .collect::<Result<HashMap<_, _>, _>>()?;
+
+                let mut changes = vec![];
+                let mut has_more = false;
+
+                for version in versions {
+                    let last_modified = micros(version.last_modified_at);
+                    let change = match synced.remove(&version.uuid) {
+                        None => SyncChange::New(version.id),
+                        Some(synced_at) if synced_at != last_modified => {
+                            SyncChange::Changed(version.id)
+                        }
+                        Some(_) => continue,
+                    };
+
+                    if changes.len() == limit {
+                        has_more = true;
+                        break;
+                    }
+
+                    tx.execute(
+                        sql::UPSERT_SYNCED_BOOK,
+                        params![&token, CompactStringSql(version.uuid), last_modified],
+                    )?;
+                    changes.push(change);
+                }
+
+                if !has_more {
+                    for uuid in synced.into_keys() {
+                        if changes.len() == limit {
+                            has_more = true;
+                            break;
+                        }
+
+                        tx.execute(sql::DELETE_SYNCED_BOOK, params![&token, uuid.as_str()])?;
+                        changes.push(SyncChange::Removed(uuid));
+                    }
+                }
+
+                tx.commit()?;
+
+                Ok((changes, has_more))
+            })
+            .await?)
+    }
+
+    /// Returns the reading state the device last reported for the book, as a JSON object.
+    pub async fn find_reading_state(
+        &self,
+        token: &str,
+        book_uuid: &str,
+    ) -> crate::Result<Option<Map<String, Value>>> {
+        let (token, book_uuid) = (token.to_owned(), book_uuid.to_owned());
+
+        let state = self
+            .db
+            .conn(move |conn| {
+                conn.query_row(sql::RETRIEVE_READING_STATE, [token, book_uuid], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()
+            })
+            .await?;
+
+        Ok(state.and_then(|state| serde_json::from_str(&state).ok()))
+    }
+
+    /// Merges the fields of the update into the reading state of the book, and returns
+    /// the new state.
+    pub async fn update_reading_state(
+        &self,
+        token: &str,
+        book_uuid: &str,
+        update: Map<String, Value>,
+    ) -> crate::Result<Map<String, Value>> {
+        let (token, book_uuid) = (token.to_owned(), book_uuid.to_owned());
+
+        Ok(self
+            .db
+            .conn(move |conn| {
+                let tx = conn.unchecked_transaction()?;
+
+                let mut state = tx
+                    .query_row(sql::RETRIEVE_READING_STATE, [&token, &book_uuid], |row| {
+                        row.get::<_, String>(0)
+                    })
+                    .optional()?
+                    .and_then(|state| serde_json::from_str::<Map<_, _>>(&state).ok())
+                    .unwrap_or_default();
+
+                state.extend(update);
+
+                tx.execute(
+                    sql::UPSERT_READING_STATE,
+                    params![
+                        token,
+                        book_uuid,
+                        Value::Object(state.clone()).to_string(),
+                        micros(OffsetDateTime::now_utc()),
+                    ],
+                )?;
+                tx.commit()?;
+
+                Ok(state)
+            })
+            .await?)
+    }
+}
+
+fn micros(datetime: OffsetDateTime) -> i64 {
+    (datetime.unix_timestamp_nanos() / 1000) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser as _;
+    use compact_str::format_compact;
+    use time::Duration;
+
+    use super::*;
+    use crate::Cli;
+
+    /// A sync database that is removed, along with its WAL files, when it's dropped.
+    struct TestDb(PathBuf);
+
+    impl TestDb {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("seshat-kobo-{}-{name}.db", std::process::id())))
+        }
+
+        async fn open(&self) -> Kobo {
+            let mut cli = Cli::parse_from([
+                "seshat",
+                "--lib:name",
+                "Sample",
+                "--lib:path",
+                "data/Library",
+            ]);
+            let libraries = Libraries::from_cli(&mut cli).await.unwrap();
+
+            Kobo::open(self.0.clone(), &[], &libraries).await.unwrap()
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    fn version(id: i64, modified_days_ago: i64) -> BookVersion {
+        BookVersion {
+            id,
+            uuid: format_compact!("uuid-{id}"),
+            last_modified_at: OffsetDateTime::UNIX_EPOCH
+                + Duration::days(10_000 - modified_days_ago),
+        }
+    }
+
+    fn summary(changes: &[SyncChange]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| match change {
+                SyncChange::New(id) => format!("new {id}"),
+                SyncChange::Changed(id) => format!("changed {id}"),
+                SyncChange::Removed(uuid) => format!("removed {uuid}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_sync_changes() {
+        let db = TestDb::new("paging");
+        let kobo = db.open().await;
+        let versions = || vec![version(1, 3), version(2, 3), version(3, 3)];
+
+        let (changes, has_more) = kobo.sync("kobo", versions(), true, 2).await.unwrap();
+        assert_eq!(summary(&changes), ["new 1", "new 2"]);
+        assert!(has_more);
+
+        let (changes, has_more) = kobo.sync("kobo", versions(), false, 2).await.unwrap();
+        assert_eq!(summary(&changes), ["new 3"]);
+        assert!(!has_more);
+
+        let (changes, has_more) = kobo.sync("kobo", versions(), false, 2).await.unwrap();
+        assert!(changes.is_empty());
+        assert!(!has_more);
+
+        // Removals are sent after the new and changed books, and are paged too.
+        let versions = || vec![version(1, 1), version(2, 3), version(4, 1)];
+
+        let (changes, has_more) = kobo.sync("kobo", versions(), false, 2).await.unwrap();
+        assert_eq!(summary(&changes), ["changed 1", "new 4"]);
+        assert!(has_more);
+
+        let (changes, has_more) = kobo.sync("kobo", versions(), false, 2).await.unwrap();
+        assert_eq!(summary(&changes), ["removed uuid-3"]);
+        assert!(!has_more);
+
+        // A full sync starts over, and other devices are tracked separately.
+        let (changes, has_more) = kobo.sync("kobo", versions(), true, 5).await.unwrap();
+        assert_eq!(summary(&changes), ["new 1", "new 2", "new 4"]);
+        assert!(!has_more);
+
+        let (changes, _) = kobo.sync("other", versions(), false, 5).await.unwrap();
+        assert_eq!(changes.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn merges_reading_states() {
+        let db = TestDb::new("reading-states");
+        let kobo = db.open().await;
+
+        assert_eq!(
+            kobo.find_reading_state("kobo", "uuid-1").await.unwrap(),
+            None
+        );
+
+        let update = |value: Value| match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+
+        kobo.update_reading_state(
+            "kobo",
+            "uuid-1",
+            update(serde_json::json!({ "CurrentBookmark": { "ProgressPercent": 10 } })),
+        )
+        .await
+        .unwrap();
+        let state = kobo
+            .update_reading_state(
+                "kobo",
+                "uuid-1",
+                update(serde_json::json!({ "StatusInfo": { "Status": "Reading" } })),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(state["CurrentBookmark"]["ProgressPercent"], 10);
+        assert_eq!(state["StatusInfo"]["Status"], "Reading");
+        assert_eq!(
+            kobo.find_reading_state("kobo", "uuid-1").await.unwrap(),
+            Some(state)
+        );
+        assert_eq!(
+            kobo.find_reading_state("other", "uuid-1").await.unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,40 @@
+pub const CREATE_SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS synced_books (
+        device TEXT NOT NULL,
+        book_uuid TEXT NOT NULL,
+        last_modified INTEGER NOT NULL,
+        PRIMARY KEY (device, book_uuid)
+    );
+
+    CREATE TABLE IF NOT EXISTS reading_states (
+        device TEXT NOT NULL,
+        book_uuid TEXT NOT NULL,
+        state TEXT NOT NULL,
+        last_modified INTEGER NOT NULL,
+        PRIMARY KEY (device, book_uuid)
+    );
+"#;
+
+pub const DELETE_SYNCED_BOOKS: &str = "DELETE FROM synced_books WHERE device = ?1";
+
+pub const RETRIEVE_SYNCED_BOOKS: &str =
+    "SELECT book_uuid, last_modified FROM synced_books WHERE device = ?1";
+
+pub const UPSERT_SYNCED_BOOK: &str = r#"
+    INSERT INTO synced_books (device, book_uuid, last_modified) VALUES (?1, ?2, ?3)
+    ON CONFLICT (device, book_uuid) DO UPDATE SET last_modified = excluded.last_modified
+"#;
+
+pub const DELETE_SYNCED_BOOK: &str =
+    "DELETE FROM synced_books WHERE device = ?1 AND book_uuid = ?2";
+
+pub const RETRIEVE_READING_STATE: &str = r#"
+    SELECT state FROM reading_states WHERE device = ?1 AND book_uuid = ?2
+"#;
+
+pub const UPSERT_READING_STATE: &str = r#"
+    INSERT INTO reading_states (device, book_uuid, state, last_modified) VALUES (?1, ?2, ?3, ?4)
+    ON CONFLICT (device, book_uuid) DO UPDATE SET
+        state = excluded.state,
+        last_modified = excluded.last_modified
+"#;
@@ -0,0 +1,367 @@
+//! Storage for the KOReader progress sync server (kosync), which keeps its users and
+//! their reading progress in a SQLite database that seshat owns.
+//!
+//! KOReader identifies documents by a hash of their content, so the progress of a book
+//! is found by hashing its files the same way. See [`document_hash`].
+
+mod sql;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read as _, Seek as _, SeekFrom},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use async_sqlite::{
+    JournalMode, Pool, PoolBuilder,
+    rusqlite::{self, OptionalExtension as _, Row},
+};
+use compact_str::CompactString;
+use md5::{Digest as _, Md5};
+use parking_lot::Mutex;
+use subtle::ConstantTimeEq as _;
+use time::OffsetDateTime;
+
+use crate::utils::{CompactStringSql, hash_str};
+
+/// The reading progress of a document, as synced by a KOReader device.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub username: CompactString,
+    pub document: CompactString,
+    /// The position in the document, as an XPointer or a page number.
+    pub progress: CompactString,
+    /// The fraction of the document that has been read, from 0 to 1.
+    pub percentage: f64,
+    pub device: CompactString,
+    pub device_id: CompactString,
+    /// When the progress was synced, as a Unix timestamp.
+    pub timestamp: i64,
+}
+
+impl TryFrom<&Row<'_>> for Progress {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            username: row.get::<_, CompactStringSql>("username")?.0,
+            document: row.get::<_, CompactStringSql>("document")?.0,
+            progress: row.get::<_, CompactStringSql>("progress")?.0,
+            percentage: row.get("percentage")?,
+            device: row.get::<_, CompactStringSql>("device")?.0,
+            device_id: row.get::<_, CompactStringSql>("device_id")?.0,
+            timestamp: row.get("timestamp")?,
+        })
+    }
+}
+
+pub struct Kosync {
+    db: Pool,
+    registration_enabled: bool,
+    /// Whether the progress of every user is shown to the catalog's anonymous visitors,
+    /// when authentication is disabled.
+    progress_shared: bool,
+    /// The document hashes of the files of books, along with the modification times of
+    /// the files when they were hashed.
+    document_hashes: Mutex<HashMap<PathBuf, (SystemTime, CompactString)>>,
+}
+
+impl Kosync {
+    /// Opens the database at the given path, which is created if it doesn't exist.
+    pub async fn open(
+        path: PathBuf,
+        registration_enabled: bool,
+        progress_shared: bool,
+    ) -> eyre::Result<Self> {
+        let db = PoolBuilder::new()
+            .path(&path)
+            .journal_mode(JournalMode::Wal)
+            .open()
+            .await?;
+
+        db.conn(|conn| conn.execute_batch(sql::CREATE_SCHEMA))
+            .await?;
+        debug!("Opened the kosync database at {path:?}");
+
+        Ok(Self {
+            document_hashes: Mutex::default(),
+            registration_enabled,
+            progress_shared,
+            db,
+        })
+    }
+
+    pub fn registration_enabled(&self) -> bool {
+        self.registration_enabled
+    }
+
+    /// Creates a user. KOReader sends the MD5 hash of the password as the key, which is
+    /// hashed again before it's stored. Returns `false` if the username is taken.
+    pub async fn create_user(&self, username: &str, key: &str) -> crate::Result<bool> {
+        let key_hash = key_hash(username, key);
+        let username = username.to_owned();
+
+        Ok(self
+            .db
+            .conn(move |conn| {
+                conn.execute(
+                    sql::CREATE_USER,
+                    (
+                        username,
+                        key_hash,
+                        OffsetDateTime::now_utc().unix_timestamp(),
+                    ),
+                )
+            })
+            .await?
+            == 1)
+    }
+
+    /// Returns `true` if the user exists and the key is theirs.
+    pub async fn authenticate(&self, username: &str, key: &str) -> crate::Result<bool> {
+        let expected_hash = key_hash(username, key);
+        let username = username.to_owned();
+        let key_hash = self
+            .db
+            .conn(move |conn| {
+                conn.query_row(sql::RETRIEVE_USER_KEY_HASH, [username], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()
+            })
+            .await?;
+
+        // The hashes are compared in constant time, so that they can't be guessed from how
+        // long the comparison takes.
+        Ok(key_hash
+            .is_some_and(|key_hash| key_hash.as_bytes().ct_eq(expected_hash.as_bytes()).into()))
+    }
+
+    /// Stores the progress of a user's document, replacing the previous one.
+    pub async fn update_progress(&self, progress: Progress) -> crate::Result<()> {
+        self.db
+            .conn(move |conn| {
+                conn.execute(
+                    sql::UPSERT_PROGRESS,
+                    (
+                        CompactStringSql(progress.username),
+                        CompactStringSql(progress.document),
+                        CompactStringSql(progress.progress),
+                        progress.percentage,
+                        CompactStringSql(progress.device),
+                        CompactStringSql(progress.device_id),
+                        progress.timestamp,
+                    ),
+                )
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_progress(
+        &self,
+        username: &str,
+        document: &str,
+    ) -> crate::Result<Option<Progress>> {
+        let (username, document) = (username.to_owned(), document.to_owned());
+
+        Ok(self
+            .db
+            .conn(move |conn| {
+                conn.query_row(sql::RETRIEVE_PROGRESS, (username, document), |row| {
+                    Progress::try_from(row)
+                })
+                .optional()
+            })
+            .await?)
+    }
+
+    /// Finds the most recent progress that the user synced for any of the files, given
+    /// their absolute paths. Files that can't be read are skipped.
+    ///
+    /// Without a user, i.e. if authentication is disabled, the progress of any user is
+    /// found, but only if progress is shared.
+    pub async fn find_latest_progress(
+        &self,
+        username: Option<&str>,
+        files: Vec<PathBuf>,
+    ) -> crate::Result<Option<Progress>> {
+        if username.is_none() && !self.progress_shared {
+            return Ok(None);
+        }
+
+        let username = username.map(str::to_owned);
+        let mut documents = vec![];
+
+        for path in files {
+            match self.document_hash(path.clone()).await {
+                Ok(document) => documents.push(serde_json::Value::from(document.as_str())),
+                Err(err) => debug!("Failed to hash {path:?}: {err}"),
+            }
+        }
+
+        if documents.is_empty() {
+            return Ok(None);
+        }
+
+        let documents = serde_json::Value::Array(documents).to_string();
+
+        Ok(self
+            .db
+            .conn(move |conn| {
+                conn.query_row(
+                    sql::RETRIEVE_LATEST_PROGRESS,
+                    (documents, username),
+                    |row| Progress::try_from(row),
+                )
+                .optional()
+            })
+            .await?)
+    }
+
+    /// Returns the document hash of the file, which is cached until the file is modified.
+    async fn document_hash(&self, path: PathBuf) -> io::Result<CompactString> {
+        let modified_at = tokio::fs::metadata(&path).await?.modified()?;
+
+        if let Some((hashed_at, hash)) = self.document_hashes.lock().get(&path)
+            && *hashed_at == modified_at
+        {
+            return Ok(hash.clone());
+        }
+
+        let hash = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || document_hash(&path)
+        })
+        .await??;
+
+        self.document_hashes
+            .lock()
+            .insert(path, (modified_at, hash.clone()));
+
+        Ok(hash)
+    }
+}
+
+fn key_hash(username: &str, key: &str) -> String {
+    hash_str(&format!("{username}\0{key}"))
+}
+
+/// Computes the hash KOReader identifies documents by when its "binary" matching method
+/// is used, which is the default. It's the MD5 hash of 1 KiB samples of the file, taken
+/// at offsets 0, 1 KiB, 4 KiB, 16 KiB and so on, up to 1 GiB.
+///
+/// See `util.partialMD5` in KOReader's source code.
+pub fn document_hash(path: &Path) -> io::Result<CompactString> {
+    const SAMPLE_SIZE: u64 = 1024;
+
+    let mut file = File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE as usize);
+
+    for i in -1..=10 {
+        let offset = if i < 0 { 0 } else { SAMPLE_SIZE << (2 * i) };
+
+        file.seek(SeekFrom::Start(offset))?;
+        sample.clear();
+        (&mut file).take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+
+        if sample.is_empty() {
+            break;
+        }
+
+        hasher.update(&sample);
+    }
+
+    let hash = hasher.finalize();
+    let mut hash_buf = [0; 32];
+
+    Ok(base16ct::lower::encode_str(&hash, &mut hash_buf)
+        .expect("hex encoding failed")
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory with a book file and a sync database, which is removed when it's
+    /// dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("seshat-kosync-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::write(path.join("book.epub"), "The text of the book").unwrap();
+
+            Self(path)
+        }
+
+        fn book(&self) -> PathBuf {
+            self.0.join("book.epub")
+        }
+
+        async fn open(&self, progress_shared: bool) -> Kosync {
+            Kosync::open(self.0.join("kosync.db"), true, progress_shared)
+                .await
+                .unwrap()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_the_progress_of_the_user() {
+        let dir = TestDir::new("progress");
+        let kosync = dir.open(false).await;
+        let document = document_hash(&dir.book()).unwrap();
+
+        for (username, percentage, timestamp) in [("alice", 0.25, 1), ("bob", 0.75, 2)] {
+            kosync.create_user(username, "key").await.unwrap();
+            kosync
+                .update_progress(Progress {
+                    username: username.into(),
+                    document: document.clone(),
+                    progress: "/body/DocFragment[2]".into(),
+                    percentage,
+                    device: "Kobo".into(),
+                    device_id: "kobo".into(),
+                    timestamp,
+                })
+                .await
+                .unwrap();
+        }
+
+        let latest = async |kosync: &Kosync, username| {
+            kosync
+                .find_latest_progress(username, vec![dir.book(), dir.0.join("missing.epub")])
+                .await
+                .unwrap()
+                .map(|progress| (progress.username, progress.percentage))
+        };
+
+        assert_eq!(
+            latest(&kosync, Some("alice")).await,
+            Some(("alice".into(), 0.25))
+        );
+        assert_eq!(
+            latest(&kosync, Some("bob")).await,
+            Some(("bob".into(), 0.75))
+        );
+        assert_eq!(latest(&kosync, Some("carol")).await, None);
+        assert_eq!(latest(&kosync, None).await, None);
+
+        // Without authentication, anyone's progress is shown if it's shared.
+        let kosync = dir.open(true).await;
+        assert_eq!(latest(&kosync, None).await, Some(("bob".into(), 0.75)));
+    }
+}
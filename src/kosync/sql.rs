@@ -0,0 +1,54 @@
+pub const CREATE_SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS users (
+        username TEXT NOT NULL PRIMARY KEY,
+        key_hash TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS progress (
+        username TEXT NOT NULL REFERENCES users (username) ON DELETE CASCADE,
+        document TEXT NOT NULL,
+        progress TEXT NOT NULL,
+        percentage REAL NOT NULL,
+        device TEXT NOT NULL,
+        device_id TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        PRIMARY KEY (username, document)
+    );
+
+    CREATE INDEX IF NOT EXISTS progress_document ON progress (document);
+"#;
+
+pub const CREATE_USER: &str = r#"
+    INSERT INTO users (username, key_hash, created_at) VALUES (?1, ?2, ?3)
+    ON CONFLICT (username) DO NOTHING
+"#;
+
+pub const RETRIEVE_USER_KEY_HASH: &str = "SELECT key_hash FROM users WHERE username = ?1";
+
+pub const UPSERT_PROGRESS: &str = r#"
+    INSERT INTO progress (username, document, progress, percentage, device, device_id, timestamp)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+    ON CONFLICT (username, document) DO UPDATE SET
+        progress = excluded.progress,
+        percentage = excluded.percentage,
+        device = excluded.device,
+        device_id = excluded.device_id,
+        timestamp = excluded.timestamp
+"#;
+
+pub const RETRIEVE_PROGRESS: &str = r#"
+    SELECT username, document, progress, percentage, device, device_id, timestamp
+    FROM progress
+    WHERE username = ?1 AND document = ?2
+"#;
+
+/// The most recent progress of the documents, which are passed as a JSON array in the
+/// first parameter.
+pub const RETRIEVE_LATEST_PROGRESS: &str = r#"
+    SELECT username, document, progress, percentage, device, device_id, timestamp
+    FROM progress
+    WHERE document IN (SELECT value FROM json_each(?1)) AND (?2 IS NULL OR username = ?2)
+    ORDER BY timestamp DESC
+    LIMIT 1
+"#;
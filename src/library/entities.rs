@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use async_sqlite::rusqlite::{Error, Row};
 use compact_str::{CompactString, format_compact};
 use time::OffsetDateTime;
@@ -9,16 +11,26 @@ pub struct FullBook {
     pub id: i64,
     pub uuid: Option<CompactString>,
     pub title: CompactString,
+    /// The names of the book's authors, as they're sorted, e.g. "Stoker, Bram".
+    pub author_sort: Option<CompactString>,
     pub added_at: Option<OffsetDateTime>,
     pub published_at: Option<OffsetDateTime>,
     pub last_modified_at: OffsetDateTime,
     pub path: CompactString,
     pub has_cover: bool,
-    pub authors: Vec<CompactString>,
+    pub series: Option<BookSeries>,
+    pub publisher: Option<CompactString>,
+    /// The rating of the book, from 1 to 10. Each star counts as 2 points.
+    pub rating: Option<u8>,
+    pub identifiers: Vec<BookIdentifier>,
+    pub authors: Vec<BookAuthor>,
     pub languages: Vec<CompactString>,
     pub tags: Vec<CompactString>,
     pub content: Option<CompactString>,
     pub data: Vec<Data>,
+    /// The values of the library's custom columns, in the order of the columns. Columns
+    /// without a value for the book are omitted.
+    pub custom_values: Vec<BookCustomValue>,
 }
 
 impl TryFrom<&Row<'_>> for FullBook {
@@ -26,6 +38,14 @@ impl TryFrom<&Row<'_>> for FullBook {
 
     fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
         let path = row.get::<_, CompactStringSql>("path")?.0;
+        let series = match row.get::<_, Option<i64>>("series_id")? {
+            Some(id) => Some(BookSeries {
+                name: row.get::<_, CompactStringSql>("series_name")?.0,
+                index: row.get("series_index")?,
+                id,
+            }),
+            None => None,
+        };
 
         Ok(Self {
             last_modified_at: row.get("last_modified_at")?,
@@ -36,10 +56,23 @@ impl TryFrom<&Row<'_>> for FullBook {
                 .get::<_, Option<CompactStringSql>>("comment")?
                 .map(|str| str.0),
             title: row.get::<_, CompactStringSql>("title")?.0,
+            author_sort: row
+                .get::<_, Option<CompactStringSql>>("author_sort")?
+                .map(|str| str.0)
+                .filter(|str| !str.is_empty()),
             uuid: row
                 .get::<_, Option<CompactStringSql>>("uuid")?
                 .map(|str| str.0),
+            publisher: row
+                .get::<_, Option<CompactStringSql>>("publisher_name")?
+                .map(|str| str.0),
+            rating: row
+                .get::<_, Option<u8>>("rating")?
+                .filter(|&rating| rating > 0),
             id: row.get("id")?,
+            series,
+            custom_values: vec![],
+            identifiers: vec![],
             languages: vec![],
             authors: vec![],
             data: vec![],
@@ -58,9 +91,232 @@ impl FullBook {
 
         format_compact!("urn:id:{}", self.id)
     }
+
+    /// Returns the path of the book's file in the given format, relative to the library's
+    /// root.
+    pub fn file_path(&self, data: &Data) -> PathBuf {
+        Path::new(self.path.as_str()).join(format!("{}.{}", data.file_name, data.format))
+    }
+}
+
+/// The version of a book, which changes whenever the book is modified.
+#[derive(Debug)]
+pub struct BookVersion {
+    pub id: i64,
+    pub uuid: CompactString,
+    pub last_modified_at: OffsetDateTime,
+}
+
+impl TryFrom<&Row<'_>> for BookVersion {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            uuid: row.get::<_, CompactStringSql>("uuid")?.0,
+            last_modified_at: row.get("last_modified_at")?,
+            id: row.get("id")?,
+        })
+    }
+}
+
+/// The cover of a book.
+#[derive(Debug)]
+pub struct BookCover {
+    pub book_id: i64,
+    /// The path of the book's directory, relative to the library's root.
+    pub path: CompactString,
+    pub last_modified_at: OffsetDateTime,
+}
+
+impl TryFrom<&Row<'_>> for BookCover {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            path: row.get::<_, CompactStringSql>("path")?.0,
+            last_modified_at: row.get("last_modified_at")?,
+            book_id: row.get("id")?,
+        })
+    }
+}
+
+/// An author of a book.
+#[derive(Debug)]
+pub struct BookAuthor {
+    pub id: i64,
+    pub name: CompactString,
+}
+
+/// The series a book belongs to.
+#[derive(Debug)]
+pub struct BookSeries {
+    pub id: i64,
+    pub name: CompactString,
+    /// The position of the book in the series.
+    pub index: f64,
+}
+
+/// An identifier of a book, such as its ISBN.
+#[derive(Debug)]
+pub struct BookIdentifier {
+    /// The kind of the identifier, in lowercase, e.g. `isbn` or `amazon`.
+    pub kind: CompactString,
+    pub value: CompactString,
+}
+
+/// The value of a custom column for a book.
+#[derive(Debug)]
+pub struct BookCustomValue {
+    /// The name of the column.
+    pub name: CompactString,
+    pub value: CustomValue,
+}
+
+#[derive(Debug)]
+pub enum CustomValue {
+    /// The values of a text or enumeration column. Text columns may have multiple values.
+    Text(Vec<CompactString>),
+    Bool(bool),
+    /// A rating from 1 to 10. Each star counts as 2 points.
+    Rating(u8),
+    Date(OffsetDateTime),
+}
+
+/// A column that was added to the library by the user in Calibre.
+#[derive(Debug, Clone)]
+pub struct CustomColumn {
+    pub id: i64,
+    /// The lookup name of the column, without the `#` prefix.
+    pub label: CompactString,
+    pub name: CompactString,
+    pub kind: CustomColumnKind,
+    /// Whether the books can be browsed by the values of the column.
+    pub browseable: bool,
+}
+
+impl TryFrom<&Row<'_>> for CustomColumn {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        let datatype = row.get::<_, CompactStringSql>("datatype")?.0;
+        let Some(kind) = CustomColumnKind::from_datatype(&datatype) else {
+            return Err(Error::InvalidColumnType(
+                row.as_ref().column_index("datatype")?,
+                datatype.into(),
+                async_sqlite::rusqlite::types::Type::Text,
+            ));
+        };
+
+        Ok(Self {
+            label: row.get::<_, CompactStringSql>("label")?.0,
+            name: row.get::<_, CompactStringSql>("name")?.0,
+            id: row.get("id")?,
+            browseable: false,
+            kind,
+        })
+    }
+}
+
+/// The data types of custom columns that are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomColumnKind {
+    Text,
+    Enumeration,
+    Bool,
+    Rating,
+    Date,
+}
+
+impl CustomColumnKind {
+    fn from_datatype(datatype: &str) -> Option<Self> {
+        match datatype {
+            "text" => Some(Self::Text),
+            "enumeration" => Some(Self::Enumeration),
+            "bool" => Some(Self::Bool),
+            "rating" => Some(Self::Rating),
+            "datetime" => Some(Self::Date),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the values are stored in a separate table, which books are
+    /// linked to. Otherwise, the values are stored along with the IDs of the books.
+    pub(super) fn is_normalized(&self) -> bool {
+        matches!(self, Self::Text | Self::Enumeration | Self::Rating)
+    }
+
+    /// Returns `true` if the books can be grouped by the values of the column.
+    pub fn is_browseable(&self) -> bool {
+        !matches!(self, Self::Date)
+    }
+}
+
+/// A group of books, such as a series or a tag, along with the number of books in it.
+#[derive(Debug)]
+pub struct Category {
+    pub id: i64,
+    pub name: CompactString,
+    pub book_count: usize,
+}
+
+impl TryFrom<&Row<'_>> for Category {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: row.get::<_, CompactStringSql>("name")?.0,
+            book_count: row.get("book_count")?,
+            id: row.get("id")?,
+        })
+    }
+}
+
+/// A value of a [`Facet`](super::Facet), along with the number of books that have it.
+#[derive(Debug)]
+pub struct FacetValue {
+    pub value: CompactString,
+    pub book_count: usize,
+}
+
+impl TryFrom<&Row<'_>> for FacetValue {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            value: row.get::<_, CompactStringSql>("value")?.0,
+            book_count: row.get("book_count")?,
+        })
+    }
+}
+
+/// A node of Calibre's tag hierarchy. Tags are nested by separating their names with
+/// dots, e.g. `Fiction.Fantasy.Epic`.
+#[derive(Debug)]
+pub struct TagNode {
+    /// The name of the node, without the names of its ancestors.
+    pub name: CompactString,
+    /// The ID of the tag whose full name matches the node, if there is one.
+    pub tag_id: Option<i64>,
+    pub has_children: bool,
+    /// The number of books that are tagged with this node or any of its descendants.
+    pub book_count: usize,
+}
+
+impl TryFrom<&Row<'_>> for TagNode {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: row.get::<_, CompactStringSql>("name")?.0,
+            has_children: row.get("has_children")?,
+            book_count: row.get("book_count")?,
+            tag_id: row.get("tag_id")?,
+        })
+    }
 }
 
 pub struct Author {
+    pub id: i64,
     pub name: CompactString,
     pub book_id: i64,
 }
@@ -71,7 +327,75 @@ impl TryFrom<&Row<'_>> for Author {
     fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
         Ok(Self {
             name: row.get::<_, CompactStringSql>("author_name")?.0,
+            id: row.get("author_id")?,
+            book_id: row.get("book_id")?,
+        })
+    }
+}
+
+/// The initial letter of the sort names of a group of authors.
+#[derive(Debug)]
+pub struct AuthorInitial {
+    pub letter: CompactString,
+    pub author_count: usize,
+}
+
+impl TryFrom<&Row<'_>> for AuthorInitial {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            letter: row.get::<_, CompactStringSql>("letter")?.0,
+            author_count: row.get("author_count")?,
+        })
+    }
+}
+
+/// An author, along with the information Calibre stores about them.
+#[derive(Debug)]
+pub struct AuthorDetails {
+    pub id: i64,
+    pub name: CompactString,
+    /// A link to a web page about the author.
+    pub link: Option<CompactString>,
+    /// The plain text of the note attached to the author in Calibre, if there is one.
+    pub note: Option<CompactString>,
+    pub book_count: usize,
+}
+
+impl TryFrom<&Row<'_>> for AuthorDetails {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            link: row
+                .get::<_, CompactStringSql>("link")
+                .map(|str| Some(str.0).filter(|link| !link.is_empty()))?,
+            name: row.get::<_, CompactStringSql>("name")?.0,
+            book_count: row.get("book_count")?,
+            id: row.get("id")?,
+            note: None,
+        })
+    }
+}
+
+pub struct Identifier {
+    pub kind: CompactString,
+    pub value: CompactString,
+    pub book_id: i64,
+}
+
+impl TryFrom<&Row<'_>> for Identifier {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        let mut kind = row.get::<_, CompactStringSql>("kind")?.0;
+        kind.make_ascii_lowercase();
+
+        Ok(Self {
+            value: row.get::<_, CompactStringSql>("value")?.0,
             book_id: row.get("book_id")?,
+            kind,
         })
     }
 }
@@ -0,0 +1,478 @@
+use async_sqlite::rusqlite::{
+    self,
+    functions::FunctionFlags,
+    types::{Value, ValueRef},
+};
+use compact_str::CompactString;
+use regex::{Regex, RegexBuilder};
+
+use super::CustomColumnKind;
+
+/// A condition that narrows down the books returned by [`Library::fetch_books`].
+///
+/// [`Library::fetch_books`]: super::Library::fetch_books
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BookFilter {
+    /// Every book of the library.
+    #[default]
+    All,
+    /// The book with the given ID.
+    Id(i64),
+    /// The books with the given IDs.
+    Ids(Vec<i64>),
+    /// Books that were written by the author with the given ID.
+    Author(i64),
+    /// Books that belong to the series with the given ID.
+    Series(i64),
+    /// Books that were published by the publisher with the given ID.
+    Publisher(i64),
+    /// Books that are written in the language with the given ID.
+    Language(i64),
+    /// Books that are tagged with the tag with the given ID.
+    Tag(i64),
+    /// Books whose custom column has the value with the given ID. The values of columns
+    /// that aren't normalized don't have IDs, so they're matched by the value itself.
+    CustomColumn {
+        column_id: i64,
+        normalized: bool,
+        value_id: i64,
+    },
+    /// Books that are written in the language with the given ISO 639 code.
+    LanguageCode(CompactString),
+    /// Books that are available in the given format, e.g. `epub`.
+    Format(CompactString),
+    /// Books that have (or don't have) a cover.
+    HasCover(bool),
+    /// Books whose field matches the condition.
+    Field(SearchField, Condition),
+    /// Books that match every filter.
+    And(Vec<BookFilter>),
+    /// Books that match any filter.
+    Or(Vec<BookFilter>),
+    /// Books that don't match the filter.
+    Not(Box<BookFilter>),
+}
+
+/// A field of books that can be searched with [`BookFilter::Field`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchField {
+    Title,
+    Author,
+    Tag,
+    Series,
+    /// The position of books in their series.
+    SeriesIndex,
+    Publisher,
+    /// The ISO 639 codes of the languages books are written in.
+    Language,
+    Format,
+    /// The size of the files of books, in bytes.
+    Size,
+    Comments,
+    /// The rating of books, in stars.
+    Rating,
+    /// The identifiers of books, only the ones of the given type (e.g. `isbn`) if any.
+    Identifier(Option<CompactString>),
+    PublishedAt,
+    AddedAt,
+    LastModifiedAt,
+    /// The custom column with the given ID.
+    CustomColumn {
+        id: i64,
+        kind: CustomColumnKind,
+    },
+}
+
+impl SearchField {
+    /// Writes the opening of the SQL condition that matches the field of a book, which is
+    /// closed with a parenthesis, and returns the expression whose value is matched.
+    fn write_opening(&self, sql: &mut String, params: &mut Vec<Value>) -> &'static str {
+        let (opening, column) = match self {
+            Self::Title => ("(", "b.title"),
+            Self::Author => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_authors_link AS link
+                    INNER JOIN authors AS x ON link.author = x.id
+                    WHERE "#,
+                "x.name",
+            ),
+            Self::Tag => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_tags_link AS link
+                    INNER JOIN tags AS x ON link.tag = x.id
+                    WHERE "#,
+                "x.name",
+            ),
+            Self::Series => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_series_link AS link
+                    INNER JOIN series AS x ON link.series = x.id
+                    WHERE "#,
+                "x.name",
+            ),
+            // Books that aren't in a series still have an index, which is meaningless.
+            Self::SeriesIndex => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_series_link AS link
+                    INNER JOIN books AS x ON link.book = x.id
+                    WHERE "#,
+                "x.series_index",
+            ),
+            Self::Publisher => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_publishers_link AS link
+                    INNER JOIN publishers AS x ON link.publisher = x.id
+                    WHERE "#,
+                "x.name",
+            ),
+            Self::Language => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_languages_link AS link
+                    INNER JOIN languages AS x ON link.lang_code = x.id
+                    WHERE "#,
+                "x.lang_code",
+            ),
+            Self::Format => ("b.id IN (SELECT x.book FROM data AS x WHERE ", "x.format"),
+            Self::Size => (
+                "b.id IN (SELECT x.book FROM data AS x WHERE ",
+                "x.uncompressed_size",
+            ),
+            Self::Comments => (
+                "b.id IN (SELECT x.book FROM comments AS x WHERE ",
+                "NULLIF(x.text, '')",
+            ),
+            // Ratings are stored in half stars, and 0 means that the book isn't rated.
+            Self::Rating => (
+                r#"b.id IN (
+                    SELECT link.book FROM books_ratings_link AS link
+                    INNER JOIN ratings AS x ON link.rating = x.id
+                    WHERE "#,
+                "NULLIF(x.rating, 0) / 2.0",
+            ),
+            Self::Identifier(kind) => {
+                sql.push_str("b.id IN (SELECT x.book FROM identifiers AS x WHERE ");
+
+                if let Some(kind) = kind {
+                    sql.push_str("x.type = ? COLLATE NOCASE AND ");
+                    params.push(Value::Text(kind.to_string()));
+                }
+
+                return "x.val";
+            }
+            // Calibre stores unknown publication dates as the year 101.
+            Self::PublishedAt => ("(", "IIF(b.pubdate >= '0102', b.pubdate, NULL)"),
+            Self::AddedAt => ("(", "b.timestamp"),
+            Self::LastModifiedAt => ("(", "b.last_modified"),
+            Self::CustomColumn { id, kind } => {
+                if kind.is_normalized() {
+                    sql.push_str(&format!(
+                        r#"b.id IN (
+                            SELECT link.book FROM books_custom_column_{id}_link AS link
+                            INNER JOIN custom_column_{id} AS x ON link.value = x.id
+                            WHERE "#
+                    ));
+                } else {
+                    sql.push_str(&format!(
+                        "b.id IN (SELECT x.book FROM custom_column_{id} AS x WHERE "
+                    ));
+                }
+
+                return match kind {
+                    CustomColumnKind::Rating => "NULLIF(x.value, 0) / 2.0",
+                    _ => "x.value",
+                };
+            }
+        };
+
+        sql.push_str(opening);
+        column
+    }
+}
+
+/// How the value of a [`BookFilter::Field`] is matched. Text is matched
+/// case-insensitively.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The field has a value, or it doesn't.
+    Exists(bool),
+    /// The field contains the text.
+    Contains(CompactString),
+    /// The field is equal to the text.
+    Equals(CompactString),
+    /// The field matches the regular expression.
+    Matches(CompactString),
+    /// The field compares to the number.
+    Compare(Comparison, f64),
+    /// The field is a date within the range, which is formatted as `YYYY-MM-DD`. The
+    /// start is inclusive and the end is exclusive.
+    DateRange(Option<CompactString>, Option<CompactString>),
+}
+
+/// A relational operator of a [`Condition::Compare`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+impl Condition {
+    /// Writes the SQL condition that matches the value of the expression.
+    fn write_sql(&self, column: &str, sql: &mut String, params: &mut Vec<Value>) {
+        sql.push_str(column);
+
+        match self {
+            // A missing value is negated by the filter, as the row may not exist at all.
+            Self::Exists(_) => sql.push_str(" IS NOT NULL"),
+            Self::Contains(text) => {
+                sql.push_str(r" LIKE ? ESCAPE '\'");
+                params.push(Value::Text(like_pattern(text)));
+            }
+            Self::Equals(text) => {
+                sql.push_str(" = ? COLLATE NOCASE");
+                params.push(Value::Text(text.to_string()));
+            }
+            Self::Matches(pattern) => {
+                sql.push_str(" REGEXP ?");
+                params.push(Value::Text(pattern.to_string()));
+            }
+            Self::Compare(comparison, number) => {
+                sql.push(' ');
+                sql.push_str(comparison.as_sql());
+                sql.push_str(" ?");
+                params.push(Value::Real(*number));
+            }
+            Self::DateRange(start, end) => {
+                sql.push_str(" IS NOT NULL");
+
+                // Dates are stored as text, like `2024-01-31 12:00:00+00:00`, so they
+                // compare in chronological order.
+                for (date, operator) in [(start, " >= ?"), (end, " < ?")] {
+                    if let Some(date) = date {
+                        sql.push_str(" AND ");
+                        sql.push_str(column);
+                        sql.push_str(operator);
+                        params.push(Value::Text(date.to_string()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Registers the `regexp` function on the connection, which SQLite calls for the
+/// `REGEXP` operator. Like in Calibre, the patterns are case-insensitive.
+pub(super) fn register_regexp(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            // The pattern is compiled once per statement.
+            let regex = ctx.get_or_create_aux(0, |pattern| {
+                case_insensitive_regex(pattern.as_str()?)
+                    .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))
+            })?;
+
+            Ok(match ctx.get_raw(1) {
+                ValueRef::Text(text) => regex.is_match(&String::from_utf8_lossy(text)),
+                _ => false,
+            })
+        },
+    )
+}
+
+pub(super) fn case_insensitive_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+impl BookFilter {
+    /// Renders the filter into a SQL condition on the `b` (`books`) table alias, along
+    /// with its positional parameters.
+    pub(super) fn to_sql(&self) -> (String, Vec<Value>) {
+        let mut sql = String::new();
+        let mut params = vec![];
+
+        self.write_sql(&mut sql, &mut params);
+        (sql, params)
+    }
+
+    fn write_sql(&self, sql: &mut String, params: &mut Vec<Value>) {
+        match self {
+            Self::All => sql.push('1'),
+
+            Self::Id(id) => {
+                sql.push_str("b.id = ?");
+                params.push(Value::Integer(*id));
+            }
+
+            Self::Ids(ids) => {
+                sql.push_str("b.id IN (SELECT value FROM json_each(?))");
+                params.push(Value::Text(format!(
+                    "[{}]",
+                    ids.iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )));
+            }
+
+            Self::Author(id) => {
+                sql.push_str("b.id IN (SELECT book FROM books_authors_link WHERE author = ?)");
+                params.push(Value::Integer(*id));
+            }
+
+            Self::Series(id) => {
+                sql.push_str("b.id IN (SELECT book FROM books_series_link WHERE series = ?)");
+                params.push(Value::Integer(*id));
+            }
+
+            Self::Publisher(id) => {
+                sql.push_str(
+                    "b.id IN (SELECT book FROM books_publishers_link WHERE publisher = ?)",
+                );
+                params.push(Value::Integer(*id));
+            }
+
+            Self::Language(id) => {
+                sql.push_str("b.id IN (SELECT book FROM books_languages_link WHERE lang_code = ?)");
+                params.push(Value::Integer(*id));
+            }
+
+            Self::Tag(id) => {
+                sql.push_str("b.id IN (SELECT book FROM books_tags_link WHERE tag = ?)");
+                params.push(Value::Integer(*id));
+            }
+
+            Self::CustomColumn {
+                column_id,
+                normalized,
+                value_id,
+            } => {
+                if *normalized {
+                    sql.push_str(&format!(
+                        "b.id IN (SELECT book FROM books_custom_column_{column_id}_link WHERE value = ?)"
+                    ));
+                } else {
+                    sql.push_str(&format!(
+                        "b.id IN (SELECT book FROM custom_column_{column_id} WHERE value = ?)"
+                    ));
+                }
+
+                params.push(Value::Integer(*value_id));
+            }
+
+            Self::LanguageCode(lang_code) => {
+                sql.push_str(
+                    r#"b.id IN (
+                        SELECT link.book FROM books_languages_link AS link
+                        INNER JOIN languages AS l ON link.lang_code = l.id
+                        WHERE l.lang_code = ?)"#,
+                );
+                params.push(Value::Text(lang_code.to_string()));
+            }
+
+            Self::Format(format) => {
+                sql.push_str("b.id IN (SELECT book FROM data WHERE format = ?)");
+                params.push(Value::Text(format.to_string()));
+            }
+
+            Self::HasCover(has_cover) => {
+                sql.push_str("b.has_cover = ?");
+                params.push(Value::Integer(*has_cover as i64));
+            }
+
+            Self::Field(field, condition) => {
+                if *condition == Condition::Exists(false) {
+                    sql.push_str("NOT ");
+                }
+
+                let column = field.write_opening(sql, params);
+
+                condition.write_sql(column, sql, params);
+                sql.push(')');
+            }
+
+            Self::And(filters) => Self::write_joined_sql(filters, " AND ", '1', sql, params),
+
+            Self::Or(filters) => Self::write_joined_sql(filters, " OR ", '0', sql, params),
+
+            Self::Not(filter) => {
+                sql.push_str("NOT (");
+                filter.write_sql(sql, params);
+                sql.push(')');
+            }
+        }
+    }
+}
+
+impl BookFilter {
+    /// Joins the conditions of the filters with the operator. `empty` is the condition
+    /// that is used if there are no filters.
+    fn write_joined_sql(
+        filters: &[BookFilter],
+        operator: &str,
+        empty: char,
+        sql: &mut String,
+        params: &mut Vec<Value>,
+    ) {
+        if filters.is_empty() {
+            sql.push(empty);
+            return;
+        }
+
+        for (i, filter) in filters.iter().enumerate() {
+            if i > 0 {
+                sql.push_str(operator);
+            }
+
+            sql.push('(');
+            filter.write_sql(sql, params);
+            sql.push(')');
+        }
+    }
+}
+
+/// Builds a `LIKE` pattern matching any text that contains `term`.
+fn like_pattern(term: &str) -> String {
+    let mut pattern = String::with_capacity(term.len() + 2);
+    pattern.push('%');
+    escape_like(term, &mut pattern);
+    pattern.push('%');
+    pattern
+}
+
+/// Builds a `LIKE` pattern matching any text that starts with `prefix`.
+pub(super) fn like_prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    escape_like(prefix, &mut pattern);
+    pattern.push('%');
+    pattern
+}
+
+/// Escapes the wildcards of a `LIKE` pattern with backslashes.
+fn escape_like(text: &str, pattern: &mut String) {
+    for ch in text.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+
+        pattern.push(ch);
+    }
+}
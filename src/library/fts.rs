@@ -0,0 +1,115 @@
+//! Support for Calibre's full-text search database, `full-text-search.db`.
+//!
+//! Calibre indexes the text of books with FTS5 tables that use its own tokenizer, named
+//! `calibre`, which doesn't exist outside of Calibre. SQLite refuses to query the tables
+//! unless a tokenizer with that name is registered, so it's registered as an alias of the
+//! built-in `unicode61` tokenizer. Both split text at Unicode word boundaries and fold
+//! case and diacritics, so the terms of the query match the indexed ones.
+
+use std::ptr;
+
+use async_sqlite::rusqlite::{self, ffi};
+
+/// Registers the `calibre` tokenizer on the connection.
+pub(super) fn register_calibre_tokenizer(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // SAFETY: The handle outlives the statement, which is always finalized, and the
+    // `fts5_api` pointer is owned by the connection. FTS5 copies the tokenizer struct on
+    // registration, and the user data of `unicode61` lives as long as the connection.
+    unsafe {
+        let db = conn.handle();
+        let api = fts5_api(db)?;
+        let mut user_data = ptr::null_mut();
+        let mut tokenizer: ffi::fts5_tokenizer = std::mem::zeroed();
+
+        let (Some(find_tokenizer), Some(create_tokenizer)) =
+            ((*api).xFindTokenizer, (*api).xCreateTokenizer)
+        else {
+            return Err(error(ffi::SQLITE_MISUSE));
+        };
+
+        check(find_tokenizer(
+            api,
+            c"unicode61".as_ptr(),
+            &mut user_data,
+            &mut tokenizer,
+        ))?;
+        check(create_tokenizer(
+            api,
+            c"calibre".as_ptr(),
+            user_data,
+            &mut tokenizer,
+            None,
+        ))
+    }
+}
+
+/// Retrieves the FTS5 API of the connection, as described in
+/// <https://www.sqlite.org/fts5.html#extending_fts5>.
+unsafe fn fts5_api(db: *mut ffi::sqlite3) -> rusqlite::Result<*mut ffi::fts5_api> {
+    let mut api: *mut ffi::fts5_api = ptr::null_mut();
+    let mut stmt = ptr::null_mut();
+
+    unsafe {
+        check(ffi::sqlite3_prepare_v2(
+            db,
+            c"SELECT fts5(?1)".as_ptr(),
+            -1,
+            &mut stmt,
+            ptr::null_mut(),
+        ))?;
+        ffi::sqlite3_bind_pointer(
+            stmt,
+            1,
+            (&raw mut api).cast(),
+            c"fts5_api_ptr".as_ptr(),
+            None,
+        );
+        ffi::sqlite3_step(stmt);
+        check(ffi::sqlite3_finalize(stmt))?;
+    }
+
+    if api.is_null() {
+        return Err(error(ffi::SQLITE_ERROR));
+    }
+
+    Ok(api)
+}
+
+fn check(code: std::ffi::c_int) -> rusqlite::Result<()> {
+    match code {
+        ffi::SQLITE_OK => Ok(()),
+        code => Err(error(code)),
+    }
+}
+
+fn error(code: std::ffi::c_int) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(ffi::Error::new(code), None)
+}
+
+/// Converts a search query into an FTS5 query that matches text containing every term.
+/// Terms wrapped in double quotes are matched as a phrase. The terms are quoted, so the
+/// query can't contain FTS5 syntax that would otherwise be rejected.
+pub(super) fn match_expression(query: &str) -> String {
+    let mut expression = String::with_capacity(query.len() + 8);
+
+    for (i, part) in query.split('"').enumerate() {
+        // Every other part of the query is inside double quotes.
+        let terms = if i % 2 == 1 {
+            vec![part.trim()]
+        } else {
+            part.split_whitespace().collect()
+        };
+
+        for term in terms.into_iter().filter(|term| !term.is_empty()) {
+            if !expression.is_empty() {
+                expression.push(' ');
+            }
+
+            expression.push('"');
+            expression.push_str(term);
+            expression.push('"');
+        }
+    }
+
+    expression
+}
@@ -1,26 +1,47 @@
 mod entities;
+mod filter;
+mod fts;
+pub mod query;
 mod sql;
 
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
     num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use async_sqlite::{Pool, PoolBuilder, rusqlite};
-use entities::{Author, Language, Tag};
-pub use entities::{Data, FullBook};
-use eyre::bail;
+use async_sqlite::{
+    Pool, PoolBuilder,
+    rusqlite::{self, OptionalExtension as _, types::Value},
+};
+use compact_str::CompactString;
+use entities::{Author, Identifier, Language, Tag};
+pub use entities::{
+    AuthorDetails, AuthorInitial, BookAuthor, BookCover, BookCustomValue, BookIdentifier,
+    BookSeries, BookVersion, Category, CustomColumn, CustomColumnKind, CustomValue, Data,
+    FacetValue, FullBook, TagNode,
+};
+use eyre::{bail, eyre};
+pub use filter::{BookFilter, Comparison, Condition, SearchField};
+use query::{QueryContext, QueryError};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tokio::fs;
 
-use crate::utils::hash_str;
+use crate::{
+    users::Users,
+    utils::{CompactStringSql, hash_str},
+};
 
 /// Handles all Calibre libraries. It's responsible for reading the metadata.db file and
 /// performing search operation of books.
 pub struct Libraries {
     entries: HashMap<String, Library>,
+    /// The libraries each user may access, keyed by username, which only show the books
+    /// the user may see.
+    user_entries: HashMap<CompactString, HashMap<String, Library>>,
 }
 
 impl Libraries {
@@ -37,7 +58,10 @@ impl Libraries {
             match (names.next(), paths.next()) {
                 (Some(name), Some(path)) => {
                     if entries
-                        .insert(name.clone(), Library::new(name, path).await?)
+                        .insert(
+                            name.clone(),
+                            Library::new(name, path, &cli.browse_column).await?,
+                        )
                         .is_some()
                     {
                         bail!("library names must be unique");
@@ -50,48 +74,232 @@ impl Libraries {
             }
         }
 
-        Ok(Self { entries })
+        Ok(Self {
+            user_entries: HashMap::new(),
+            entries,
+        })
     }
 
-    pub fn get(&self, name: &str) -> Option<&Library> {
-        self.entries.get(name)
+    /// Sets up the libraries that each user may access, whose books are restricted to the
+    /// ones matching the user's restriction.
+    pub fn restrict_to_users(&mut self, users: &Users) -> eyre::Result<()> {
+        for user in users.iter() {
+            let mut entries = HashMap::new();
+
+            for (name, lib) in &self.entries {
+                if let Some(allowed) = &user.libraries
+                    && !allowed.iter().any(|allowed| allowed == name)
+                {
+                    continue;
+                }
+
+                let mut lib = lib.clone();
+
+                if let Some(restriction) = &user.restriction {
+                    lib.restriction = Some(lib.parse_query(restriction).map_err(|err| {
+                        eyre!(
+                            "Invalid restriction of the user \"{}\" in \"{name}\": {err}",
+                            user.name
+                        )
+                    })?);
+                }
+
+                entries.insert(name.clone(), lib);
+            }
+
+            if let Some(allowed) = &user.libraries
+                && let Some(unknown) = allowed
+                    .iter()
+                    .find(|name| !self.entries.contains_key(name.as_str()))
+            {
+                bail!(
+                    "The library \"{unknown}\" of the user \"{}\" doesn't exist",
+                    user.name
+                );
+            }
+
+            self.user_entries.insert(user.name.clone(), entries);
+        }
+
+        Ok(())
     }
 
-    pub fn get_all(&self) -> impl Iterator<Item = &Library> {
-        self.entries.values()
+    /// Returns the library with the given name, as the user sees it. Every library is
+    /// returned as is if there's no user.
+    pub fn get(&self, name: &str, user: Option<&str>) -> Option<&Library> {
+        match user {
+            Some(user) => self.user_entries.get(user)?.get(name),
+            None => self.entries.get(name),
+        }
+    }
+
+    /// Returns the libraries the user may access, or every library if there's no user.
+    pub fn get_all(&self, user: Option<&str>) -> impl Iterator<Item = &Library> {
+        match user {
+            Some(user) => self.user_entries.get(user),
+            None => Some(&self.entries),
+        }
+        .into_iter()
+        .flat_map(HashMap::values)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum OrderBooksBy {
     DateAdded,
     Title,
     Author,
-    // TODO: Group by the following options instead.
-    // Language,
-    // Tags,
-    // Publisher,
-    // Series,
+    SeriesIndex,
+    /// The most relevant books to a full-text search first.
+    Relevance,
 }
 
 impl OrderBooksBy {
-    fn as_sql_query(&self) -> &'static dyn sql::SqlQueries {
+    /// Returns the queries of the books in the order. Full-text searches select their
+    /// books by ID, the most relevant first, so the books that aren't selected by ID have
+    /// no relevance, and are ordered by title instead.
+    fn as_sql_queries(&self, filter: &BookFilter) -> &'static dyn sql::SqlQueries {
         match self {
             Self::DateAdded => &sql::OrderedByDateAdded,
             Self::Author => &sql::OrderedByAuthor,
             Self::Title => &sql::OrderedByTitle,
+            Self::SeriesIndex => &sql::OrderedBySeriesIndex,
+            Self::Relevance if ranked_ids(filter).is_some() => &sql::OrderedByRelevance,
+            Self::Relevance => &sql::OrderedByTitle,
+        }
+    }
+}
+
+/// Returns the IDs that the filter selects books by, if any.
+fn ranked_ids(filter: &BookFilter) -> Option<&[i64]> {
+    match filter {
+        BookFilter::Ids(ids) => Some(ids),
+        BookFilter::And(filters) => filters.iter().find_map(ranked_ids),
+        _ => None,
+    }
+}
+
+fn json_ids(ids: &[i64]) -> String {
+    format!(
+        "[{}]",
+        ids.iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// A kind of [`Category`] that books can be grouped by.
+#[derive(Debug, Clone, Copy)]
+pub enum CategoryKind {
+    Series,
+    Publisher,
+    Language,
+}
+
+impl CategoryKind {
+    fn table(&self) -> &'static sql::CategoryTable {
+        match self {
+            Self::Series => &sql::SERIES,
+            Self::Publisher => &sql::PUBLISHERS,
+            Self::Language => &sql::LANGUAGES,
+        }
+    }
+
+    /// Returns the filter that selects the books of the category with the given ID.
+    pub fn filter(&self, id: i64) -> BookFilter {
+        match self {
+            Self::Series => BookFilter::Series(id),
+            Self::Publisher => BookFilter::Publisher(id),
+            Self::Language => BookFilter::Language(id),
+        }
+    }
+
+    /// Returns the order in which the books of a category are listed.
+    pub fn order_books_by(&self) -> OrderBooksBy {
+        match self {
+            Self::Series => OrderBooksBy::SeriesIndex,
+            Self::Publisher | Self::Language => OrderBooksBy::Title,
+        }
+    }
+}
+
+impl CustomColumn {
+    /// Returns the filter that selects the books whose column has the value with the
+    /// given ID.
+    pub fn filter(&self, value_id: i64) -> BookFilter {
+        BookFilter::CustomColumn {
+            normalized: self.kind.is_normalized(),
+            column_id: self.id,
+            value_id,
+        }
+    }
+}
+
+/// A kind of query that is saved in Calibre.
+#[derive(Debug, Clone, Copy)]
+pub enum SavedQueryKind {
+    /// A query that restricts the books Calibre shows.
+    VirtualLibrary,
+    /// A query that can be run again, or referred to by other queries.
+    SavedSearch,
+}
+
+impl SavedQueryKind {
+    /// Returns the key of the preference in which Calibre stores the queries.
+    fn preference_key(&self) -> &'static str {
+        match self {
+            Self::VirtualLibrary => "virtual_libraries",
+            Self::SavedSearch => "saved_searches",
         }
     }
 }
 
-// TODO: Use full text search database too if it's available?
+/// A named query that is saved in Calibre, such as a virtual library.
+#[derive(Debug, Clone)]
+pub struct SavedQuery {
+    pub name: CompactString,
+    /// The query, written in Calibre's search language.
+    pub query: CompactString,
+    pub filter: BookFilter,
+}
+
+/// A property of books that can be used to narrow down a list of books.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facet {
+    /// The ISO 639 codes of the languages books are written in.
+    Language,
+    /// The formats books are available in, in lowercase.
+    Format,
+    /// Whether books have a cover, either `true` or `false`.
+    Cover,
+}
+
+/// The number of the most relevant books that a full-text search returns, since common
+/// terms are found in most books of large libraries.
+pub const MAX_FULL_TEXT_SEARCH_RESULTS: usize = 1000;
+
+#[derive(Clone)]
 pub struct Library {
     modified_at: OffsetDateTime,
     root_path: PathBuf,
     metadata_db: Pool,
+    /// Calibre stores the notes of authors, series, etc. in a separate database, which
+    /// doesn't exist in libraries created by older versions.
+    notes_db: Option<Pool>,
+    /// Calibre indexes the text of books in a separate database, but only if full-text
+    /// searching is enabled for the library.
+    fts_db: Option<Pool>,
+    custom_columns: Arc<[CustomColumn]>,
+    virtual_libraries: Vec<SavedQuery>,
+    saved_searches: Vec<SavedQuery>,
+    query_context: QueryContext,
     name: String,
     acquisition_feed_id: String,
+    /// The filter that the books of the library match, if they're restricted to the ones
+    /// a user may see. Books that don't match it aren't counted, listed or found.
+    restriction: Option<BookFilter>,
 }
 
 impl Library {
@@ -99,7 +307,7 @@ impl Library {
     pub const MAX_PAGE_SIZE: NonZeroUsize = NonZeroUsize::new(50).unwrap();
     pub const MIN_PAGE_SIZE: NonZeroUsize = NonZeroUsize::new(1).unwrap();
 
-    async fn new(name: String, lib_path: PathBuf) -> eyre::Result<Self> {
+    async fn new(name: String, lib_path: PathBuf, browse_columns: &[String]) -> eyre::Result<Self> {
         let root_path = fs::canonicalize(&lib_path).await?;
         debug!(lib_name = %name, "Canonicalized path {lib_path:?} => {root_path:?}");
 
@@ -117,9 +325,113 @@ impl Library {
             .await?;
         debug!(mtime = ?modified_at, lib_name = %name, "Opened \"metadata.db\"");
 
+        let mut custom_columns = metadata_db
+            .conn(|conn| {
+                conn.prepare(sql::RETRIEVE_CUSTOM_COLUMNS)?
+                    .query_map((), |row| CustomColumn::try_from(row))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await?;
+
+        for column in &mut custom_columns {
+            column.browseable = browse_columns.iter().any(|label| {
+                label
+                    .trim_start_matches('#')
+                    .eq_ignore_ascii_case(&column.label)
+            });
+
+            if column.browseable && !column.kind.is_browseable() {
+                warn!(lib_name = %name, "The \"#{}\" column can't be browsed", column.label);
+                column.browseable = false;
+            }
+        }
+
+        let saved_searches = read_saved_queries(&metadata_db, SavedQueryKind::SavedSearch).await?;
+        let virtual_libraries =
+            read_saved_queries(&metadata_db, SavedQueryKind::VirtualLibrary).await?;
+        let custom_columns: Arc<[CustomColumn]> = custom_columns.into();
+        // Queries refer to saved searches and virtual libraries by their names,
+        // case-insensitively.
+        let by_lowercase_name = |queries: &BTreeMap<CompactString, CompactString>| {
+            queries
+                .iter()
+                .map(|(name, query)| (name.to_lowercase(), query.clone()))
+                .collect::<HashMap<_, _>>()
+        };
+        let query_context = QueryContext {
+            saved_searches: by_lowercase_name(&saved_searches),
+            virtual_libraries: by_lowercase_name(&virtual_libraries),
+            custom_columns: custom_columns.clone(),
+        };
+        let parse_saved_queries =
+            |kind: SavedQueryKind, queries: BTreeMap<_, _>| {
+                queries
+                    .into_iter()
+                    .filter_map(|(query_name, query): (CompactString, CompactString)| {
+                        match query::parse(&query, &query_context) {
+                            Ok(filter) => Some(SavedQuery {
+                                name: query_name,
+                                filter,
+                                query,
+                            }),
+                            Err(err) => {
+                                warn!(lib_name = %name, ?kind, "Ignoring \"{query_name}\": {err}");
+                                None
+                            }
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            };
+        let virtual_libraries =
+            parse_saved_queries(SavedQueryKind::VirtualLibrary, virtual_libraries);
+        let saved_searches = parse_saved_queries(SavedQueryKind::SavedSearch, saved_searches);
+
+        for result in metadata_db.conn_for_each(filter::register_regexp).await {
+            result?;
+        }
+
+        let notes_db_path = root_path.join(".calnotes").join("notes.db");
+        let notes_db = if fs::try_exists(&notes_db_path).await? {
+            let notes_db = PoolBuilder::new()
+                .flags(rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .path(notes_db_path)
+                .open()
+                .await?;
+            debug!(lib_name = %name, "Opened \".calnotes/notes.db\"");
+
+            Some(notes_db)
+        } else {
+            None
+        };
+
+        let fts_db_path = root_path.join("full-text-search.db");
+        let fts_db = if fs::try_exists(&fts_db_path).await? {
+            let fts_db = PoolBuilder::new()
+                .flags(rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .path(fts_db_path)
+                .open()
+                .await?;
+
+            for result in fts_db.conn_for_each(fts::register_calibre_tokenizer).await {
+                result?;
+            }
+            debug!(lib_name = %name, "Opened \"full-text-search.db\"");
+
+            Some(fts_db)
+        } else {
+            None
+        };
+
         Ok(Self {
             acquisition_feed_id: format!("urn:seshat:lib-{}", hash_str(&name)),
             metadata_db,
+            custom_columns,
+            virtual_libraries,
+            query_context,
+            saved_searches,
+            notes_db,
+            fts_db,
+            restriction: None,
             modified_at,
             root_path,
             name,
@@ -142,15 +454,112 @@ impl Library {
         self.modified_at
     }
 
+    /// Narrows down the filter to the books that match the library's restriction.
+    fn restricted<'a>(&self, filter: &'a BookFilter) -> Cow<'a, BookFilter> {
+        match &self.restriction {
+            Some(restriction) => {
+                Cow::Owned(BookFilter::And(vec![restriction.clone(), filter.clone()]))
+            }
+            None => Cow::Borrowed(filter),
+        }
+    }
+
+    /// Renders the condition that the books that categories are counted among match,
+    /// which are the ones that the library's restriction allows, along with its
+    /// parameters.
+    fn visible_books(&self) -> (String, Vec<Value>) {
+        self.restricted(&BookFilter::All).to_sql()
+    }
+
+    /// Returns the custom columns of the library, sorted by name.
+    pub fn custom_columns(&self) -> &[CustomColumn] {
+        &self.custom_columns
+    }
+
+    /// Finds a custom column by its lookup name, without the `#` prefix.
+    pub fn find_custom_column(&self, label: &str) -> Option<&CustomColumn> {
+        self.custom_columns
+            .iter()
+            .find(|column| column.label.eq_ignore_ascii_case(label))
+    }
+
+    /// Returns the queries of the given kind that are saved in the library, sorted by
+    /// name.
+    pub fn saved_queries(&self, kind: SavedQueryKind) -> &[SavedQuery] {
+        match kind {
+            SavedQueryKind::VirtualLibrary => &self.virtual_libraries,
+            SavedQueryKind::SavedSearch => &self.saved_searches,
+        }
+    }
+
+    /// Finds a saved query of the given kind by its name.
+    pub fn find_saved_query(&self, kind: SavedQueryKind, name: &str) -> Option<&SavedQuery> {
+        self.saved_queries(kind)
+            .iter()
+            .find(|saved_query| saved_query.name == name)
+    }
+
+    /// Parses a query written in Calibre's search language, which can refer to the saved
+    /// searches and the custom columns of the library.
+    pub fn parse_query(&self, query: &str) -> Result<BookFilter, QueryError> {
+        query::parse(query, &self.query_context)
+    }
+
+    /// Returns `true` if the text of the library's books can be searched.
+    pub fn has_full_text_search(&self) -> bool {
+        self.fts_db.is_some()
+    }
+
     /// Returns the number of books in the library.
     pub async fn len(&self) -> crate::Result<usize> {
+        if self.restriction.is_some() {
+            return self.count_books(&BookFilter::All).await;
+        }
+
         Ok(self
             .metadata_db
             .conn(|conn| conn.query_row("SELECT COUNT(*) FROM books", (), |row| row.get(0)))
             .await?)
     }
 
-    /// Fetches a page of books from the library. Returns `true` if there is a next page.
+    /// Returns the number of books in the library that match the filter.
+    pub async fn count_books(&self, filter: &BookFilter) -> crate::Result<usize> {
+        let filter = self.restricted(filter);
+        let (filter, params) = filter.to_sql();
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&sql::count_books(&filter))?
+                    .query_row(rusqlite::params_from_iter(params), |row| row.get(0))
+            })
+            .await?)
+    }
+
+    /// Returns the values of the facet among the books that match the filter, along with
+    /// the number of books that have each one. The most common values come first.
+    pub async fn count_facet_values(
+        &self,
+        facet: Facet,
+        filter: &BookFilter,
+    ) -> crate::Result<Vec<FacetValue>> {
+        let filter = self.restricted(filter);
+        let (filter, params) = filter.to_sql();
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&sql::count_facet_values(facet, &filter))?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        FacetValue::try_from(row)
+                    })?
+                    .collect()
+            })
+            .await?)
+    }
+
+    /// Fetches a page of books from the library that match the filter. Returns `true` if
+    /// there is a next page.
     #[allow(
         clippy::unwrap_or_default,
         reason = "Default impl takes away type info from the compiler"
@@ -160,6 +569,7 @@ impl Library {
         limit: NonZeroUsize,
         offset: usize,
         order_by: OrderBooksBy,
+        filter: &BookFilter,
         mut acc: A,
         mut f: F,
     ) -> crate::Result<(A, bool)>
@@ -167,24 +577,55 @@ impl Library {
         F: FnMut(A, FullBook) -> A + Send + 'static,
         A: Send + 'static,
     {
+        let queries = order_by.as_sql_queries(filter);
+        let order_params = queries.params(filter);
+        let filter = self.restricted(filter);
+        let (filter, filter_params) = filter.to_sql();
+        let params = order_params
+            .into_iter()
+            .chain(filter_params)
+            .chain(page_params(limit, offset))
+            .collect::<Vec<_>>();
+        let custom_columns = self.custom_columns.clone();
+
         Ok(self
             .metadata_db
             .conn(move |conn| {
-                let sql_query = order_by.as_sql_query();
+                let mut books = conn
+                    .prepare_cached(&queries.retrieve_books(&filter))?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        FullBook::try_from(row)
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let has_next_page = books.len() > limit.get();
+                books.truncate(limit.get());
+
+                // The related rows are fetched in bulk for the whole page.
+                let book_ids = format!(
+                    "[{}]",
+                    books
+                        .iter()
+                        .map(|book| book.id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                );
 
                 let mut authors = conn
-                    .prepare_cached(sql_query.retrieve_book_authors())?
-                    .query_map([limit.get(), offset], |row| Author::try_from(row))?
+                    .prepare_cached(sql::RETRIEVE_BOOK_AUTHORS)?
+                    .query_map([&book_ids], |row| Author::try_from(row))?
                     .try_fold(HashMap::new(), |mut acc, row| -> rusqlite::Result<_> {
                         let row = row?;
 
-                        acc.entry(row.book_id).or_insert(vec![]).push(row.name);
+                        acc.entry(row.book_id).or_insert(vec![]).push(BookAuthor {
+                            name: row.name,
+                            id: row.id,
+                        });
                         Ok(acc)
                     })?;
 
                 let mut languages = conn
-                    .prepare_cached(sql_query.retrieve_book_languages())?
-                    .query_map([limit.get(), offset], |row| Language::try_from(row))?
+                    .prepare_cached(sql::RETRIEVE_BOOK_LANGUAGES)?
+                    .query_map([&book_ids], |row| Language::try_from(row))?
                     .try_fold(HashMap::new(), |mut acc, row| -> rusqlite::Result<_> {
                         let row = row?;
 
@@ -193,8 +634,8 @@ impl Library {
                     })?;
 
                 let mut tags = conn
-                    .prepare_cached(sql_query.retrieve_book_tags())?
-                    .query_map([limit.get(), offset], |row| Tag::try_from(row))?
+                    .prepare_cached(sql::RETRIEVE_BOOK_TAGS)?
+                    .query_map([&book_ids], |row| Tag::try_from(row))?
                     .try_fold(HashMap::new(), |mut acc, row| -> rusqlite::Result<_> {
                         let row = row?;
 
@@ -202,9 +643,24 @@ impl Library {
                         Ok(acc)
                     })?;
 
+                let mut identifiers = conn
+                    .prepare_cached(sql::RETRIEVE_BOOK_IDENTIFIERS)?
+                    .query_map([&book_ids], |row| Identifier::try_from(row))?
+                    .try_fold(HashMap::new(), |mut acc, row| -> rusqlite::Result<_> {
+                        let row = row?;
+
+                        acc.entry(row.book_id)
+                            .or_insert(vec![])
+                            .push(BookIdentifier {
+                                value: row.value,
+                                kind: row.kind,
+                            });
+                        Ok(acc)
+                    })?;
+
                 let mut data = conn
-                    .prepare_cached(sql_query.retrieve_book_data())?
-                    .query_map([limit.get(), offset], |row| Data::try_from(row))?
+                    .prepare_cached(sql::RETRIEVE_BOOK_DATA)?
+                    .query_map([&book_ids], |row| Data::try_from(row))?
                     .try_fold(HashMap::new(), |mut acc, row| -> rusqlite::Result<_> {
                         let row = row?;
 
@@ -212,18 +668,14 @@ impl Library {
                         Ok(acc)
                     })?;
 
-                let mut stmt = conn.prepare_cached(sql_query.retrieve_books())?;
-                let mut books =
-                    stmt.query_map([limit.get() + 1, offset], |row| FullBook::try_from(row))?;
-
-                for _ in 0..limit.get() {
-                    let Some(book) = books.next().transpose()? else {
-                        return Ok((acc, false));
-                    };
+                let mut custom_values = fetch_custom_values(conn, &custom_columns, &book_ids)?;
 
+                for book in books {
                     acc = f(
                         acc,
                         FullBook {
+                            custom_values: custom_values.remove(&book.id).unwrap_or_default(),
+                            identifiers: identifiers.remove(&book.id).unwrap_or_default(),
                             languages: languages.remove(&book.id).unwrap_or_default(),
                             authors: authors.remove(&book.id).unwrap_or_default(),
                             data: data.remove(&book.id).unwrap_or_default(),
@@ -233,8 +685,691 @@ impl Library {
                     );
                 }
 
-                Ok((acc, books.next().is_some()))
+                Ok((acc, has_next_page))
+            })
+            .await?)
+    }
+
+    /// Fetches a book by its ID.
+    pub async fn find_book(&self, id: i64) -> crate::Result<Option<FullBook>> {
+        let (mut books, _) = self
+            .fetch_books(
+                Self::MIN_PAGE_SIZE,
+                0,
+                OrderBooksBy::Title,
+                &BookFilter::Id(id),
+                vec![],
+                |mut acc, book| {
+                    acc.push(book);
+                    acc
+                },
+            )
+            .await?;
+
+        Ok(books.pop())
+    }
+
+    /// Returns the versions of every book in the library that matches the filter.
+    pub async fn fetch_book_versions(
+        &self,
+        filter: &BookFilter,
+    ) -> crate::Result<Vec<BookVersion>> {
+        let filter = self.restricted(filter);
+        let (filter, params) = filter.to_sql();
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&sql::retrieve_book_versions(&filter))?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        BookVersion::try_from(row)
+                    })?
+                    .collect()
+            })
+            .await?)
+    }
+
+    pub async fn find_book_id_by_uuid(&self, uuid: &str) -> crate::Result<Option<i64>> {
+        let uuid = uuid.to_owned();
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(sql::FIND_BOOK_ID_BY_UUID)?
+                    .query_row([uuid], |row| row.get(0))
+                    .optional()
+            })
+            .await?)
+    }
+
+    /// Finds the ID of the book whose directory is at the path, relative to the library's
+    /// root.
+    pub async fn find_book_id_by_path(&self, path: &str) -> crate::Result<Option<i64>> {
+        let path = path.to_owned();
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(sql::FIND_BOOK_ID_BY_PATH)?
+                    .query_row([path], |row| row.get(0))
+                    .optional()
+            })
+            .await?)
+    }
+
+    /// Finds the cover of a book by the book's ID, unless the book doesn't have one.
+    pub async fn find_cover(&self, book_id: i64) -> crate::Result<Option<BookCover>> {
+        if self.restriction.is_some() && self.count_books(&BookFilter::Id(book_id)).await? == 0 {
+            return Ok(None);
+        }
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(sql::RETRIEVE_BOOK_COVER)?
+                    .query_row([book_id], |row| BookCover::try_from(row))
+                    .optional()
+            })
+            .await?)
+    }
+
+    /// Returns the IDs of up to [`MAX_FULL_TEXT_SEARCH_RESULTS`] books whose text
+    /// contains every term of the query, the most relevant first, or `None` if the
+    /// library doesn't have a full-text search database. Terms wrapped in double
+    /// quotes are matched as a phrase.
+    pub async fn full_text_search(&self, query: &str) -> crate::Result<Option<Vec<i64>>> {
+        let Some(fts_db) = &self.fts_db else {
+            return Ok(None);
+        };
+
+        let expression = fts::match_expression(query);
+
+        if expression.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        Ok(Some(
+            fts_db
+                .conn(move |conn| {
+                    conn.prepare_cached(sql::FULL_TEXT_SEARCH)?
+                        .query_map([expression], |row| row.get(0))?
+                        .collect()
+                })
+                .await?,
+        ))
+    }
+
+    /// Fetches the most relevant excerpt of each book's text that matches the query. The
+    /// matched terms are enclosed in the `\x01` and `\x02` control characters.
+    pub async fn fetch_full_text_snippets(
+        &self,
+        query: &str,
+        book_ids: &[i64],
+    ) -> crate::Result<HashMap<i64, CompactString>> {
+        let Some(fts_db) = &self.fts_db else {
+            return Ok(HashMap::new());
+        };
+
+        let expression = fts::match_expression(query);
+        let book_ids = format!(
+            "[{}]",
+            book_ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        if expression.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        Ok(fts_db
+            .conn(move |conn| {
+                conn.prepare_cached(sql::RETRIEVE_FULL_TEXT_SNIPPETS)?
+                    .query_map([expression, book_ids], |row| {
+                        Ok((row.get(0)?, row.get::<_, CompactStringSql>(1)?.0))
+                    })?
+                    .try_fold(HashMap::new(), |mut acc, row| -> rusqlite::Result<_> {
+                        let (book_id, snippet) = row?;
+
+                        // The most relevant excerpt comes first.
+                        acc.entry(book_id).or_insert(snippet);
+                        Ok(acc)
+                    })
             })
             .await?)
     }
+
+    /// Returns the number of distinct values of the custom column.
+    pub async fn count_custom_column_values(&self, column: &CustomColumn) -> crate::Result<usize> {
+        let (filter, params) = self.visible_books();
+        let query = sql::count_custom_column_values(column, &filter);
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| row.get(0))
+            })
+            .await?)
+    }
+
+    /// Fetches a page of the values of the custom column, along with the number of books
+    /// that have each one. Returns `true` if there is a next page.
+    pub async fn fetch_custom_column_values(
+        &self,
+        column: &CustomColumn,
+        limit: NonZeroUsize,
+        offset: usize,
+    ) -> crate::Result<(Vec<Category>, bool)> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::retrieve_custom_column_values(column, &filter, false);
+        params.extend(page_params(limit, offset));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                let mut values = conn
+                    .prepare_cached(&query)?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        Category::try_from(row)
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let has_next_page = values.len() > limit.get();
+                values.truncate(limit.get());
+
+                Ok((values, has_next_page))
+            })
+            .await?)
+    }
+
+    /// Finds a value of the custom column by its ID.
+    pub async fn find_custom_column_value(
+        &self,
+        column: &CustomColumn,
+        id: i64,
+    ) -> crate::Result<Option<Category>> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::retrieve_custom_column_values(column, &filter, true);
+        params.push(Value::Integer(id));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| {
+                        Category::try_from(row)
+                    })
+                    .optional()
+            })
+            .await?)
+    }
+
+    /// Returns the number of categories of the given kind that contain at least one book.
+    pub async fn count_categories(&self, kind: CategoryKind) -> crate::Result<usize> {
+        let (filter, params) = self.visible_books();
+        let query = kind.table().count(&filter);
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| row.get(0))
+            })
+            .await?)
+    }
+
+    /// Fetches a page of categories of the given kind, sorted by name. Returns `true` if
+    /// there is a next page.
+    pub async fn fetch_categories(
+        &self,
+        kind: CategoryKind,
+        limit: NonZeroUsize,
+        offset: usize,
+    ) -> crate::Result<(Vec<Category>, bool)> {
+        let (filter, mut params) = self.visible_books();
+        let query = kind.table().retrieve(&filter);
+        params.extend(page_params(limit, offset));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                let mut categories = conn
+                    .prepare_cached(&query)?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        Category::try_from(row)
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let has_next_page = categories.len() > limit.get();
+                categories.truncate(limit.get());
+
+                Ok((categories, has_next_page))
+            })
+            .await?)
+    }
+
+    /// Finds a category of the given kind by its ID.
+    pub async fn find_category(
+        &self,
+        kind: CategoryKind,
+        id: i64,
+    ) -> crate::Result<Option<Category>> {
+        let (filter, mut params) = self.visible_books();
+        let query = kind.table().retrieve_by_id(&filter);
+        params.push(Value::Integer(id));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| {
+                        Category::try_from(row)
+                    })
+                    .optional()
+            })
+            .await?)
+    }
+
+    /// Returns the number of nodes in a level of the tag hierarchy. The top level is
+    /// selected if `parent` is `None`.
+    pub async fn count_tag_nodes(&self, parent: Option<&str>) -> crate::Result<usize> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::count_tag_nodes(&filter);
+        params.extend(tag_level_params(parent));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| row.get(0))
+            })
+            .await?)
+    }
+
+    /// Fetches a page of nodes in a level of the tag hierarchy, sorted by name. The top
+    /// level is selected if `parent` is `None`. Returns `true` if there is a next page.
+    pub async fn fetch_tag_nodes(
+        &self,
+        parent: Option<&str>,
+        limit: NonZeroUsize,
+        offset: usize,
+    ) -> crate::Result<(Vec<TagNode>, bool)> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::retrieve_tag_nodes(&filter);
+        params.extend(tag_level_params(parent));
+        params.extend(page_params(limit, offset));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                let mut nodes = conn
+                    .prepare_cached(&query)?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        TagNode::try_from(row)
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let has_next_page = nodes.len() > limit.get();
+                nodes.truncate(limit.get());
+
+                Ok((nodes, has_next_page))
+            })
+            .await?)
+    }
+
+    /// Finds a tag by its ID.
+    pub async fn find_tag(&self, id: i64) -> crate::Result<Option<Category>> {
+        self.find_tag_by("t.id", Value::Integer(id)).await
+    }
+
+    /// Finds a tag by its full name.
+    pub async fn find_tag_by_name(&self, name: &str) -> crate::Result<Option<Category>> {
+        self.find_tag_by("t.name", Value::Text(name.to_owned()))
+            .await
+    }
+
+    async fn find_tag_by(&self, column: &str, value: Value) -> crate::Result<Option<Category>> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::retrieve_tag(&filter, column);
+        params.push(value);
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| {
+                        Category::try_from(row)
+                    })
+                    .optional()
+            })
+            .await?)
+    }
+
+    /// Fetches the initial letters of the authors' sort names, along with the number of
+    /// authors that start with each one.
+    pub async fn fetch_author_initials(&self) -> crate::Result<Vec<AuthorInitial>> {
+        let (filter, params) = self.visible_books();
+        let query = sql::retrieve_author_initials(&filter);
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        AuthorInitial::try_from(row)
+                    })?
+                    .collect()
+            })
+            .await?)
+    }
+
+    /// Returns the number of authors whose sort names start with the given uppercase
+    /// letter.
+    pub async fn count_authors(&self, initial: &str) -> crate::Result<usize> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::count_authors(&filter);
+        params.push(Value::Text(initial.to_owned()));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| row.get(0))
+            })
+            .await?)
+    }
+
+    /// Fetches a page of authors whose sort names start with the given uppercase letter,
+    /// sorted by their sort names. Returns `true` if there is a next page.
+    pub async fn fetch_authors(
+        &self,
+        initial: &str,
+        limit: NonZeroUsize,
+        offset: usize,
+    ) -> crate::Result<(Vec<Category>, bool)> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::retrieve_authors(&filter);
+        params.push(Value::Text(initial.to_owned()));
+        params.extend(page_params(limit, offset));
+
+        Ok(self
+            .metadata_db
+            .conn(move |conn| {
+                let mut authors = conn
+                    .prepare_cached(&query)?
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        Category::try_from(row)
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let has_next_page = authors.len() > limit.get();
+                authors.truncate(limit.get());
+
+                Ok((authors, has_next_page))
+            })
+            .await?)
+    }
+
+    /// Finds an author by their ID, along with their note, if there is one.
+    pub async fn find_author(&self, id: i64) -> crate::Result<Option<AuthorDetails>> {
+        let (filter, mut params) = self.visible_books();
+        let query = sql::retrieve_author_by_id(&filter);
+        params.push(Value::Integer(id));
+
+        let Some(mut author) = self
+            .metadata_db
+            .conn(move |conn| {
+                conn.prepare_cached(&query)?
+                    .query_row(rusqlite::params_from_iter(params), |row| {
+                        AuthorDetails::try_from(row)
+                    })
+                    .optional()
+            })
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        if let Some(notes_db) = &self.notes_db {
+            author.note = notes_db
+                .conn(move |conn| {
+                    conn.prepare_cached(sql::RETRIEVE_AUTHOR_NOTE)?
+                        .query_row([id], |row| row.get::<_, CompactStringSql>(0))
+                        .optional()
+                })
+                .await?
+                .map(|note| note.0)
+                .filter(|note| !note.trim().is_empty());
+        }
+
+        Ok(Some(author))
+    }
+}
+
+/// Reads the queries of the given kind that are saved in the library's preferences, keyed
+/// by their names.
+async fn read_saved_queries(
+    metadata_db: &Pool,
+    kind: SavedQueryKind,
+) -> eyre::Result<BTreeMap<CompactString, CompactString>> {
+    let key = kind.preference_key();
+    let Some(value) = metadata_db
+        .conn(move |conn| {
+            conn.prepare(sql::RETRIEVE_PREFERENCE)?
+                .query_row([key], |row| row.get::<_, String>(0))
+                .optional()
+        })
+        .await?
+    else {
+        return Ok(BTreeMap::new());
+    };
+
+    Ok(serde_json::from_str(&value)?)
+}
+
+/// Fetches the values of the custom columns of the books whose IDs are in the JSON array,
+/// grouped by book.
+fn fetch_custom_values(
+    conn: &rusqlite::Connection,
+    columns: &[CustomColumn],
+    book_ids: &str,
+) -> rusqlite::Result<HashMap<i64, Vec<BookCustomValue>>> {
+    let mut custom_values = HashMap::<_, Vec<_>>::new();
+
+    for column in columns {
+        let mut stmt = conn.prepare_cached(&sql::retrieve_book_custom_values(column))?;
+        let mut rows = stmt.query([book_ids])?;
+        let mut values = HashMap::new();
+
+        while let Some(row) = rows.next()? {
+            let book_id: i64 = row.get("book_id")?;
+
+            match column.kind {
+                CustomColumnKind::Text | CustomColumnKind::Enumeration => {
+                    let text = row.get::<_, CompactStringSql>("value")?.0;
+
+                    if let CustomValue::Text(texts) =
+                        values.entry(book_id).or_insert(CustomValue::Text(vec![]))
+                    {
+                        texts.push(text);
+                    }
+                }
+
+                CustomColumnKind::Bool => {
+                    values.insert(book_id, CustomValue::Bool(row.get("value")?));
+                }
+
+                CustomColumnKind::Rating => {
+                    let rating = row.get::<_, u8>("value")?;
+
+                    if rating > 0 {
+                        values.insert(book_id, CustomValue::Rating(rating));
+                    }
+                }
+
+                CustomColumnKind::Date => {
+                    values.insert(book_id, CustomValue::Date(row.get("value")?));
+                }
+            }
+        }
+
+        for (book_id, value) in values {
+            custom_values
+                .entry(book_id)
+                .or_default()
+                .push(BookCustomValue {
+                    name: column.name.clone(),
+                    value,
+                });
+        }
+    }
+
+    Ok(custom_values)
+}
+
+/// Returns the prefix that is stripped from the tag names of a level of the hierarchy,
+/// and the `LIKE` pattern that matches them.
+fn tag_level_params(parent: Option<&str>) -> [Value; 2] {
+    let (prefix, pattern) = match parent {
+        Some(parent) => (
+            format!("{parent}."),
+            filter::like_prefix_pattern(&format!("{parent}.")),
+        ),
+        None => (String::new(), "%".to_owned()),
+    };
+
+    [Value::Text(prefix), Value::Text(pattern)]
+}
+
+/// Returns the limit and the offset of a page, fetching one more item than the page
+/// holds to find out whether there is a next one.
+fn page_params(limit: NonZeroUsize, offset: usize) -> [Value; 2] {
+    [
+        Value::Integer(limit.get() as i64 + 1),
+        Value::Integer(offset as i64),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: NonZeroUsize = NonZeroUsize::new(100).unwrap();
+
+    /// Opens the sample library, restricted to the books that weren't written by Bram
+    /// Stoker, the author of Dracula.
+    async fn restricted_library() -> Library {
+        let mut lib = Library::new("Sample".into(), "data/Library".into(), &[])
+            .await
+            .unwrap();
+        lib.restriction = Some(lib.parse_query("not author:Stoker").unwrap());
+
+        lib
+    }
+
+    #[tokio::test]
+    async fn restriction_hides_the_authors_of_hidden_books() {
+        let lib = restricted_library().await;
+
+        let initials = lib.fetch_author_initials().await.unwrap();
+        let s = initials
+            .iter()
+            .find(|initial| initial.letter == "S")
+            .unwrap();
+        assert_eq!(s.author_count, 1);
+        assert_eq!(lib.count_authors("S").await.unwrap(), 1);
+
+        let (authors, _) = lib.fetch_authors("S", PAGE_SIZE, 0).await.unwrap();
+        let names: Vec<_> = authors.iter().map(|author| author.name.as_str()).collect();
+        assert_eq!(names, ["William Shakespeare"]);
+
+        assert!(lib.find_author(5).await.unwrap().is_none());
+        assert!(lib.find_author(1).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn restriction_hides_the_tags_of_hidden_books() {
+        let lib = restricted_library().await;
+
+        let (nodes, _) = lib.fetch_tag_nodes(None, PAGE_SIZE, 0).await.unwrap();
+        assert!(nodes.iter().all(|node| !node.name.contains("Dracula")
+            && !node.name.starts_with("Horror")
+            && !node.name.starts_with("Vampires")));
+        assert_eq!(lib.count_tag_nodes(None).await.unwrap(), nodes.len());
+
+        assert!(lib.find_tag(27).await.unwrap().is_none());
+        assert!(lib.find_tag_by_name("Dracula").await.unwrap().is_none());
+        assert!(lib.find_tag_by_name("Epic poetry").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn restriction_leaves_hidden_books_out_of_counts() {
+        let lib = restricted_library().await;
+
+        let (languages, _) = lib
+            .fetch_categories(CategoryKind::Language, PAGE_SIZE, 0)
+            .await
+            .unwrap();
+        assert_eq!(languages.len(), 1);
+        assert_eq!(languages[0].book_count, 5);
+
+        let english = lib
+            .find_category(CategoryKind::Language, languages[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(english.book_count, 5);
+        assert_eq!(
+            lib.count_categories(CategoryKind::Language).await.unwrap(),
+            1
+        );
+        assert_eq!(lib.len().await.unwrap(), 5);
+    }
+
+    async fn fetch_book_ids(
+        lib: &Library,
+        limit: usize,
+        offset: usize,
+        order_by: OrderBooksBy,
+        filter: &BookFilter,
+    ) -> Vec<i64> {
+        let (ids, _) = lib
+            .fetch_books(
+                NonZeroUsize::new(limit).unwrap(),
+                offset,
+                order_by,
+                filter,
+                vec![],
+                |mut ids, book| {
+                    ids.push(book.id);
+                    ids
+                },
+            )
+            .await
+            .unwrap();
+
+        ids
+    }
+
+    #[tokio::test]
+    async fn orders_books_by_relevance() {
+        let lib = restricted_library().await;
+        let ranked = BookFilter::Ids(vec![5, 6, 2, 4]);
+
+        let ids = fetch_book_ids(&lib, 100, 0, OrderBooksBy::Relevance, &ranked).await;
+        assert_eq!(ids, [5, 2, 4]);
+
+        let narrowed = BookFilter::And(vec![ranked, BookFilter::HasCover(true)]);
+        let first = fetch_book_ids(&lib, 1, 0, OrderBooksBy::Relevance, &narrowed).await;
+        let rest = fetch_book_ids(&lib, 100, 1, OrderBooksBy::Relevance, &narrowed).await;
+        let all = fetch_book_ids(&lib, 100, 0, OrderBooksBy::Relevance, &narrowed).await;
+        assert_eq!([first, rest].concat(), all);
+        assert!(all.windows(2).all(|w| {
+            let position = |id| [5, 6, 2, 4].iter().position(|&i| i == id);
+            position(w[0]) < position(w[1])
+        }));
+
+        // Books that aren't selected by ID have no relevance, so they're ordered by title.
+        let ids = fetch_book_ids(&lib, 100, 0, OrderBooksBy::Relevance, &BookFilter::All).await;
+        assert_eq!(ids, [4, 1, 3, 5, 2]);
+    }
 }
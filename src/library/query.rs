@@ -0,0 +1,873 @@
+//! A parser for Calibre's search language, which virtual libraries and saved searches are
+//! written in.
+//!
+//! See <https://manual.calibre-ebook.com/gui.html#the-search-interface>.
+
+use std::{collections::HashMap, iter::Peekable, str::CharIndices, sync::Arc};
+
+use compact_str::{CompactString, format_compact};
+use time::{Date, Duration, Month, OffsetDateTime};
+
+use super::{
+    BookFilter, CustomColumn, CustomColumnKind,
+    filter::{self, Comparison, Condition, SearchField},
+};
+
+/// Saved searches and virtual libraries can refer to each other, so their nesting is
+/// limited to break cycles.
+const MAX_SAVED_SEARCH_DEPTH: usize = 8;
+
+/// The filters are built and rendered recursively, so the nesting of parentheses and
+/// `not` is limited to keep queries from overflowing the stack.
+const MAX_NESTING: usize = 64;
+
+/// The maximum length of a query, in bytes.
+const MAX_QUERY_LEN: usize = 4096;
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryError {
+    #[error("unexpected end of query")]
+    UnexpectedEnd,
+    #[error("unexpected \"{0}\" at position {1}")]
+    UnexpectedToken(CompactString, usize),
+    #[error("unterminated quoted string at position {0}")]
+    UnterminatedString(usize),
+    #[error("unknown search field \"{0}\"")]
+    UnknownField(CompactString),
+    #[error("unknown saved search \"{0}\"")]
+    UnknownSavedSearch(CompactString),
+    #[error("unknown virtual library \"{0}\"")]
+    UnknownVirtualLibrary(CompactString),
+    #[error("saved searches and virtual libraries are nested too deeply")]
+    TooDeep,
+    #[error("the query is nested too deeply")]
+    TooNested,
+    #[error("the query is longer than {MAX_QUERY_LEN} bytes")]
+    TooLong,
+    #[error("invalid number \"{0}\"")]
+    InvalidNumber(CompactString),
+    #[error("invalid date \"{0}\"")]
+    InvalidDate(CompactString),
+    #[error("invalid boolean \"{0}\"")]
+    InvalidBoolean(CompactString),
+    #[error("invalid regular expression \"{0}\": {1}")]
+    InvalidRegex(CompactString, regex::Error),
+}
+
+/// What the queries of a library can refer to, besides the built-in fields.
+#[derive(Clone)]
+pub struct QueryContext {
+    /// The queries of the saved searches, keyed by their lowercase names. They're
+    /// referred to with the `search:` prefix.
+    pub saved_searches: HashMap<CompactString, CompactString>,
+    /// The queries of the virtual libraries, keyed by their lowercase names. They're
+    /// referred to with the `vl:` prefix.
+    pub virtual_libraries: HashMap<CompactString, CompactString>,
+    /// The custom columns, which are referred to by their lookup names, e.g. `#read:`.
+    pub custom_columns: Arc<[CustomColumn]>,
+}
+
+/// Parses a query into a filter.
+pub fn parse(query: &str, context: &QueryContext) -> Result<BookFilter, QueryError> {
+    parse_nested(query, context, 0)
+}
+
+fn parse_nested(
+    query: &str,
+    context: &QueryContext,
+    depth: usize,
+) -> Result<BookFilter, QueryError> {
+    if depth > MAX_SAVED_SEARCH_DEPTH {
+        return Err(QueryError::TooDeep);
+    }
+
+    if query.len() > MAX_QUERY_LEN {
+        return Err(QueryError::TooLong);
+    }
+
+    let mut parser = Parser {
+        tokens: tokenize(query)?.into_iter().peekable(),
+        context,
+        depth,
+        nesting: 0,
+    };
+
+    if parser.tokens.peek().is_none() {
+        return Ok(BookFilter::All);
+    }
+
+    let filter = parser.parse_or()?;
+
+    match parser.tokens.next() {
+        None => Ok(filter),
+        Some((token, position)) => Err(QueryError::UnexpectedToken(token.text(), position)),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    /// A value, optionally prefixed by the field it's matched against.
+    Term {
+        field: Option<CompactString>,
+        value: CompactString,
+    },
+}
+
+impl Token {
+    fn text(&self) -> CompactString {
+        match self {
+            Self::LParen => "(".into(),
+            Self::RParen => ")".into(),
+            Self::And => "and".into(),
+            Self::Or => "or".into(),
+            Self::Not => "not".into(),
+            Self::Term { field: None, value } => value.clone(),
+            Self::Term {
+                field: Some(field),
+                value,
+            } => compact_str::format_compact!("{field}:{value}"),
+        }
+    }
+}
+
+/// Splits the query into tokens, along with their positions.
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let mut tokens = vec![];
+    let mut chars = query.char_indices().peekable();
+
+    while let Some(&(position, ch)) = chars.peek() {
+        match ch {
+            _ if ch.is_whitespace() => {
+                chars.next();
+            }
+
+            '(' => {
+                chars.next();
+                tokens.push((Token::LParen, position));
+            }
+
+            ')' => {
+                chars.next();
+                tokens.push((Token::RParen, position));
+            }
+
+            '"' => {
+                let value = read_quoted(&mut chars)?;
+                tokens.push((Token::Term { field: None, value }, position));
+            }
+
+            _ => {
+                let word = read_word(&mut chars);
+                let token = match field_prefix(&word) {
+                    Some(field) if chars.peek().is_some_and(|&(_, ch)| ch == '"') => {
+                        let mut value = CompactString::from(&word[field.len() + 1..]);
+                        value.push_str(&read_quoted(&mut chars)?);
+
+                        Token::Term {
+                            field: Some(field.to_lowercase().into()),
+                            value,
+                        }
+                    }
+                    Some(field) => Token::Term {
+                        field: Some(field.to_lowercase().into()),
+                        value: word[field.len() + 1..].into(),
+                    },
+                    None if word.eq_ignore_ascii_case("and") => Token::And,
+                    None if word.eq_ignore_ascii_case("or") => Token::Or,
+                    None if word.eq_ignore_ascii_case("not") => Token::Not,
+                    None => Token::Term {
+                        field: None,
+                        value: word,
+                    },
+                };
+
+                tokens.push((token, position));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads a string enclosed in double quotes, in which backslashes escape the next
+/// character.
+fn read_quoted(chars: &mut Peekable<CharIndices>) -> Result<CompactString, QueryError> {
+    let Some((start, _)) = chars.next() else {
+        return Err(QueryError::UnexpectedEnd);
+    };
+    let mut value = CompactString::default();
+
+    loop {
+        match chars.next() {
+            Some((_, '"')) => return Ok(value),
+            Some((_, '\\')) => match chars.next() {
+                Some((_, ch)) => value.push(ch),
+                None => return Err(QueryError::UnterminatedString(start)),
+            },
+            Some((_, ch)) => value.push(ch),
+            None => return Err(QueryError::UnterminatedString(start)),
+        }
+    }
+}
+
+/// Reads the characters up to the next whitespace, parenthesis or double quote that
+/// follows a field prefix.
+fn read_word(chars: &mut Peekable<CharIndices>) -> CompactString {
+    let mut word = CompactString::default();
+
+    while let Some(&(_, ch)) = chars.peek() {
+        if ch.is_whitespace() || ch == '(' || ch == ')' || (ch == '"' && word.ends_with(':')) {
+            break;
+        }
+
+        word.push(ch);
+        chars.next();
+    }
+
+    word
+}
+
+/// Returns the field of a word like `tag:fiction`, if it has one. Field names consist of
+/// letters, digits and underscores, and custom columns start with `#`.
+fn field_prefix(word: &str) -> Option<&str> {
+    let (field, _) = word.split_once(':')?;
+    let mut chars = field.chars();
+
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '#' || ch == '_' => {}
+        _ => return None,
+    }
+
+    chars
+        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        .then_some(field)
+}
+
+struct Parser<'a, I: Iterator<Item = (Token, usize)>> {
+    tokens: Peekable<I>,
+    context: &'a QueryContext,
+    depth: usize,
+    /// The number of parentheses and `not` that enclose the current expression.
+    nesting: usize,
+}
+
+impl<I: Iterator<Item = (Token, usize)>> Parser<'_, I> {
+    /// `or_expr := and_expr ("or" and_expr)*`
+    fn parse_or(&mut self) -> Result<BookFilter, QueryError> {
+        let mut filters = vec![self.parse_and()?];
+
+        while self
+            .tokens
+            .next_if(|(token, _)| *token == Token::Or)
+            .is_some()
+        {
+            filters.push(self.parse_and()?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.pop().unwrap(),
+            _ => BookFilter::Or(filters),
+        })
+    }
+
+    /// `and_expr := not_expr (["and"] not_expr)*`
+    fn parse_and(&mut self) -> Result<BookFilter, QueryError> {
+        let mut filters = vec![self.parse_not()?];
+
+        loop {
+            match self.tokens.peek() {
+                Some((Token::And, _)) => {
+                    self.tokens.next();
+                }
+                // Adjacent expressions are implicitly joined with "and".
+                Some((Token::LParen | Token::Not | Token::Term { .. }, _)) => {}
+                _ => break,
+            }
+
+            filters.push(self.parse_not()?);
+        }
+
+        Ok(match filters.len() {
+            1 => filters.pop().unwrap(),
+            _ => BookFilter::And(filters),
+        })
+    }
+
+    /// `not_expr := "not" not_expr | "(" or_expr ")" | term`
+    fn parse_not(&mut self) -> Result<BookFilter, QueryError> {
+        match self.tokens.next() {
+            Some((Token::Not, _)) => {
+                let filter = self.nested(Self::parse_not)?;
+
+                Ok(BookFilter::Not(Box::new(filter)))
+            }
+
+            Some((Token::LParen, _)) => {
+                let filter = self.nested(Self::parse_or)?;
+
+                match self.tokens.next() {
+                    Some((Token::RParen, _)) => Ok(filter),
+                    Some((token, position)) => {
+                        Err(QueryError::UnexpectedToken(token.text(), position))
+                    }
+                    None => Err(QueryError::UnexpectedEnd),
+                }
+            }
+
+            Some((Token::Term { field, value }, _)) => self.term(field, value),
+
+            Some((token, position)) => Err(QueryError::UnexpectedToken(token.text(), position)),
+            None => Err(QueryError::UnexpectedEnd),
+        }
+    }
+
+    /// Parses an expression that is nested in parentheses or `not`.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<BookFilter, QueryError>,
+    ) -> Result<BookFilter, QueryError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(QueryError::TooNested);
+        }
+
+        self.nesting += 1;
+        let filter = parse(self);
+        self.nesting -= 1;
+
+        filter
+    }
+
+    fn term(
+        &mut self,
+        field: Option<CompactString>,
+        value: CompactString,
+    ) -> Result<BookFilter, QueryError> {
+        let Some(field) = field else {
+            // Like in Calibre, values without a field are searched in most text fields.
+            let condition = text_condition(&value)?;
+
+            return Ok(BookFilter::Or(
+                [
+                    SearchField::Title,
+                    SearchField::Author,
+                    SearchField::Tag,
+                    SearchField::Series,
+                ]
+                .into_iter()
+                .map(|field| BookFilter::Field(field, condition.clone()))
+                .collect(),
+            ));
+        };
+
+        let (field, kind) = match field.as_str() {
+            "title" => (SearchField::Title, ValueKind::Text),
+            "author" | "authors" => (SearchField::Author, ValueKind::Text),
+            "tag" | "tags" => (SearchField::Tag, ValueKind::Text),
+            "series" => (SearchField::Series, ValueKind::Text),
+            "series_index" => (SearchField::SeriesIndex, ValueKind::Number),
+            "publisher" => (SearchField::Publisher, ValueKind::Text),
+            "language" | "languages" => (SearchField::Language, ValueKind::Text),
+            "format" | "formats" => (SearchField::Format, ValueKind::Text),
+            "size" => (SearchField::Size, ValueKind::Size),
+            "comments" => (SearchField::Comments, ValueKind::Text),
+            "rating" => (SearchField::Rating, ValueKind::Number),
+            "pubdate" => (SearchField::PublishedAt, ValueKind::Date),
+            "date" => (SearchField::AddedAt, ValueKind::Date),
+            "last_modified" => (SearchField::LastModifiedAt, ValueKind::Date),
+
+            // `identifiers:isbn:978` only matches the identifiers of that type.
+            "identifier" | "identifiers" => {
+                let (kind, value) = match value.split_once(':') {
+                    Some((kind, value)) => (Some(kind.into()), value),
+                    None => (None, value.as_str()),
+                };
+                let condition = match exists_condition(value) {
+                    Some(condition) => condition,
+                    None => text_condition(value)?,
+                };
+
+                return Ok(BookFilter::Field(SearchField::Identifier(kind), condition));
+            }
+
+            "cover" => {
+                return match exists_condition(&value) {
+                    Some(Condition::Exists(has_cover)) => Ok(BookFilter::HasCover(has_cover)),
+                    _ => Err(QueryError::InvalidBoolean(value)),
+                };
+            }
+
+            "search" => {
+                let Some(query) = self.context.saved_searches.get(&value.to_lowercase()) else {
+                    return Err(QueryError::UnknownSavedSearch(value));
+                };
+
+                return parse_nested(query, self.context, self.depth + 1);
+            }
+
+            "vl" => {
+                let Some(query) = self.context.virtual_libraries.get(&value.to_lowercase()) else {
+                    return Err(QueryError::UnknownVirtualLibrary(value));
+                };
+
+                return parse_nested(query, self.context, self.depth + 1);
+            }
+
+            label if label.starts_with('#') => {
+                let Some(column) = self
+                    .context
+                    .custom_columns
+                    .iter()
+                    .find(|column| column.label.eq_ignore_ascii_case(&label[1..]))
+                else {
+                    return Err(QueryError::UnknownField(field));
+                };
+                let kind = match column.kind {
+                    CustomColumnKind::Text | CustomColumnKind::Enumeration => ValueKind::Text,
+                    CustomColumnKind::Bool => ValueKind::Bool,
+                    CustomColumnKind::Rating => ValueKind::Number,
+                    CustomColumnKind::Date => ValueKind::Date,
+                };
+
+                (
+                    SearchField::CustomColumn {
+                        id: column.id,
+                        kind: column.kind,
+                    },
+                    kind,
+                )
+            }
+
+            _ => return Err(QueryError::UnknownField(field)),
+        };
+
+        if let Some(condition) = exists_condition(&value) {
+            return Ok(BookFilter::Field(field, condition));
+        }
+
+        let condition = match kind {
+            ValueKind::Text => text_condition(&value)?,
+            ValueKind::Number | ValueKind::Size => {
+                let (comparison, number) = split_comparison(&value);
+                let Some(number) = parse_number(number, kind == ValueKind::Size) else {
+                    return Err(QueryError::InvalidNumber(value));
+                };
+
+                Condition::Compare(comparison, number)
+            }
+            ValueKind::Date => return date_filter(field, &value),
+            ValueKind::Bool => {
+                let is_true = BookFilter::Field(field, Condition::Compare(Comparison::Equal, 1.0));
+
+                // Like in Calibre, books without a value are considered unchecked.
+                return match value.to_lowercase().as_str() {
+                    "yes" | "checked" => Ok(is_true),
+                    "no" | "unchecked" => Ok(BookFilter::Not(Box::new(is_true))),
+                    _ => Err(QueryError::InvalidBoolean(value)),
+                };
+            }
+        };
+
+        Ok(BookFilter::Field(field, condition))
+    }
+}
+
+/// How the values of a field are written in queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    Text,
+    Number,
+    /// A number of bytes, optionally followed by the `K`, `M` or `G` unit.
+    Size,
+    Date,
+    Bool,
+}
+
+/// Parses `true` and `false`, which match books whose field has a value, or doesn't.
+fn exists_condition(value: &str) -> Option<Condition> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(Condition::Exists(true))
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(Condition::Exists(false))
+    } else {
+        None
+    }
+}
+
+/// Parses the value of a text field. Values that start with `=` are matched exactly, and
+/// the ones that start with `~` are regular expressions.
+fn text_condition(value: &str) -> Result<Condition, QueryError> {
+    if let Some(text) = value.strip_prefix('=') {
+        return Ok(Condition::Equals(text.into()));
+    }
+
+    if let Some(pattern) = value.strip_prefix('~') {
+        // Invalid patterns are rejected here, as SQLite would fail the whole query.
+        return match filter::case_insensitive_regex(pattern) {
+            Ok(_) => Ok(Condition::Matches(pattern.into())),
+            Err(err) => Err(QueryError::InvalidRegex(pattern.into(), err)),
+        };
+    }
+
+    Ok(Condition::Contains(value.into()))
+}
+
+/// Splits the relational operator off the value, which defaults to `=`.
+fn split_comparison(value: &str) -> (Comparison, &str) {
+    const OPERATORS: [(&str, Comparison); 6] = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        ("!=", Comparison::NotEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ];
+
+    OPERATORS
+        .into_iter()
+        .find_map(|(operator, comparison)| Some((comparison, value.strip_prefix(operator)?)))
+        .unwrap_or((Comparison::Equal, value))
+}
+
+fn parse_number(text: &str, with_unit: bool) -> Option<f64> {
+    let (text, multiplier) = match text.as_bytes().last() {
+        Some(b'k' | b'K') if with_unit => (&text[..text.len() - 1], 1024.0),
+        Some(b'm' | b'M') if with_unit => (&text[..text.len() - 1], 1024.0 * 1024.0),
+        Some(b'g' | b'G') if with_unit => (&text[..text.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (text, 1.0),
+    };
+
+    text.parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+        .map(|number| number * multiplier)
+}
+
+/// Builds the filter of a date field. Dates match every moment of the days they refer
+/// to, so `pubdate:<2020` matches books published before 2020, and `pubdate:<=2020`
+/// matches the ones published before 2021.
+fn date_filter(field: SearchField, value: &str) -> Result<BookFilter, QueryError> {
+    let (comparison, spec) = split_comparison(value);
+    let Some((start, end)) = date_range(spec, OffsetDateTime::now_utc().date()) else {
+        return Err(QueryError::InvalidDate(value.into()));
+    };
+    let (start, end) = (format_date(start), format_date(end));
+
+    let (start, end) = match comparison {
+        Comparison::Equal => (Some(start), Some(end)),
+        Comparison::NotEqual => {
+            let range = Condition::DateRange(Some(start), Some(end));
+            return Ok(BookFilter::Not(Box::new(BookFilter::Field(field, range))));
+        }
+        Comparison::Less => (None, Some(start)),
+        Comparison::LessOrEqual => (None, Some(end)),
+        Comparison::Greater => (Some(end), None),
+        Comparison::GreaterOrEqual => (Some(start), None),
+    };
+
+    Ok(BookFilter::Field(field, Condition::DateRange(start, end)))
+}
+
+/// Returns the first day a date refers to, and the day after the last one. Dates are
+/// written as `YYYY`, `YYYY-MM`, `YYYY-MM-DD`, `today`, `yesterday`, `thismonth` or
+/// `<N>daysago`.
+fn date_range(spec: &str, today: Date) -> Option<(Date, Date)> {
+    let day = |date: Date| Some((date, date.next_day()?));
+
+    match spec.to_lowercase().as_str() {
+        "today" => return day(today),
+        "yesterday" => return day(today.previous_day()?),
+        "thismonth" => return month_range(today.year(), today.month()),
+        spec => {
+            if let Some(days) = spec.strip_suffix("daysago") {
+                return day(today.checked_sub(Duration::days(days.parse().ok()?))?);
+            }
+        }
+    }
+
+    let parts = spec
+        .split('-')
+        .map(|part| part.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let month = |month: i32| Month::try_from(u8::try_from(month).ok()?).ok();
+
+    match parts[..] {
+        [year] => Some((
+            Date::from_calendar_date(year, Month::January, 1).ok()?,
+            Date::from_calendar_date(year + 1, Month::January, 1).ok()?,
+        )),
+        [year, m] => month_range(year, month(m)?),
+        [year, m, d] => day(Date::from_calendar_date(year, month(m)?, u8::try_from(d).ok()?).ok()?),
+        _ => None,
+    }
+}
+
+fn month_range(year: i32, month: Month) -> Option<(Date, Date)> {
+    let next = match month {
+        Month::December => Date::from_calendar_date(year + 1, Month::January, 1),
+        _ => Date::from_calendar_date(year, month.next(), 1),
+    };
+
+    Some((Date::from_calendar_date(year, month, 1).ok()?, next.ok()?))
+}
+
+fn format_date(date: Date) -> CompactString {
+    format_compact!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        date.month() as u8,
+        date.day()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> QueryContext {
+        QueryContext {
+            saved_searches: HashMap::from([
+                ("scifi".into(), "tag:\"=Sci-Fi\"".into()),
+                ("loop".into(), "search:loop".into()),
+            ]),
+            virtual_libraries: HashMap::new(),
+            custom_columns: Arc::new([CustomColumn {
+                id: 3,
+                label: "read".into(),
+                name: "Read".into(),
+                kind: CustomColumnKind::Bool,
+                browseable: false,
+            }]),
+        }
+    }
+
+    fn field(field: SearchField, condition: Condition) -> BookFilter {
+        BookFilter::Field(field, condition)
+    }
+
+    fn to_sql(query: &str) -> (String, Vec<rusqlite::types::Value>) {
+        let (sql, params) = parse(query, &context()).unwrap().to_sql();
+
+        (sql.split_whitespace().collect::<Vec<_>>().join(" "), params)
+    }
+
+    #[test]
+    fn parses_fields_and_operators() {
+        assert_eq!(
+            parse(r#"tag:"=Sci-Fi" and not author:Asimov"#, &context()).unwrap(),
+            BookFilter::And(vec![
+                field(SearchField::Tag, Condition::Equals("Sci-Fi".into())),
+                BookFilter::Not(Box::new(field(
+                    SearchField::Author,
+                    Condition::Contains("Asimov".into())
+                ))),
+            ])
+        );
+        assert_eq!(
+            parse("(title:a or title:b) series:true", &context()).unwrap(),
+            BookFilter::And(vec![
+                BookFilter::Or(vec![
+                    field(SearchField::Title, Condition::Contains("a".into())),
+                    field(SearchField::Title, Condition::Contains("b".into())),
+                ]),
+                field(SearchField::Series, Condition::Exists(true)),
+            ])
+        );
+        assert_eq!(parse("  ", &context()).unwrap(), BookFilter::All);
+    }
+
+    #[test]
+    fn parses_comparisons() {
+        assert_eq!(
+            parse("rating:>=4", &context()).unwrap(),
+            field(
+                SearchField::Rating,
+                Condition::Compare(Comparison::GreaterOrEqual, 4.0)
+            )
+        );
+        assert_eq!(
+            parse("size:<2M", &context()).unwrap(),
+            field(
+                SearchField::Size,
+                Condition::Compare(Comparison::Less, 2.0 * 1024.0 * 1024.0)
+            )
+        );
+        assert_eq!(
+            parse("#read:yes", &context()).unwrap(),
+            field(
+                SearchField::CustomColumn {
+                    id: 3,
+                    kind: CustomColumnKind::Bool
+                },
+                Condition::Compare(Comparison::Equal, 1.0)
+            )
+        );
+    }
+
+    #[test]
+    fn parses_dates() {
+        let range = |start: Option<&str>, end: Option<&str>| {
+            field(
+                SearchField::PublishedAt,
+                Condition::DateRange(start.map(Into::into), end.map(Into::into)),
+            )
+        };
+
+        assert_eq!(
+            parse("pubdate:2020", &context()).unwrap(),
+            range(Some("2020-01-01"), Some("2021-01-01"))
+        );
+        assert_eq!(
+            parse("pubdate:<2020-02", &context()).unwrap(),
+            range(None, Some("2020-02-01"))
+        );
+        assert_eq!(
+            parse("pubdate:<=2020-12", &context()).unwrap(),
+            range(None, Some("2021-01-01"))
+        );
+        assert_eq!(
+            parse("pubdate:>2020-02-28", &context()).unwrap(),
+            range(Some("2020-02-29"), None)
+        );
+
+        let today = Date::from_calendar_date(2024, Month::March, 1).unwrap();
+        let day = |month, day| Date::from_calendar_date(2024, month, day).unwrap();
+
+        assert_eq!(
+            date_range("yesterday", today),
+            Some((day(Month::February, 29), today))
+        );
+        assert_eq!(
+            date_range("thismonth", today),
+            Some((today, day(Month::April, 1)))
+        );
+        assert_eq!(
+            date_range("2daysago", today),
+            Some((day(Month::February, 28), day(Month::February, 29)))
+        );
+    }
+
+    #[test]
+    fn expands_saved_searches() {
+        assert_eq!(
+            parse("search:SciFi", &context()).unwrap(),
+            field(SearchField::Tag, Condition::Equals("Sci-Fi".into()))
+        );
+        assert!(matches!(
+            parse("search:loop", &context()),
+            Err(QueryError::TooDeep)
+        ));
+        assert!(matches!(
+            parse("search:missing", &context()),
+            Err(QueryError::UnknownSavedSearch(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        let error = |query: &str| parse(query, &context()).unwrap_err();
+
+        assert!(matches!(error("(title:a"), QueryError::UnexpectedEnd));
+        assert!(matches!(error("title:a)"), QueryError::UnexpectedToken(..)));
+        assert!(matches!(
+            error("title:\"a"),
+            QueryError::UnterminatedString(6)
+        ));
+        assert!(matches!(error("foo:bar"), QueryError::UnknownField(_)));
+        assert!(matches!(error("rating:>abc"), QueryError::InvalidNumber(_)));
+        assert!(matches!(
+            error("pubdate:2020-13"),
+            QueryError::InvalidDate(_)
+        ));
+        assert!(matches!(
+            error("cover:maybe"),
+            QueryError::InvalidBoolean(_)
+        ));
+        assert!(matches!(
+            error(r#"title:"~(""#),
+            QueryError::InvalidRegex(..)
+        ));
+        assert!(matches!(error("not"), QueryError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn limits_nesting_and_length() {
+        let nested = |depth: usize| format!("{}title:a{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert!(parse(&nested(MAX_NESTING), &context()).is_ok());
+        assert!(matches!(
+            parse(&nested(MAX_NESTING + 1), &context()),
+            Err(QueryError::TooNested)
+        ));
+        assert!(matches!(
+            parse(&"not ".repeat(1000), &context()),
+            Err(QueryError::TooNested)
+        ));
+        assert!(matches!(
+            parse(&"(".repeat(5000), &context()),
+            Err(QueryError::TooLong)
+        ));
+        assert!(matches!(
+            parse(&"a ".repeat(MAX_QUERY_LEN), &context()),
+            Err(QueryError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn renders_sql() {
+        use rusqlite::types::Value;
+
+        let (sql, params) = to_sql(r#"tag:"=Sci-Fi" and not author:Asimov"#);
+
+        assert_eq!(
+            sql,
+            "(b.id IN ( SELECT link.book FROM books_tags_link AS link \
+             INNER JOIN tags AS x ON link.tag = x.id WHERE x.name = ? COLLATE NOCASE)) \
+             AND (NOT (b.id IN ( SELECT link.book FROM books_authors_link AS link \
+             INNER JOIN authors AS x ON link.author = x.id WHERE x.name LIKE ? ESCAPE '\\')))"
+        );
+        assert_eq!(
+            params,
+            [Value::Text("Sci-Fi".into()), Value::Text("%Asimov%".into())]
+        );
+
+        let (sql, params) = to_sql("rating:>=4");
+
+        assert_eq!(
+            sql,
+            "b.id IN ( SELECT link.book FROM books_ratings_link AS link \
+             INNER JOIN ratings AS x ON link.rating = x.id \
+             WHERE NULLIF(x.rating, 0) / 2.0 >= ?)"
+        );
+        assert_eq!(params, [Value::Real(4.0)]);
+
+        let (sql, _) = to_sql("series:false");
+
+        assert!(sql.starts_with("NOT b.id IN ("), "{sql}");
+        assert!(sql.ends_with("WHERE x.name IS NOT NULL)"), "{sql}");
+
+        let (sql, params) = to_sql("pubdate:2020");
+
+        assert_eq!(
+            sql,
+            "(IIF(b.pubdate >= '0102', b.pubdate, NULL) IS NOT NULL \
+             AND IIF(b.pubdate >= '0102', b.pubdate, NULL) >= ? \
+             AND IIF(b.pubdate >= '0102', b.pubdate, NULL) < ?)"
+        );
+        assert_eq!(
+            params,
+            [
+                Value::Text("2020-01-01".into()),
+                Value::Text("2021-01-01".into())
+            ]
+        );
+
+        let (_, params) = to_sql(r"title:50%_off\");
+
+        assert_eq!(params, [Value::Text(r"%50\%\_off\\%".into())]);
+    }
+}
@@ -1,11 +1,23 @@
+use async_sqlite::rusqlite::types::Value;
 use const_format::formatcp;
 
+use super::{BookFilter, CustomColumn, CustomColumnKind};
+
+/// Expands to the IDs of the books in the current page. They are passed as a JSON array
+/// in the first parameter.
+const PAGE_BOOK_IDS: &str = "SELECT value FROM json_each(?1)";
+
+/// The queries of a page of books in some order. The filters of the books are composed
+/// into them, as they're built at runtime from the queries and facets of feeds.
 pub trait SqlQueries: Send + Sync + 'static {
-    fn retrieve_books(&self) -> &'static str;
-    fn retrieve_book_authors(&self) -> &'static str;
-    fn retrieve_book_languages(&self) -> &'static str;
-    fn retrieve_book_tags(&self) -> &'static str;
-    fn retrieve_book_data(&self) -> &'static str;
+    /// Selects the books that match the filter. Its parameters are the ones returned by
+    /// [`SqlQueries::params`], followed by the filter's, and the limit and the offset.
+    fn retrieve_books(&self, filter: &str) -> String;
+
+    /// Returns the parameters that the query of books takes before the filter's.
+    fn params(&self, _filter: &BookFilter) -> Vec<Value> {
+        vec![]
+    }
 }
 
 macro_rules! impl_sql_queries {
@@ -13,107 +25,541 @@ macro_rules! impl_sql_queries {
         pub struct $struct_name;
 
         impl SqlQueries for $struct_name {
-            fn retrieve_books(&self) -> &'static str {
-                const {
-                    formatcp!(
-                        r#"SELECT
-                           	b.id AS id,
-                           	b.uuid AS uuid,
-                           	b.title AS title,
-                           	b.timestamp AS added_at,
-                           	b.pubdate AS published_at,
-                           	b.has_cover AS has_cover,
-                           	b.last_modified AS last_modified_at,
-                           	b.path AS path,
-                           	c.text AS comment
-                        FROM books as b
-                  		LEFT JOIN comments as c ON c.book = b.id
-                       	ORDER BY {order_by}
-                       	LIMIT ?1 OFFSET ?2"#,
-                        order_by = $order_by
-                    )
-                }
+            fn retrieve_books(&self, filter: &str) -> String {
+                retrieve_books("", "", filter, $order_by)
             }
+        }
+    )+};
+}
 
-            fn retrieve_book_authors(&self) -> &'static str {
-                const {
-                    formatcp!(
-                        r#"SELECT
-                            a.name AS author_name,
-                            link.book AS book_id
-                        FROM books_authors_link as link
-                       	INNER JOIN (
-                      		SELECT id AS b_id FROM books
-                      		ORDER BY {order_by}
-                      		LIMIT ?1 OFFSET ?2
-                       	) ON book_id = b_id
-                       	INNER JOIN authors AS a ON link.author = a.id;"#,
-                        order_by = $order_by
-                    )
-                }
-            }
+impl_sql_queries! {
+    OrderedByDateAdded: [order_by: "b.timestamp DESC"],
+    OrderedByAuthor: [order_by: "b.author_sort ASC"],
+    OrderedByTitle: [order_by: "b.sort ASC"],
+    OrderedBySeriesIndex: [order_by: "b.series_index ASC"],
+}
 
-            fn retrieve_book_languages(&self) -> &'static str {
-                const {
-                    formatcp!(
-                        r#"SELECT
-                            l.lang_code AS lang_code,
-                            link.book AS book_id
-                        FROM books_languages_link as link
-                       	INNER JOIN (
-                      		SELECT id AS b_id FROM books
-                      		ORDER BY {order_by}
-                      		LIMIT ?1 OFFSET ?2
-                       	) ON book_id = b_id
-                       	INNER JOIN languages AS l ON link.lang_code = l.id;"#,
-                        order_by = $order_by
-                    )
-                }
-            }
+/// Orders the books that full-text searches select by ID, the most relevant first, by
+/// their position among the IDs. The books are joined with their positions, which are
+/// passed as a JSON array of IDs in the first parameter, rather than looked up for each
+/// book.
+pub struct OrderedByRelevance;
 
-            fn retrieve_book_tags(&self) -> &'static str {
-                const {
-                    formatcp!(
-                        r#"SELECT
-                            link.book AS book_id,
-                            t.name AS tag_name
-                        FROM books_tags_link as link
-                       	INNER JOIN (
-                      		SELECT id AS b_id FROM books
-                      		ORDER BY {order_by}
-                      		LIMIT ?1 OFFSET ?2
-                       	) ON book_id = b_id
-                       	INNER JOIN tags AS t ON link.tag = t.id;"#,
-                        order_by = $order_by
-                    )
-                }
-            }
+impl SqlQueries for OrderedByRelevance {
+    fn retrieve_books(&self, filter: &str) -> String {
+        retrieve_books(
+            "WITH ranks(id, rank) AS (SELECT value, key FROM json_each(?))",
+            "INNER JOIN ranks AS r ON r.id = b.id",
+            filter,
+            "r.rank ASC",
+        )
+    }
 
-            fn retrieve_book_data(&self) -> &'static str {
-                const {
-                    formatcp!(
-                        r#"SELECT
-                            d.uncompressed_size AS file_size,
-                            d.name AS file_name,
-                            d.format AS format,
-                            d.book AS book_id
-                        FROM data AS d
-                       	WHERE book_id IN (
-                            SELECT
-                                id AS b_id
-                            FROM books
-                            ORDER BY {order_by}
-                            LIMIT ?1 OFFSET ?2);"#,
-                        order_by = $order_by
-                    )
-                }
-            }
-        }
-    )+};
+    fn params(&self, filter: &BookFilter) -> Vec<Value> {
+        super::ranked_ids(filter)
+            .map(|ids| Value::Text(super::json_ids(ids)))
+            .into_iter()
+            .collect()
+    }
 }
 
-impl_sql_queries! {
-    OrderedByDateAdded: [order_by: "timestamp DESC"],
-    OrderedByAuthor: [order_by: "author_sort ASC"],
-    OrderedByTitle: [order_by: "sort ASC"],
+fn retrieve_books(with: &str, join: &str, filter: &str, order_by: &str) -> String {
+    format!(
+        r#"{with}
+        SELECT
+           	b.id AS id,
+           	b.uuid AS uuid,
+           	b.title AS title,
+           	b.author_sort AS author_sort,
+           	b.timestamp AS added_at,
+           	b.pubdate AS published_at,
+           	b.has_cover AS has_cover,
+           	b.last_modified AS last_modified_at,
+           	b.path AS path,
+           	b.series_index AS series_index,
+           	s.id AS series_id,
+           	s.name AS series_name,
+           	(
+           	    SELECT p.name FROM books_publishers_link AS link
+           	    INNER JOIN publishers AS p ON link.publisher = p.id
+           	    WHERE link.book = b.id
+           	) AS publisher_name,
+           	(
+           	    SELECT r.rating FROM books_ratings_link AS link
+           	    INNER JOIN ratings AS r ON link.rating = r.id
+           	    WHERE link.book = b.id
+           	) AS rating,
+           	c.text AS comment
+        FROM books AS b
+        {join}
+  		LEFT JOIN comments AS c ON c.book = b.id
+  		LEFT JOIN books_series_link AS bsl ON bsl.book = b.id
+  		LEFT JOIN series AS s ON bsl.series = s.id
+  		WHERE {filter}
+       	ORDER BY {order_by}
+       	LIMIT ? OFFSET ?"#
+    )
+}
+
+pub fn count_books(filter: &str) -> String {
+    format!("SELECT COUNT(*) FROM books AS b WHERE {filter}")
+}
+
+pub fn count_facet_values(facet: super::Facet, filter: &str) -> String {
+    let (value, join) = match facet {
+        super::Facet::Language => (
+            "l.lang_code",
+            r#"INNER JOIN books_languages_link AS link ON link.book = b.id
+            INNER JOIN languages AS l ON link.lang_code = l.id"#,
+        ),
+        super::Facet::Format => ("lower(d.format)", "INNER JOIN data AS d ON d.book = b.id"),
+        super::Facet::Cover => ("CASE b.has_cover WHEN 1 THEN 'true' ELSE 'false' END", ""),
+    };
+
+    format!(
+        r#"SELECT
+            {value} AS value,
+            COUNT(DISTINCT b.id) AS book_count
+        FROM books AS b
+       	{join}
+       	WHERE {filter}
+       	GROUP BY value
+       	ORDER BY book_count DESC, value ASC"#
+    )
+}
+
+pub const RETRIEVE_BOOK_AUTHORS: &str = formatcp!(
+    r#"SELECT
+        a.id AS author_id,
+        a.name AS author_name,
+        link.book AS book_id
+    FROM books_authors_link AS link
+   	INNER JOIN authors AS a ON link.author = a.id
+   	WHERE link.book IN ({PAGE_BOOK_IDS});"#
+);
+
+pub const RETRIEVE_BOOK_LANGUAGES: &str = formatcp!(
+    r#"SELECT
+        l.lang_code AS lang_code,
+        link.book AS book_id
+    FROM books_languages_link AS link
+   	INNER JOIN languages AS l ON link.lang_code = l.id
+   	WHERE link.book IN ({PAGE_BOOK_IDS});"#
+);
+
+pub const RETRIEVE_BOOK_TAGS: &str = formatcp!(
+    r#"SELECT
+        link.book AS book_id,
+        t.name AS tag_name
+    FROM books_tags_link AS link
+   	INNER JOIN tags AS t ON link.tag = t.id
+   	WHERE link.book IN ({PAGE_BOOK_IDS});"#
+);
+
+pub const RETRIEVE_BOOK_IDENTIFIERS: &str = formatcp!(
+    r#"SELECT
+        i.type AS kind,
+        i.val AS value,
+        i.book AS book_id
+    FROM identifiers AS i
+   	WHERE i.book IN ({PAGE_BOOK_IDS})
+   	ORDER BY i.type ASC;"#
+);
+
+pub const RETRIEVE_BOOK_DATA: &str = formatcp!(
+    r#"SELECT
+        d.uncompressed_size AS file_size,
+        d.name AS file_name,
+        d.format AS format,
+        d.book AS book_id
+    FROM data AS d
+   	WHERE d.book IN ({PAGE_BOOK_IDS});"#
+);
+
+pub const RETRIEVE_PREFERENCE: &str = "SELECT val FROM preferences WHERE key = ?1;";
+
+pub const RETRIEVE_CUSTOM_COLUMNS: &str = r#"SELECT
+        id,
+        label,
+        name,
+        datatype
+    FROM custom_columns
+   	WHERE NOT mark_for_delete
+   	    AND datatype IN ('text', 'enumeration', 'bool', 'rating', 'datetime')
+   	ORDER BY name ASC;"#;
+
+/// Selects the values of the custom column of the books in the current page.
+pub fn retrieve_book_custom_values(column: &CustomColumn) -> String {
+    let id = column.id;
+
+    if column.kind.is_normalized() {
+        format!(
+            r#"SELECT
+                link.book AS book_id,
+                v.value AS value
+            FROM books_custom_column_{id}_link AS link
+           	INNER JOIN custom_column_{id} AS v ON link.value = v.id
+           	WHERE link.book IN ({PAGE_BOOK_IDS})
+           	ORDER BY link.id ASC;"#
+        )
+    } else {
+        format!(
+            r#"SELECT
+                v.book AS book_id,
+                v.value AS value
+            FROM custom_column_{id} AS v
+           	WHERE v.book IN ({PAGE_BOOK_IDS});"#
+        )
+    }
+}
+
+/// Selects the IDs of the books that match the filter, as the `visible_books` table that
+/// categories are counted among, so that the ones of the books a user may not see are
+/// left out. It opens the queries, so the parameters of the filter come first.
+fn visible_books(filter: &str) -> String {
+    format!("WITH visible_books AS (SELECT b.id FROM books AS b WHERE {filter})")
+}
+
+/// Counts the distinct values of the custom column.
+pub fn count_custom_column_values(column: &CustomColumn, filter: &str) -> String {
+    let id = column.id;
+    let visible_books = visible_books(filter);
+
+    if column.kind.is_normalized() {
+        format!(
+            r#"{visible_books}
+            SELECT COUNT(DISTINCT value) FROM books_custom_column_{id}_link
+           	WHERE book IN visible_books"#
+        )
+    } else {
+        format!(
+            r#"{visible_books}
+            SELECT COUNT(DISTINCT value) FROM custom_column_{id}
+           	WHERE book IN visible_books"#
+        )
+    }
+}
+
+/// Selects the values of the custom column as [`Category`](super::Category) rows. If
+/// `by_id` is `true`, only the value with the ID in the parameter after the filter's is
+/// selected. Otherwise, the parameters after the filter's are the limit and the offset.
+///
+/// The values of columns that aren't normalized don't have IDs, so the values themselves
+/// are used instead.
+pub fn retrieve_custom_column_values(column: &CustomColumn, filter: &str, by_id: bool) -> String {
+    let id = column.id;
+    let visible_books = visible_books(filter);
+    let (condition, pagination) = match by_id {
+        true => ("AND v.id = ?", ""),
+        false => ("", "LIMIT ? OFFSET ?"),
+    };
+
+    if column.kind.is_normalized() {
+        format!(
+            r#"{visible_books}
+            SELECT
+                v.id AS id,
+                CAST(v.value AS TEXT) AS name,
+                COUNT(link.book) AS book_count
+            FROM custom_column_{id} AS v
+           	INNER JOIN books_custom_column_{id}_link AS link ON link.value = v.id
+           	WHERE link.book IN visible_books {condition}
+           	GROUP BY v.id
+           	ORDER BY v.value ASC
+           	{pagination};"#
+        )
+    } else {
+        let condition = condition.replace("v.id", "v.value");
+        let name = match column.kind {
+            CustomColumnKind::Bool => "CASE WHEN v.value THEN 'Yes' ELSE 'No' END",
+            _ => "CAST(v.value AS TEXT)",
+        };
+
+        format!(
+            r#"{visible_books}
+            SELECT
+                v.value AS id,
+                {name} AS name,
+                COUNT(v.book) AS book_count
+            FROM custom_column_{id} AS v
+           	WHERE v.book IN visible_books {condition}
+           	GROUP BY v.value
+           	ORDER BY v.value DESC
+           	{pagination};"#
+        )
+    }
 }
+
+/// The tables of a [`CategoryKind`](super::CategoryKind).
+pub struct CategoryTable {
+    table: &'static str,
+    link_table: &'static str,
+    link_column: &'static str,
+    name: &'static str,
+    sort: &'static str,
+}
+
+pub const SERIES: CategoryTable = CategoryTable {
+    table: "series",
+    link_table: "books_series_link",
+    link_column: "series",
+    name: "name",
+    sort: "COALESCE(c.sort, c.name)",
+};
+
+pub const PUBLISHERS: CategoryTable = CategoryTable {
+    table: "publishers",
+    link_table: "books_publishers_link",
+    link_column: "publisher",
+    name: "name",
+    sort: "COALESCE(c.sort, c.name)",
+};
+
+pub const LANGUAGES: CategoryTable = CategoryTable {
+    table: "languages",
+    link_table: "books_languages_link",
+    link_column: "lang_code",
+    name: "lang_code",
+    sort: "c.lang_code",
+};
+
+impl CategoryTable {
+    pub fn count(&self, filter: &str) -> String {
+        let Self {
+            link_table,
+            link_column,
+            ..
+        } = self;
+
+        format!(
+            r#"{}
+            SELECT COUNT(DISTINCT {link_column}) FROM {link_table}
+           	WHERE book IN visible_books"#,
+            visible_books(filter)
+        )
+    }
+
+    /// Selects a page of the categories. The parameters after the filter's are the limit
+    /// and the offset.
+    pub fn retrieve(&self, filter: &str) -> String {
+        let Self {
+            table,
+            link_table,
+            link_column,
+            name,
+            sort,
+        } = self;
+
+        format!(
+            r#"{}
+            SELECT
+                c.id AS id,
+                c.{name} AS name,
+                COUNT(link.book) AS book_count
+            FROM {table} AS c
+           	INNER JOIN {link_table} AS link ON link.{link_column} = c.id
+           	WHERE link.book IN visible_books
+           	GROUP BY c.id
+           	ORDER BY {sort} ASC
+           	LIMIT ? OFFSET ?;"#,
+            visible_books(filter)
+        )
+    }
+
+    /// Selects the category with the ID in the parameter after the filter's.
+    pub fn retrieve_by_id(&self, filter: &str) -> String {
+        let Self {
+            table,
+            link_table,
+            link_column,
+            name,
+            ..
+        } = self;
+
+        format!(
+            r#"{}
+            SELECT
+                c.id AS id,
+                c.{name} AS name,
+                COUNT(link.book) AS book_count
+            FROM {table} AS c
+           	INNER JOIN {link_table} AS link ON link.{link_column} = c.id
+           	WHERE link.book IN visible_books AND c.id = ?
+           	GROUP BY c.id;"#,
+            visible_books(filter)
+        )
+    }
+}
+
+/// Selects the tags under a level of the hierarchy. The first parameter after the
+/// filter's is the prefix that is stripped from their names and the second one is the
+/// `LIKE` pattern that matches them.
+const TAG_LEVEL: &str = r#"SELECT
+        t.id AS id,
+        substr(t.name, length(?) + 1) AS rest
+    FROM tags AS t
+   	WHERE t.name LIKE ? ESCAPE '\'"#;
+
+/// Expands to the name of the node at the current level of the hierarchy.
+const TAG_NODE_NAME: &str =
+    "substr(rest, 1, CASE instr(rest, '.') WHEN 0 THEN length(rest) ELSE instr(rest, '.') - 1 END)";
+
+pub fn count_tag_nodes(filter: &str) -> String {
+    format!(
+        r#"{}
+        SELECT COUNT(DISTINCT {TAG_NODE_NAME} COLLATE NOCASE)
+        FROM ({TAG_LEVEL})
+       	WHERE id IN (SELECT tag FROM books_tags_link WHERE book IN visible_books);"#,
+        visible_books(filter)
+    )
+}
+
+/// Selects a page of the nodes of a level of the hierarchy. The parameters after the
+/// ones of [`TAG_LEVEL`] are the limit and the offset.
+pub fn retrieve_tag_nodes(filter: &str) -> String {
+    format!(
+        r#"{}
+        SELECT
+            {TAG_NODE_NAME} AS name,
+            MAX(CASE instr(rest, '.') WHEN 0 THEN level.id END) AS tag_id,
+            MAX(instr(rest, '.') > 0) AS has_children,
+            COUNT(DISTINCT link.book) AS book_count
+        FROM ({TAG_LEVEL}) AS level
+       	INNER JOIN books_tags_link AS link ON link.tag = level.id
+       	WHERE link.book IN visible_books
+       	GROUP BY name COLLATE NOCASE
+       	ORDER BY name COLLATE NOCASE ASC
+       	LIMIT ? OFFSET ?;"#,
+        visible_books(filter)
+    )
+}
+
+/// Selects the tag whose ID (`t.id`) or full name (`t.name`) is in the parameter after
+/// the filter's.
+pub fn retrieve_tag(filter: &str, column: &str) -> String {
+    format!(
+        r#"{}
+        SELECT
+            t.id AS id,
+            t.name AS name,
+            COUNT(link.book) AS book_count
+        FROM tags AS t
+       	INNER JOIN books_tags_link AS link ON link.tag = t.id
+       	WHERE link.book IN visible_books AND {column} = ?
+       	GROUP BY t.id;"#,
+        visible_books(filter)
+    )
+}
+
+/// Expands to the initial letter of an author's sort name.
+const AUTHOR_INITIAL: &str = "upper(substr(COALESCE(a.sort, a.name), 1, 1))";
+
+/// Selects the IDs of the authors of the visible books.
+const VISIBLE_AUTHORS: &str = "SELECT author FROM books_authors_link WHERE book IN visible_books";
+
+pub fn retrieve_author_initials(filter: &str) -> String {
+    format!(
+        r#"{}
+        SELECT
+            {AUTHOR_INITIAL} AS letter,
+            COUNT(*) AS author_count
+        FROM authors AS a
+       	WHERE a.id IN ({VISIBLE_AUTHORS})
+       	GROUP BY letter
+       	ORDER BY letter ASC;"#,
+        visible_books(filter)
+    )
+}
+
+/// Counts the authors whose initial is in the parameter after the filter's.
+pub fn count_authors(filter: &str) -> String {
+    format!(
+        r#"{}
+        SELECT COUNT(*)
+        FROM authors AS a
+       	WHERE a.id IN ({VISIBLE_AUTHORS}) AND {AUTHOR_INITIAL} = ?;"#,
+        visible_books(filter)
+    )
+}
+
+/// Selects a page of the authors whose initial is in the parameter after the filter's,
+/// which is followed by the limit and the offset.
+pub fn retrieve_authors(filter: &str) -> String {
+    format!(
+        r#"{}
+        SELECT
+            a.id AS id,
+            a.name AS name,
+            COUNT(link.book) AS book_count
+        FROM authors AS a
+       	INNER JOIN books_authors_link AS link ON link.author = a.id
+       	WHERE link.book IN visible_books AND {AUTHOR_INITIAL} = ?
+       	GROUP BY a.id
+       	ORDER BY COALESCE(a.sort, a.name) ASC
+       	LIMIT ? OFFSET ?;"#,
+        visible_books(filter)
+    )
+}
+
+/// Selects the author with the ID in the parameter after the filter's.
+pub fn retrieve_author_by_id(filter: &str) -> String {
+    format!(
+        r#"{}
+        SELECT
+            a.id AS id,
+            a.name AS name,
+            a.link AS link,
+            COUNT(link.book) AS book_count
+        FROM authors AS a
+       	INNER JOIN books_authors_link AS link ON link.author = a.id
+       	WHERE link.book IN visible_books AND a.id = ?
+       	GROUP BY a.id;"#,
+        visible_books(filter)
+    )
+}
+
+pub const RETRIEVE_AUTHOR_NOTE: &str =
+    "SELECT searchable_text FROM notes WHERE item = ?1 AND colname = 'authors';";
+
+/// Books without a UUID are skipped, as they can't be told apart across libraries.
+pub fn retrieve_book_versions(filter: &str) -> String {
+    format!(
+        r#"SELECT b.id AS id, b.uuid AS uuid, b.last_modified AS last_modified_at
+        FROM books AS b
+        WHERE b.uuid IS NOT NULL AND ({filter})"#
+    )
+}
+
+pub const FIND_BOOK_ID_BY_UUID: &str = "SELECT id FROM books WHERE uuid = ?1";
+
+pub const FIND_BOOK_ID_BY_PATH: &str = "SELECT id FROM books WHERE path = ?1";
+
+pub const RETRIEVE_BOOK_COVER: &str = r#"SELECT
+        b.id AS id,
+        b.path AS path,
+        b.last_modified AS last_modified_at
+    FROM books AS b
+   	WHERE b.id = ?1 AND b.has_cover;"#;
+
+/// Selects the books whose text contains the terms of the FTS5 query, ranked by their
+/// most relevant match.
+pub const FULL_TEXT_SEARCH: &str = formatcp!(
+    r#"SELECT t.book
+    FROM books_fts AS f
+    INNER JOIN books_text AS t ON f.rowid = t.id
+    WHERE books_fts MATCH ?1
+    GROUP BY t.book
+    ORDER BY MIN(f.rank)
+    LIMIT {};"#,
+    super::MAX_FULL_TEXT_SEARCH_RESULTS
+);
+
+/// Selects the excerpts of the books' text that match the FTS5 query, the most relevant
+/// first. The matched terms are enclosed in the `\x01` and `\x02` control characters.
+pub const RETRIEVE_FULL_TEXT_SNIPPETS: &str = r#"SELECT
+        t.book AS book_id,
+        snippet(books_fts, 0, char(1), char(2), '…', 32) AS snippet
+    FROM books_fts AS f
+    INNER JOIN books_text AS t ON f.rowid = t.id
+    WHERE books_fts MATCH ?1 AND t.book IN (SELECT value FROM json_each(?2))
+    ORDER BY f.rank;"#;
@@ -1,16 +1,32 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod disk_cache;
+pub mod epubs;
 pub mod errors;
+pub mod file_names;
+pub mod kobo;
+pub mod kosync;
 pub mod library;
 mod router;
+pub mod signing;
+pub mod thumbnails;
+pub mod users;
 pub mod utils;
 
-use std::{path::PathBuf, time::Duration};
+use std::{net::IpAddr, path::PathBuf, time::Duration};
 
 use actix_web::{App, HttpServer, middleware as mw, web::Data};
 use clap::Parser;
+use epubs::Epubs;
+use file_names::FileNameTemplate;
+use kobo::{Kobo, KoboDeviceArg};
+use kosync::Kosync;
 use library::Libraries;
+use router::TrustedProxies;
+use signing::LinkSigner;
+use thumbnails::{ThumbnailSize, Thumbnails};
+use users::{LoginLinks, Users};
 
 pub type Result<T, E = errors::AppError> = std::result::Result<T, E>;
 
@@ -28,6 +44,95 @@ pub struct Cli {
     #[clap(long, default_value = "10100")]
     pub port: u16,
 
+    /// Set the directory where generated files, such as thumbnails, are cached. It must
+    /// not be writable by other users. Defaults to $XDG_CACHE_HOME/seshat, or
+    /// ~/.cache/seshat
+    #[clap(long)]
+    pub cache_dir: Option<PathBuf>,
+    /// Set the bounding box that cover thumbnails are resized to fit in
+    #[clap(long, value_name = "WIDTHxHEIGHT", default_value = "240x360")]
+    pub thumbnail_size: ThumbnailSize,
+    /// Embed the current metadata of books, such as their title, authors and cover, into
+    /// their EPUB files when they're downloaded. The rewritten files are cached
+    #[clap(long)]
+    pub embed_metadata: bool,
+    /// Set the template of the names that books are downloaded as. The available fields
+    /// are {id}, {title}, {author}, {authors}, {author_sort}, {series}, {series_index},
+    /// {publisher}, {year} and {ext}
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{title} - {authors}.{ext}"
+    )]
+    pub download_name: FileNameTemplate,
+
+    /// Require the users of the catalog to log in with HTTP Basic authentication. Each
+    /// line of the file at the path is a user, formatted as USERNAME:HASH, where HASH is
+    /// the Argon2 hash of their password, e.g. as printed by --hash-password. It may be
+    /// followed by :LIBRARIES:RESTRICTION, to limit the user to the comma-separated
+    /// libraries, and to the books that match the query, e.g. "not tag:adult"
+    #[clap(long, value_name = "PATH")]
+    pub users_file: Option<PathBuf>,
+    /// Set the URL of the logo that OPDS clients show on their login screens
+    #[clap(long, value_name = "URL", requires = "users_file")]
+    pub login_logo: Option<String>,
+    /// Set the URL of a web page that helps users log in, which OPDS clients link to from
+    /// their login screens
+    #[clap(long, value_name = "URL", requires = "users_file")]
+    pub login_help: Option<String>,
+    /// Let the devices of users authenticate with tokens embedded in the catalog's URL,
+    /// e.g. /device/TOKEN/opds, for readers that can't send credentials. Each line of the
+    /// file at the path is a device, formatted as USERNAME:DEVICE:TOKEN, where TOKEN is
+    /// at least 16 random letters and digits. It's read again when it's modified
+    #[clap(long, value_name = "PATH", requires = "users_file")]
+    pub device_tokens: Option<PathBuf>,
+    /// Sign the links to book files and covers, so that they can be downloaded without
+    /// credentials until they expire after the number of seconds
+    #[clap(long, value_name = "SECONDS", requires = "users_file")]
+    pub signed_links_ttl: Option<u64>,
+    /// Trust the Forwarded and X-Forwarded-For headers of the requests from the reverse
+    /// proxy at the address, to log the addresses of the clients it forwards requests of
+    #[clap(long, value_name = "IP")]
+    pub trusted_proxy: Vec<IpAddr>,
+    /// Read a password from the standard input, print its Argon2 hash for the users file,
+    /// and exit
+    #[clap(long, exclusive = true)]
+    pub hash_password: bool,
+
+    /// Serve a KOReader progress sync server under /kosync, which stores its users and
+    /// their reading progress in the SQLite database at the path. It's created if it
+    /// doesn't exist
+    #[clap(long, value_name = "PATH")]
+    pub kosync_db: Option<PathBuf>,
+    /// Don't let new users register to the KOReader progress sync server
+    #[clap(long, requires = "kosync_db")]
+    pub kosync_disable_registration: bool,
+    /// Let the users of the catalog register to the KOReader progress sync server, under
+    /// their usernames. It's disabled by default if there's a users file, since anyone
+    /// could register the name of a user who hasn't yet
+    #[clap(
+        long,
+        requires_all = ["kosync_db", "users_file"],
+        conflicts_with = "kosync_disable_registration"
+    )]
+    pub kosync_enable_registration: bool,
+    /// Show the reading progress of every user of the KOReader progress sync server in
+    /// the catalog, which doesn't require logging in. Users who log in to the catalog
+    /// only see their own progress, as the user of the same name
+    #[clap(long, requires = "kosync_db", conflicts_with = "users_file")]
+    pub kosync_share_progress: bool,
+
+    /// Emulate the Kobo store's sync API under /kobo, so Kobo devices sync books from
+    /// the libraries. The synced books and their reading states are stored in the SQLite
+    /// database at the path. It's created if it doesn't exist
+    #[clap(long, value_name = "PATH", requires = "kobo_device")]
+    pub kobo_db: Option<PathBuf>,
+    /// Add a Kobo device, which syncs the books of the library that match the query, or
+    /// every book if there's no query, e.g. "mykobo:Books:vl:Fiction" or
+    /// "mykobo:Books:#shelf:Kobo". The token is part of the device's API endpoint
+    #[clap(long, value_name = "TOKEN:LIBRARY[:QUERY]", requires = "kobo_db")]
+    pub kobo_device: Vec<KoboDeviceArg>,
+
     /// Enable verbose logging. For greater control, use the $RUST_LOG environment
     /// variable
     #[cfg_attr(debug_assertions, clap(default_value = "true"))]
@@ -41,6 +146,10 @@ pub struct Cli {
     /// "metadata.db" is located.
     #[clap(long = "lib:path", group = "lib")]
     pub lib_path: Vec<PathBuf>,
+    /// Let the books be browsed by the values of a custom column, given by its lookup
+    /// name, e.g. "#shelf". It applies to every library that has the column
+    #[clap(long, value_name = "LOOKUP_NAME")]
+    pub browse_column: Vec<String>,
 }
 
 #[tokio::main]
@@ -48,14 +157,87 @@ async fn main() -> eyre::Result<()> {
     let mut cli = Cli::parse();
     install_helpers(cli.verbose)?;
 
-    let libraries = Data::new(Libraries::from_cli(&mut cli).await?);
+    if cli.hash_password {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        println!(
+            "{}",
+            users::hash_password(password.trim_end_matches(['\r', '\n']))?
+        );
+
+        return Ok(());
+    }
+
+    let mut libraries = Libraries::from_cli(&mut cli).await?;
+    let users = match cli.users_file.take() {
+        Some(path) => {
+            let login_links = LoginLinks {
+                logo: cli.login_logo.take(),
+                help: cli.login_help.take(),
+            };
+            let mut users = Users::open(&path, login_links)?;
+
+            if let Some(path) = cli.device_tokens.take() {
+                users.read_devices(&path)?;
+            }
+
+            libraries.restrict_to_users(&users)?;
+
+            Some(Data::new(users))
+        }
+        None => None,
+    };
+    let libraries = Data::new(libraries);
+    let signer = match cli.signed_links_ttl {
+        Some(ttl) => Some(Data::new(LinkSigner::new(Duration::from_secs(ttl))?)),
+        None => None,
+    };
+    let cache_dir = cli.cache_dir.take().unwrap_or_else(disk_cache::default_dir);
+    disk_cache::create_dir(&cache_dir)?;
+    let thumbnails = Data::new(Thumbnails::new(cache_dir.clone(), cli.thumbnail_size));
+    let file_name_template = Data::new(cli.download_name.clone());
+    let trusted_proxies = Data::new(TrustedProxies(std::mem::take(&mut cli.trusted_proxy)));
+    let epubs = cli.embed_metadata.then(|| Data::new(Epubs::new(cache_dir)));
+    let kosync = match cli.kosync_db.take() {
+        Some(path) => Some(Data::new(
+            Kosync::open(
+                path,
+                match users {
+                    Some(_) => cli.kosync_enable_registration,
+                    None => !cli.kosync_disable_registration,
+                },
+                cli.kosync_share_progress,
+            )
+            .await?,
+        )),
+        None => None,
+    };
+    let kobo = match cli.kobo_db.take() {
+        Some(path) => Some(Data::new(
+            Kobo::open(path, &cli.kobo_device, &libraries).await?,
+        )),
+        None => None,
+    };
 
     HttpServer::new(move || {
         App::new()
-            .wrap(mw::Condition::new(cli.verbose, mw::Logger::default()))
+            .wrap(mw::from_fn(router::authenticate))
+            .wrap(mw::Condition::new(cli.verbose, router::access_logger()))
             .wrap(mw::NormalizePath::trim())
             .app_data(libraries.clone())
-            .configure(router::config)
+            .app_data(thumbnails.clone())
+            .app_data(file_name_template.clone())
+            .app_data(trusted_proxies.clone())
+            .configure(|cfg| {
+                router::config(
+                    cfg,
+                    kosync.as_ref(),
+                    kobo.as_ref(),
+                    epubs.as_ref(),
+                    users.as_ref(),
+                    signer.as_ref(),
+                )
+            })
     })
     .keep_alive(Duration::from_secs(30))
     .bind((cli.host, cli.port))?
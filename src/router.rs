@@ -1,9 +1,46 @@
 use actix_web::web;
+pub use auth::{TrustedProxies, access_logger, authenticate};
 
+use crate::{epubs::Epubs, kobo::Kobo, kosync::Kosync, signing::LinkSigner, users::Users};
+
+mod auth;
+mod kobo;
+mod kosync;
 mod lib_content;
 mod opds;
 
-pub fn config(cfg: &mut web::ServiceConfig) {
+pub fn config(
+    cfg: &mut web::ServiceConfig,
+    kosync: Option<&web::Data<Kosync>>,
+    kobo: Option<&web::Data<Kobo>>,
+    epubs: Option<&web::Data<Epubs>>,
+    users: Option<&web::Data<Users>>,
+    signer: Option<&web::Data<LinkSigner>>,
+) {
     cfg.service(web::scope(lib_content::COMMON_ROUTE).configure(lib_content::configure))
-        .service(web::scope(opds::COMMON_ROUTE).configure(opds::configure));
+        .service(web::scope(opds::COMMON_ROUTE).configure(opds::configure))
+        .service(web::scope(opds::v2::COMMON_ROUTE).configure(opds::v2::configure))
+        .service(web::scope(opds::ui::COMMON_ROUTE).configure(opds::ui::configure));
+
+    if let Some(users_data) = users {
+        cfg.app_data(users_data.clone());
+    }
+
+    if let Some(signer_data) = signer {
+        cfg.app_data(signer_data.clone());
+    }
+
+    if let Some(epubs_data) = epubs {
+        cfg.app_data(epubs_data.clone());
+    }
+
+    if let Some(kosync_data) = kosync {
+        cfg.app_data(kosync_data.clone())
+            .service(web::scope(kosync::COMMON_ROUTE).configure(kosync::configure));
+    }
+
+    if let Some(kobo_data) = kobo {
+        cfg.app_data(kobo_data.clone())
+            .service(web::scope(kobo::COMMON_ROUTE).configure(kobo::configure));
+    }
 }
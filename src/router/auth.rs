@@ -0,0 +1,645 @@
+//! HTTP Basic authentication of the catalog's users, which OPDS clients such as KOReader
+//! support. The KOReader progress sync server and the Kobo sync API are exempt, since
+//! they authenticate their clients on their own.
+//!
+//! Readers that can't send credentials use a device token instead, which is embedded in
+//! the catalog's URL, e.g. `/device/TOKEN/opds`. The links of the responses to such
+//! requests keep the prefix, and the links to files are signed if signed links are
+//! enabled, so that they can be followed without credentials. See [`LinkContext`].
+//!
+//! The client addresses of the security logs are the addresses of the peers, unless
+//! they're trusted proxies, whose `Forwarded` and `X-Forwarded-For` headers are used.
+//! The tokens and signatures in the URLs of requests are redacted from the access log, as
+//! they're credentials too.
+
+use std::{
+    future::{Ready, ready},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use actix_web::{
+    FromRequest, HttpMessage as _, HttpRequest,
+    body::{EitherBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{
+        Uri,
+        header::{self, HeaderMap, HeaderValue},
+    },
+    middleware::{Logger, Next},
+    web,
+};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use compact_str::{CompactString, ToCompactString as _, format_compact};
+use serde::{Deserialize, Serializer};
+
+use super::opds::authentication;
+use crate::{
+    errors::AppError,
+    kosync::{Kosync, Progress},
+    library::{Libraries, Library},
+    signing::LinkSigner,
+    users::Users,
+};
+
+/// The routes that are exempt from authentication.
+const EXEMPT_ROUTES: &[&str] = &[
+    super::kosync::COMMON_ROUTE,
+    super::kobo::COMMON_ROUTE,
+    authentication::ROUTE,
+];
+
+/// The prefix of the routes that are authenticated by a device token.
+const DEVICE_ROUTE: &str = "/device";
+
+/// Rejects the requests without the credentials of a user, if there are users.
+pub async fn authenticate(
+    users: Option<web::Data<Users>>,
+    signer: Option<web::Data<LinkSigner>>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> actix_web::Result<ServiceResponse<EitherBody<impl MessageBody>>> {
+    let mut context = LinkContext {
+        prefix: CompactString::default(),
+        signer: signer.map(web::Data::into_inner),
+    };
+
+    let Some(users) = users else {
+        return call_with_context(context, req, next).await;
+    };
+
+    if let Some(rest) = req
+        .path()
+        .strip_prefix(DEVICE_ROUTE)
+        .and_then(|rest| rest.strip_prefix('/'))
+    {
+        let (token, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (token, path) = (token.to_owned(), format!("/{path}"));
+        let Some(device) = users.find_device(&token) else {
+            warn!(
+                target: "seshat::security",
+                client = client_addr(req.request()).as_str(),
+                "Rejected an unknown device token"
+            );
+
+            return Ok(unauthorized(req).map_into_right_body());
+        };
+
+        let user = CompactString::from(device.user.as_str());
+        context.prefix = format_compact!("{DEVICE_ROUTE}/{token}");
+        strip_path_prefix(&mut req, &path)?;
+        req.extensions_mut().insert(AuthenticatedUser(user));
+
+        return call_with_context(context, req, next).await;
+    }
+
+    let path = req.path();
+    let is_exempt = EXEMPT_ROUTES.iter().any(|route| {
+        path.strip_prefix(route)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+
+    if is_exempt {
+        return call_with_context(context, req, next).await;
+    }
+
+    // A signature is only valid for the path it was made for, so a signed link can't be
+    // used to access anything else.
+    if let Ok(web::Query(SignedLinkQuery {
+        expires,
+        signature: Some(signature),
+    })) = web::Query::<SignedLinkQuery>::from_query(req.query_string())
+    {
+        let is_valid = context.signer.as_ref().is_some_and(|signer| {
+            signer.verify(req.path(), expires.unwrap_or_default(), &signature)
+        });
+
+        if !is_valid {
+            warn!(
+                target: "seshat::security",
+                path = req.path(),
+                client = client_addr(req.request()).as_str(),
+                "Rejected an invalid or expired signed link"
+            );
+
+            return Ok(req
+                .error_response(AppError::InvalidSignature)
+                .map_into_right_body());
+        }
+
+        return call_with_context(context, req, next).await;
+    }
+
+    let Some((name, password)) = basic_credentials(req.headers()) else {
+        return Ok(unauthorized(req).map_into_right_body());
+    };
+
+    let users = users.into_inner();
+    let authenticated_name = name.clone();
+    let is_authenticated = tokio::task::spawn_blocking(move || {
+        users.authenticate(&authenticated_name, &password).is_some()
+    })
+    .await
+    .map_err(AppError::from)?;
+
+    if !is_authenticated {
+        warn!(
+            target: "seshat::security",
+            user = name,
+            client = client_addr(req.request()).as_str(),
+            "Rejected the credentials of a user"
+        );
+
+        return Ok(unauthorized(req).map_into_right_body());
+    }
+
+    req.extensions_mut()
+        .insert(AuthenticatedUser(CompactString::from(name)));
+
+    call_with_context(context, req, next).await
+}
+
+/// Logs the requests like [`Logger::default`], but with the tokens of devices and Kobo
+/// devices, and the signatures of links, redacted from their URLs and referrers.
+pub fn access_logger() -> Logger {
+    Logger::new(r#"%a "%{request}xi" %s %b "%{referer}xi" "%{User-Agent}i" %T"#)
+        .custom_request_replace("request", |req| {
+            format!(
+                "{} {} {:?}",
+                req.method(),
+                redacted(&req.uri().to_string()),
+                req.version()
+            )
+        })
+        .custom_request_replace("referer", |req| {
+            req.headers()
+                .get(header::REFERER)
+                .and_then(|referer| referer.to_str().ok())
+                .map_or_else(|| "-".to_owned(), redacted)
+        })
+}
+
+/// Replaces the token in the path of the link, and its signature, with `REDACTED`. The
+/// link may be absolute, as referrers are.
+fn redacted(link: &str) -> String {
+    let path_start = link.find("://").map_or(0, |scheme_end| {
+        let authority_start = scheme_end + "://".len();
+
+        link[authority_start..]
+            .find('/')
+            .map_or(link.len(), |i| authority_start + i)
+    });
+    let (origin, path_and_query) = link.split_at(path_start);
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+    let mut redacted = origin.to_owned();
+
+    let token_route = [DEVICE_ROUTE, super::kobo::COMMON_ROUTE]
+        .into_iter()
+        .find_map(|route| Some((route, path.strip_prefix(route)?.strip_prefix('/')?)));
+
+    match token_route {
+        Some((route, rest)) => {
+            redacted += route;
+            redacted += "/REDACTED";
+
+            if let Some((_, rest)) = rest.split_once('/') {
+                redacted += "/";
+                redacted += rest;
+            }
+        }
+        None => redacted += path,
+    }
+
+    if let Some(query) = query {
+        let query = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some(("signature", _)) => "signature=REDACTED",
+                _ => pair,
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        redacted += "?";
+        redacted += &query;
+    }
+
+    redacted
+}
+
+/// Calls the next service, building the links of its response in the context.
+async fn call_with_context<B: MessageBody>(
+    context: LinkContext,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> actix_web::Result<ServiceResponse<EitherBody<B>>> {
+    Ok(LINK_CONTEXT
+        .scope(context, async move { next.call(req).await })
+        .await?
+        .map_into_left_body())
+}
+
+#[derive(Deserialize)]
+struct SignedLinkQuery {
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+/// Replaces the path of the request, so that it's routed without the prefix of the device
+/// token.
+fn strip_path_prefix(req: &mut ServiceRequest, path: &str) -> actix_web::Result<()> {
+    let path_and_query = match req.query_string() {
+        "" => path.to_owned(),
+        query => format!("{path}?{query}"),
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(
+        path_and_query
+            .parse()
+            .map_err(actix_web::error::ErrorBadRequest)?,
+    );
+    let uri = Uri::from_parts(parts).map_err(actix_web::error::ErrorBadRequest)?;
+
+    req.match_info_mut().get_mut().update(&uri);
+    req.head_mut().uri = uri;
+
+    Ok(())
+}
+
+/// Responds with the challenge of the Basic scheme, along with a link to the OPDS
+/// Authentication document, which clients that support it use instead.
+fn unauthorized(req: ServiceRequest) -> ServiceResponse {
+    let mut res = req.error_response(AppError::Unauthorized);
+
+    res.headers_mut().insert(
+        header::LINK,
+        HeaderValue::from_static(const_format::concatcp!(
+            "<",
+            authentication::ROUTE,
+            ">; rel=\"http://opds-spec.org/auth/document\"; type=\"",
+            authentication::MEDIA_TYPE,
+            "\""
+        )),
+    );
+
+    res
+}
+
+tokio::task_local! {
+    static LINK_CONTEXT: LinkContext;
+}
+
+/// How the links of a response are built, which depends on how its request was
+/// authenticated.
+#[derive(Clone, Default)]
+pub struct LinkContext {
+    /// The prefix of the routes that the request was sent through, which is prepended to
+    /// the links of the response.
+    prefix: CompactString,
+    /// Signs the links to files, if signed links are enabled.
+    signer: Option<Arc<LinkSigner>>,
+}
+
+impl LinkContext {
+    /// Returns the context of the request, for the functions that build links on another
+    /// thread, such as the ones that [`Library::fetch_books`] calls.
+    pub fn current() -> Self {
+        LINK_CONTEXT.try_with(Self::clone).unwrap_or_default()
+    }
+
+    /// Builds links in the context.
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        LINK_CONTEXT.sync_scope(self.clone(), f)
+    }
+}
+
+/// Prepends the prefix of the routes that the request was sent through to the link, if
+/// it's a local link.
+pub fn prefixed(link: &str) -> CompactString {
+    let prefix = LINK_CONTEXT
+        .try_with(|context| context.prefix.clone())
+        .unwrap_or_default();
+
+    if prefix.is_empty() || !link.starts_with('/') {
+        return link.into();
+    }
+
+    format_compact!("{prefix}{link}")
+}
+
+/// Serializes a link with [`prefixed`].
+pub fn serialize_link<S: Serializer>(link: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&prefixed(link))
+}
+
+/// Signs the link to a file, if signed links are enabled.
+pub fn signed(link: CompactString) -> CompactString {
+    LINK_CONTEXT
+        .try_with(|context| {
+            context
+                .signer
+                .as_ref()
+                .map(|signer| CompactString::from(signer.sign(&link)))
+        })
+        .ok()
+        .flatten()
+        .unwrap_or(link)
+}
+
+/// The name of the user who sent the request, which is stored in the request's
+/// extensions once their credentials are verified.
+struct AuthenticatedUser(CompactString);
+
+/// The libraries that the user who sent the request may access, which only show the books
+/// the user may see. Every library is accessible if authentication is disabled.
+pub struct UserLibraries {
+    libraries: web::Data<Libraries>,
+    user: Option<CompactString>,
+}
+
+impl UserLibraries {
+    pub fn get(&self, name: &str) -> Option<&Library> {
+        self.libraries.get(name, self.user.as_deref())
+    }
+
+    pub fn get_all(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.get_all(self.user.as_deref())
+    }
+}
+
+impl FromRequest for UserLibraries {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let libraries = req
+            .app_data::<web::Data<Libraries>>()
+            .expect("the libraries are configured")
+            .clone();
+        let user = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.0.clone());
+
+        ready(Ok(Self { libraries, user }))
+    }
+}
+
+/// The addresses of the reverse proxies whose `Forwarded` and `X-Forwarded-For` headers
+/// are trusted to tell the addresses of clients.
+#[derive(Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+/// Returns the address of the client that sent the request, for logging. The headers that
+/// tell it are only trusted if the peer is a trusted proxy, as clients can set them too.
+fn client_addr(req: &HttpRequest) -> CompactString {
+    let Some(peer_addr) = req.peer_addr() else {
+        return CompactString::const_new("unknown");
+    };
+    let is_trusted = req
+        .app_data::<web::Data<TrustedProxies>>()
+        .is_some_and(|proxies| proxies.0.contains(&peer_addr.ip()));
+
+    if is_trusted && let Some(addr) = req.connection_info().realip_remote_addr() {
+        return addr.into();
+    }
+
+    peer_addr.ip().to_compact_string()
+}
+
+/// The address of the client that sent the request, for logging. See [`TrustedProxies`].
+pub struct ClientAddr(pub CompactString);
+
+impl FromRequest for ClientAddr {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self(client_addr(req))))
+    }
+}
+
+/// The KOReader progress sync server, as seen by the user who sent the request, who only
+/// sees the progress they synced as the user of the same name. It's only extracted if the
+/// server is enabled.
+pub struct UserKosync {
+    kosync: web::Data<Kosync>,
+    user: Option<CompactString>,
+}
+
+impl UserKosync {
+    pub async fn find_latest_progress(
+        &self,
+        files: Vec<PathBuf>,
+    ) -> crate::Result<Option<Progress>> {
+        self.kosync
+            .find_latest_progress(self.user.as_deref(), files)
+            .await
+    }
+}
+
+impl FromRequest for UserKosync {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(kosync) = req.app_data::<web::Data<Kosync>>() else {
+            return ready(Err(actix_web::error::ErrorNotFound(
+                "the KOReader progress sync server is disabled",
+            )));
+        };
+        let user = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.0.clone());
+
+        ready(Ok(Self {
+            kosync: kosync.clone(),
+            user,
+        }))
+    }
+}
+
+/// Returns the username and the password of the `Authorization` header, if it uses the
+/// Basic scheme.
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.split_once(' ')?;
+
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let credentials = String::from_utf8(BASE64.decode(credentials.trim()).ok()?).ok()?;
+    let (name, password) = credentials.split_once(':')?;
+
+    Some((name.to_owned(), password.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, http::StatusCode, middleware::from_fn, test};
+
+    use super::*;
+    use crate::users::{self, LoginLinks};
+
+    /// Reads the user "alice", whose password is "secret", from a users file named after
+    /// the test.
+    fn users(test_name: &str) -> web::Data<Users> {
+        let path = std::env::temp_dir().join(format!(
+            "seshat-auth-{}-{test_name}.txt",
+            std::process::id()
+        ));
+        let hash = users::hash_password("secret").unwrap();
+        std::fs::write(&path, format!("alice:{hash}\n")).unwrap();
+        let users = Users::open(&path, LoginLinks::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        web::Data::new(users)
+    }
+
+    fn basic(credentials: &str) -> (header::HeaderName, String) {
+        (
+            header::AUTHORIZATION,
+            format!("Basic {}", BASE64.encode(credentials)),
+        )
+    }
+
+    #[actix_web::test]
+    async fn requires_credentials() {
+        let app = test::init_service(
+            App::new()
+                .app_data(users("credentials"))
+                .wrap(from_fn(authenticate))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for req in [
+            test::TestRequest::get().uri("/opds"),
+            test::TestRequest::get()
+                .uri("/opds")
+                .insert_header(basic("alice:wrong")),
+            test::TestRequest::get()
+                .uri("/opds")
+                .insert_header(basic("bob:secret")),
+            test::TestRequest::get()
+                .uri("/opds")
+                .insert_header((header::AUTHORIZATION, "Bearer secret")),
+        ] {
+            let res = test::call_service(&app, req.to_request()).await;
+
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                res.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                r#"Basic realm="seshat", charset="UTF-8""#
+            );
+            assert!(
+                res.headers()
+                    .get(header::LINK)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .starts_with(&format!("<{}>", authentication::ROUTE))
+            );
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/opds")
+            .insert_header(basic("alice:secret"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn verifies_signed_links() {
+        let signer = LinkSigner::new(std::time::Duration::from_secs(60)).unwrap();
+        let link = signer.sign("/opds/Books/books/1/thumbnail");
+        let app = test::init_service(
+            App::new()
+                .app_data(users("signed-links"))
+                .app_data(web::Data::new(signer))
+                .wrap(from_fn(authenticate))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri(&link).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // The signature is only valid for its path, and can't be tampered with.
+        for uri in [
+            link.replace("/books/1/", "/books/2/"),
+            link.replace("signature=", "signature=0"),
+            link.replace("expires=", "expires=1"),
+        ] {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::FORBIDDEN
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn redacts_credentials_from_links() {
+        for (link, expected) in [
+            ("/opds/Books", "/opds/Books"),
+            ("/device/abcdefghijklmnop0123", "/device/REDACTED"),
+            (
+                "/device/abcdefghijklmnop0123/opds/Books?page=2",
+                "/device/REDACTED/opds/Books?page=2",
+            ),
+            (
+                "/kobo/mykobo/v1/library/sync",
+                "/kobo/REDACTED/v1/library/sync",
+            ),
+            (
+                "/lib-content/Books/1/file?expires=1&signature=abc",
+                "/lib-content/Books/1/file?expires=1&signature=REDACTED",
+            ),
+            (
+                "https://example.com/device/abcdefghijklmnop0123/ui",
+                "https://example.com/device/REDACTED/ui",
+            ),
+            ("https://example.com", "https://example.com"),
+            (
+                "/devices/abcdefghijklmnop0123",
+                "/devices/abcdefghijklmnop0123",
+            ),
+        ] {
+            assert_eq!(redacted(link), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn exempts_routes_with_their_own_authentication() {
+        let app = test::init_service(
+            App::new()
+                .app_data(users("exempt"))
+                .wrap(from_fn(authenticate))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for uri in [
+            "/kosync/users/auth",
+            "/kobo/token/v1/initialization",
+            authentication::ROUTE,
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+
+        // A prefix of an exempt route isn't exempt.
+        let req = test::TestRequest::get().uri("/kosyncx").to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,896 @@
+//! An emulation of the Kobo store's sync API, which lets Kobo devices sync the books of a
+//! library as if they were bought from the store. Devices are pointed to it by setting
+//! `api_endpoint` in the `[OneStoreServices]` section of their
+//! `.kobo/Kobo/Kobo eReader.conf` file to `http://HOST:PORT/kobo/TOKEN`.
+//!
+//! Only the endpoints that are needed to sync books and their reading states are
+//! implemented. The rest respond with an empty object, which devices accept.
+//!
+//! Devices download books long after they're synced, and they can't log in, so the books
+//! are served under the device's token too, rather than by the lib-content routes.
+
+use actix_files::NamedFile;
+use actix_web::{
+    HttpRequest, HttpResponse, Responder, delete, get, http::header::ContentType, post, put, web,
+};
+use compact_str::{CompactString, format_compact};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use time::{OffsetDateTime, UtcOffset};
+
+use super::{auth::ClientAddr, lib_content};
+use crate::{
+    epubs::Epubs,
+    errors::AppError,
+    file_names::FileNameTemplate,
+    kobo::{Kobo, KoboDevice, SyncChange},
+    library::{BookFilter, FullBook, Libraries, Library},
+    utils::{hash_str, language_tag},
+};
+
+pub const COMMON_ROUTE: &str = "/kobo";
+
+/// The number of changes that are sent to a device at once.
+const SYNC_LIMIT: usize = 50;
+
+/// The sync token we give to devices. Its content doesn't matter, as the synced books are
+/// tracked by us, but devices only ask for a full sync when they don't have one.
+const SYNC_TOKEN: &str = "seshat";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(auth_device)
+        .service(initialization)
+        .service(library_sync)
+        .service(book_metadata)
+        .service(reading_state)
+        .service(update_reading_state)
+        .service(delete_book)
+        .service(download_book)
+        .service(cover_image)
+        .service(cover_image_with_quality)
+        .default_service(web::to(|| async {
+            HttpResponse::Ok()
+                .insert_header(ContentType::json())
+                .body("{}")
+        }));
+}
+
+/// Finds the device with the token and its library.
+fn device_lib<'a>(
+    kobo: &'a Kobo,
+    libraries: &'a Libraries,
+    token: &str,
+) -> crate::Result<(&'a KoboDevice, &'a Library)> {
+    let Some(device) = kobo.device(token) else {
+        return Err(AppError::KoboDeviceNotFound);
+    };
+    let Some(lib) = libraries.get(&device.lib_name, None) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    Ok((device, lib))
+}
+
+/// Returns the scheme and host the request was made to, which absolute URLs are built
+/// with, since devices don't resolve relative ones.
+fn origin(req: &HttpRequest) -> CompactString {
+    let conn = req.connection_info();
+    format_compact!("{}://{}", conn.scheme(), conn.host())
+}
+
+/// Returns the absolute URL of the routes of the device with the token.
+fn device_root(req: &HttpRequest, token: &str) -> CompactString {
+    format_compact!("{}{COMMON_ROUTE}/{token}", origin(req))
+}
+
+/// Formats the timestamp the way the store does, e.g. `2025-01-31T12:00:00Z`.
+fn timestamp(datetime: OffsetDateTime) -> CompactString {
+    let datetime = datetime.to_offset(UtcOffset::UTC);
+
+    format_compact!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day(),
+        datetime.hour(),
+        datetime.minute(),
+        datetime.second()
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AuthDeviceBody {
+    user_key: Option<String>,
+}
+
+#[post("/{token}/v1/auth/device")]
+async fn auth_device(
+    kobo: web::Data<Kobo>,
+    token: web::Path<String>,
+    body: web::Json<AuthDeviceBody>,
+) -> crate::Result<impl Responder> {
+    if kobo.device(&token).is_none() {
+        return Err(AppError::KoboDeviceNotFound);
+    }
+
+    // Devices are authenticated by their token, so the access tokens are never checked.
+    let access_token = hash_str(&format!(
+        "{token}\0{}",
+        OffsetDateTime::now_utc().unix_timestamp_nanos()
+    ));
+
+    Ok(HttpResponse::Ok().json(json!({
+        "AccessToken": access_token,
+        "RefreshToken": access_token,
+        "TokenType": "Bearer",
+        "TrackingId": token.as_str(),
+        "UserKey": body.user_key.as_deref().unwrap_or_default(),
+    })))
+}
+
+#[get("/{token}/v1/initialization")]
+async fn initialization(
+    kobo: web::Data<Kobo>,
+    req: HttpRequest,
+    token: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    if kobo.device(&token).is_none() {
+        return Err(AppError::KoboDeviceNotFound);
+    }
+
+    let device_root = device_root(&req, &token);
+
+    Ok(HttpResponse::Ok()
+        // An empty JSON object, encoded in base64.
+        .insert_header(("x-kobo-apitoken", "e30="))
+        .json(json!({
+            "Resources": {
+                "image_host": origin(&req),
+                "image_url_template":
+                    format!("{device_root}/{{ImageId}}/{{Width}}/{{Height}}/false/image.jpg"),
+                "image_url_quality_template": format!(
+                    "{device_root}/{{ImageId}}/{{Width}}/{{Height}}/{{Quality}}/{{IsGreyscale}}/image.jpg"
+                ),
+                "library_sync": format!("{device_root}/v1/library/sync"),
+            }
+        })))
+}
+
+/// Sends the changes to the device's books since its last sync. The device keeps asking
+/// for more while the `x-kobo-sync` header is `continue`.
+#[get("/{token}/v1/library/sync")]
+async fn library_sync(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    req: HttpRequest,
+    token: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let (device, lib) = device_lib(&kobo, &libraries, &token)?;
+    let full = !req.headers().contains_key("x-kobo-synctoken");
+
+    let versions = lib.fetch_book_versions(&device.filter).await?;
+    let (changes, has_more) = kobo.sync(&token, versions, full, SYNC_LIMIT).await?;
+
+    if !changes.is_empty() {
+        debug!(
+            "Syncing {} changes to the Kobo device #{}",
+            changes.len(),
+            device.number
+        );
+    }
+
+    let device_root = device_root(&req, &token);
+    let mut items = Vec::with_capacity(changes.len());
+
+    for change in changes {
+        let (kind, book) = match change {
+            SyncChange::New(id) => ("NewEntitlement", id),
+            SyncChange::Changed(id) => ("ChangedEntitlement", id),
+            SyncChange::Removed(uuid) => {
+                items.push(json!({
+                    "ChangedEntitlement": {
+                        "BookEntitlement": removed_entitlement(&uuid),
+                    }
+                }));
+                continue;
+            }
+        };
+
+        // The book may have been deleted since its version was fetched.
+        let Some(book) = lib.find_book(book).await? else {
+            continue;
+        };
+        let Some(uuid) = &book.uuid else {
+            continue;
+        };
+        let state = kobo.find_reading_state(&token, uuid).await?;
+
+        items.push(json!({
+            kind: {
+                "BookEntitlement": book_entitlement(&book, uuid),
+                "BookMetadata": book_metadata_of(&device_root, &book, uuid),
+                "ReadingState": merge_reading_state(&book, uuid, state),
+            }
+        }));
+    }
+
+    let mut res = HttpResponse::Ok();
+    res.insert_header(("x-kobo-synctoken", SYNC_TOKEN));
+
+    if has_more {
+        res.insert_header(("x-kobo-sync", "continue"));
+    }
+
+    Ok(res.json(items))
+}
+
+#[get("/{token}/v1/library/{uuid}/metadata")]
+async fn book_metadata(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> crate::Result<impl Responder> {
+    let (token, uuid) = path.into_inner();
+    let (device, lib) = device_lib(&kobo, &libraries, &token)?;
+    let book = find_book_by_uuid(device, lib, &uuid).await?;
+
+    let device_root = device_root(&req, &token);
+
+    Ok(HttpResponse::Ok().json([book_metadata_of(&device_root, &book, &uuid)]))
+}
+
+#[get("/{token}/v1/library/{uuid}/state")]
+async fn reading_state(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    path: web::Path<(String, String)>,
+) -> crate::Result<impl Responder> {
+    let (token, uuid) = path.into_inner();
+    let (device, lib) = device_lib(&kobo, &libraries, &token)?;
+    let book = find_book_by_uuid(device, lib, &uuid).await?;
+    let state = kobo.find_reading_state(&token, &uuid).await?;
+
+    Ok(HttpResponse::Ok().json([merge_reading_state(&book, &uuid, state)]))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct UpdateReadingStateBody {
+    reading_states: Vec<Map<String, Value>>,
+}
+
+#[put("/{token}/v1/library/{uuid}/state")]
+async fn update_reading_state(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    path: web::Path<(String, String)>,
+    body: web::Json<UpdateReadingStateBody>,
+) -> crate::Result<impl Responder> {
+    let (token, uuid) = path.into_inner();
+    let (device, lib) = device_lib(&kobo, &libraries, &token)?;
+    find_synced_book_id(device, lib, &uuid).await?;
+
+    let now = Value::from(timestamp(OffsetDateTime::now_utc()).as_str());
+    let mut results = vec![];
+
+    for mut update in body.into_inner().reading_states {
+        // The entitlement is given by the path, and the timestamps are ours to set.
+        update.remove("EntitlementId");
+        update.insert("LastModified".into(), now.clone());
+        update.insert("PriorityTimestamp".into(), now.clone());
+
+        let mut result = Map::new();
+        result.insert("EntitlementId".into(), uuid.as_str().into());
+
+        for (key, result_key) in [
+            ("CurrentBookmark", "CurrentBookmarkResult"),
+            ("Statistics", "StatisticsResult"),
+            ("StatusInfo", "StatusInfoResult"),
+        ] {
+            if let Some(Value::Object(value)) = update.get_mut(key) {
+                value.insert("LastModified".into(), now.clone());
+                result.insert(result_key.into(), json!({ "Result": "Success" }));
+            }
+        }
+
+        kobo.update_reading_state(&token, &uuid, update).await?;
+        results.push(Value::Object(result));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "RequestResult": "Success",
+        "UpdateResults": results,
+    })))
+}
+
+/// Devices call this when a book is removed from them. The book stays in the library, so
+/// there's nothing to do.
+#[delete("/{token}/v1/library/{uuid}")]
+async fn delete_book(
+    kobo: web::Data<Kobo>,
+    path: web::Path<(String, String)>,
+) -> crate::Result<impl Responder> {
+    if kobo.device(&path.0).is_none() {
+        return Err(AppError::KoboDeviceNotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Serves the book's file in the given format, if the book is synced to the device.
+#[get("/{token}/download/{uuid}/{format}")]
+async fn download_book(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    file_name_template: web::Data<FileNameTemplate>,
+    epubs: Option<web::Data<Epubs>>,
+    client: ClientAddr,
+    path: web::Path<(String, String, String)>,
+) -> crate::Result<impl Responder> {
+    let (token, uuid, format) = path.into_inner();
+    let (device, lib) = device_lib(&kobo, &libraries, &token)?;
+    let mut book = find_book_by_uuid(device, lib, &uuid).await?;
+    let Some(data_index) = book
+        .data
+        .iter()
+        .position(|data| data.format.eq_ignore_ascii_case(&format))
+    else {
+        return Err(AppError::file_not_found());
+    };
+
+    let data = book.data.swap_remove(data_index);
+    let file_path = lib_content::resolve(lib, &book.file_path(&data), &client).await?;
+
+    lib_content::serve_book_file(
+        lib,
+        book,
+        data,
+        file_path,
+        &file_name_template,
+        epubs.as_ref().map(|epubs| epubs.get_ref()),
+    )
+    .await
+}
+
+#[get("/{token}/{uuid}/{width}/{height}/{greyscale}/image.jpg")]
+async fn cover_image(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    path: web::Path<(String, String, String, String, String)>,
+) -> crate::Result<impl Responder> {
+    let (token, uuid, ..) = path.into_inner();
+    serve_cover(&kobo, &libraries, &token, &uuid).await
+}
+
+#[get("/{token}/{uuid}/{width}/{height}/{quality}/{greyscale}/image.jpg")]
+async fn cover_image_with_quality(
+    kobo: web::Data<Kobo>,
+    libraries: web::Data<Libraries>,
+    path: web::Path<(String, String, String, String, String, String)>,
+) -> crate::Result<impl Responder> {
+    let (token, uuid, ..) = path.into_inner();
+    serve_cover(&kobo, &libraries, &token, &uuid).await
+}
+
+/// Serves the book's cover as is, since devices resize it themselves.
+async fn serve_cover(
+    kobo: &Kobo,
+    libraries: &Libraries,
+    token: &str,
+    uuid: &str,
+) -> crate::Result<NamedFile> {
+    let (device, lib) = device_lib(kobo, libraries, token)?;
+    let book_id = find_synced_book_id(device, lib, uuid).await?;
+    let Some(cover) = lib.find_cover(book_id).await? else {
+        return Err(AppError::file_not_found());
+    };
+
+    let cover_path = lib.root_path().join(cover.path.as_str()).join("cover.jpg");
+
+    Ok(tokio::task::spawn_blocking(move || NamedFile::open(cover_path)).await??)
+}
+
+/// Finds the ID of the book with the UUID, if it's synced to the device. Devices may only
+/// access the books that match their query, like the ones they sync.
+async fn find_synced_book_id(device: &KoboDevice, lib: &Library, uuid: &str) -> crate::Result<i64> {
+    let Some(book_id) = lib.find_book_id_by_uuid(uuid).await? else {
+        return Err(AppError::BookNotFound);
+    };
+
+    let synced = BookFilter::And(vec![device.filter.clone(), BookFilter::Id(book_id)]);
+
+    if lib.count_books(&synced).await? == 0 {
+        return Err(AppError::BookNotFound);
+    }
+
+    Ok(book_id)
+}
+
+/// Finds the book with the UUID, if it's synced to the device.
+async fn find_book_by_uuid(
+    device: &KoboDevice,
+    lib: &Library,
+    uuid: &str,
+) -> crate::Result<FullBook> {
+    let book_id = find_synced_book_id(device, lib, uuid).await?;
+    let Some(book) = lib.find_book(book_id).await? else {
+        return Err(AppError::BookNotFound);
+    };
+
+    Ok(book)
+}
+
+fn book_entitlement(book: &FullBook, uuid: &str) -> Value {
+    let added_at = timestamp(book.added_at.unwrap_or(book.last_modified_at));
+
+    json!({
+        "Accessibility": "Full",
+        "ActivePeriod": { "From": added_at },
+        "Created": added_at,
+        "CrossRevisionId": uuid,
+        "Id": uuid,
+        "IsHiddenFromArchive": false,
+        "IsLocked": false,
+        "IsRemoved": false,
+        "LastModified": timestamp(book.last_modified_at),
+        "OriginCategory": "Imported",
+        "RevisionId": uuid,
+        "Status": "Active",
+    })
+}
+
+fn removed_entitlement(uuid: &str) -> Value {
+    let now = timestamp(OffsetDateTime::now_utc());
+
+    json!({
+        "Accessibility": "Full",
+        "ActivePeriod": { "From": now },
+        "Created": now,
+        "CrossRevisionId": uuid,
+        "Id": uuid,
+        "IsHiddenFromArchive": false,
+        "IsLocked": false,
+        "IsRemoved": true,
+        "LastModified": now,
+        "OriginCategory": "Imported",
+        "RevisionId": uuid,
+        "Status": "Active",
+    })
+}
+
+fn book_metadata_of(device_root: &str, book: &FullBook, uuid: &str) -> Value {
+    let download_urls = book
+        .data
+        .iter()
+        .flat_map(|data| {
+            let formats: &[&str] = match data.format.as_str() {
+                "epub" => &["EPUB3", "EPUB"],
+                "kepub" => &["KEPUB"],
+                _ => &[],
+            };
+            let url = format!(
+                "{device_root}/download/{uuid}/{}",
+                data.format.to_lowercase()
+            );
+
+            formats.iter().map(move |format| {
+                json!({
+                    "Format": format,
+                    "Size": data.file_size,
+                    "Url": url,
+                    "Platform": "Generic",
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut metadata = json!({
+        "Categories": ["00000000-0000-0000-0000-000000000001"],
+        "ContributorRoles": book
+            .authors
+            .iter()
+            .map(|author| json!({ "Name": author.name }))
+            .collect::<Vec<_>>(),
+        "Contributors": book.authors.iter().map(|author| &author.name).collect::<Vec<_>>(),
+        "CoverImageId": uuid,
+        "CrossRevisionId": uuid,
+        "CurrentDisplayPrice": { "CurrencyCode": "USD", "TotalAmount": 0 },
+        "CurrentLoveDisplayPrice": { "TotalAmount": 0 },
+        "Description": book.content,
+        "DownloadUrls": download_urls,
+        "EntitlementId": uuid,
+        "ExternalIds": [],
+        "Genre": "00000000-0000-0000-0000-000000000001",
+        "IsEligibleForKoboLove": false,
+        "IsInternetArchive": false,
+        "IsPreOrder": false,
+        "IsSocialEnabled": true,
+        "Language": book
+            .languages
+            .first()
+            .map_or_else(|| CompactString::const_new("en"), |lang| language_tag(lang.clone())),
+        "PhoneticPronunciations": {},
+        "Publisher": { "Imprint": "", "Name": book.publisher },
+        "RevisionId": uuid,
+        "Title": book.title,
+        "WorkId": uuid,
+    });
+
+    if let Some(published_at) = book.published_at {
+        metadata["PublicationDate"] = timestamp(published_at).as_str().into();
+    }
+
+    if let Some(series) = &book.series {
+        metadata["Series"] = json!({
+            "Name": series.name,
+            "Number": series.index.to_string(),
+            "NumberFloat": series.index,
+            "Id": hash_str(&series.name),
+        });
+    }
+
+    metadata
+}
+
+/// Returns the reading state the device reported for the book, or the state of an unread
+/// book if it hasn't reported any.
+fn merge_reading_state(book: &FullBook, uuid: &str, state: Option<Map<String, Value>>) -> Value {
+    let last_modified_at = timestamp(book.last_modified_at);
+
+    let mut merged = json!({
+        "EntitlementId": uuid,
+        "Created": last_modified_at,
+        "LastModified": last_modified_at,
+        "PriorityTimestamp": last_modified_at,
+        "StatusInfo": {
+            "LastModified": last_modified_at,
+            "Status": "ReadyToRead",
+            "TimesStartedReading": 0,
+        },
+        "Statistics": { "LastModified": last_modified_at },
+        "CurrentBookmark": { "LastModified": last_modified_at },
+    });
+
+    if let (Value::Object(merged), Some(state)) = (&mut merged, state) {
+        merged.extend(state);
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use actix_web::{
+        App,
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        http::StatusCode,
+        test,
+    };
+    use clap::Parser as _;
+    use rusqlite::{Connection, functions::FunctionFlags};
+
+    use super::*;
+    use crate::Cli;
+
+    const TOKEN: &str = "kobo";
+    const SECOND_TREATISE: &str = "6e6e5cd4-76b5-49aa-8457-dc2fc35b1c40";
+    const SHERLOCK_HOLMES: &str = "78ee993d-3d13-4bcd-98e2-488c5a08a332";
+
+    /// A copy of the sample library's database, and a sync database, in a directory that
+    /// is removed when it's dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("seshat-kobo-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            std::fs::copy("data/Library/metadata.db", path.join("metadata.db")).unwrap();
+
+            Self(path)
+        }
+
+        /// Changes the library, as Calibre would.
+        fn edit_library(&self, sql: &str) {
+            let conn = Connection::open(self.0.join("metadata.db")).unwrap();
+            // The triggers of Calibre's tables call the functions it defines.
+            conn.create_scalar_function("title_sort", 1, FunctionFlags::SQLITE_UTF8, |ctx| {
+                ctx.get::<String>(0)
+            })
+            .unwrap();
+            conn.execute_batch(sql).unwrap();
+        }
+
+        /// Copies the files of the book from the sample library.
+        fn copy_book(&self, path: &str) {
+            let dir = self.0.join(path);
+            std::fs::create_dir_all(&dir).unwrap();
+
+            for entry in std::fs::read_dir(Path::new("data/Library").join(path)).unwrap() {
+                let entry = entry.unwrap();
+                std::fs::copy(entry.path(), dir.join(entry.file_name())).unwrap();
+            }
+        }
+
+        async fn app(
+            &self,
+        ) -> impl Service<
+            actix_http::Request,
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+        > {
+            self.app_with_device(format!("{TOKEN}:Sample")).await
+        }
+
+        async fn app_with_device(
+            &self,
+            device: String,
+        ) -> impl Service<
+            actix_http::Request,
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+        > {
+            let mut cli = Cli::parse_from([
+                "seshat".as_ref(),
+                "--lib:name".as_ref(),
+                "Sample".as_ref(),
+                "--lib:path".as_ref(),
+                self.0.as_os_str(),
+            ]);
+            let libraries = Libraries::from_cli(&mut cli).await.unwrap();
+            let devices = [device.parse().unwrap()];
+            let kobo = Kobo::open(self.0.join("kobo.db"), &devices, &libraries)
+                .await
+                .unwrap();
+
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(kobo))
+                    .app_data(web::Data::new(libraries))
+                    .app_data(web::Data::new(cli.download_name))
+                    .service(web::scope(COMMON_ROUTE).configure(configure)),
+            )
+            .await
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Syncs the device, and returns the synced items and whether there are more.
+    async fn sync(
+        app: &impl Service<
+            actix_http::Request,
+            Response = ServiceResponse<impl MessageBody>,
+            Error = actix_web::Error,
+        >,
+        full: bool,
+    ) -> (Vec<Value>, bool) {
+        let mut req = test::TestRequest::get().uri(&format!("/kobo/{TOKEN}/v1/library/sync"));
+
+        if !full {
+            req = req.insert_header(("x-kobo-synctoken", SYNC_TOKEN));
+        }
+
+        let res = test::call_service(app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("x-kobo-synctoken").unwrap(), SYNC_TOKEN);
+
+        let has_more = res
+            .headers()
+            .get("x-kobo-sync")
+            .is_some_and(|value| value == "continue");
+
+        (test::read_body_json(res).await, has_more)
+    }
+
+    /// Returns the kind and the UUID of the synced items.
+    fn summary(items: &[Value]) -> Vec<(&str, &str)> {
+        items
+            .iter()
+            .map(|item| {
+                let (kind, item) = item.as_object().unwrap().iter().next().unwrap();
+                let uuid = item["BookEntitlement"]["Id"].as_str().unwrap();
+
+                match item["BookEntitlement"]["IsRemoved"].as_bool().unwrap() {
+                    true => ("Removed", uuid),
+                    false => (kind.as_str(), uuid),
+                }
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn initializes_devices() {
+        let dir = TestDir::new("initialization");
+        let app = dir.app().await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/kobo/{TOKEN}/v1/initialization"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key("x-kobo-apitoken"));
+
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(
+            body["Resources"]["library_sync"],
+            format!("http://localhost:8080/kobo/{TOKEN}/v1/library/sync")
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/kobo/unknown/v1/initialization")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn syncs_new_changed_and_removed_books() {
+        let dir = TestDir::new("sync");
+        let app = dir.app().await;
+
+        let (items, has_more) = sync(&app, true).await;
+        assert_eq!(items.len(), 6);
+        assert!(
+            summary(&items)
+                .iter()
+                .all(|(kind, _)| *kind == "NewEntitlement")
+        );
+        assert!(!has_more);
+
+        let sherlock_holmes = items
+            .iter()
+            .map(|item| &item["NewEntitlement"])
+            .find(|item| item["BookEntitlement"]["Id"] == SHERLOCK_HOLMES)
+            .unwrap();
+        assert_eq!(
+            sherlock_holmes["BookMetadata"]["Title"],
+            "The Adventures of Sherlock Holmes"
+        );
+        assert_eq!(
+            sherlock_holmes["BookMetadata"]["DownloadUrls"][0]["Url"],
+            format!("http://localhost:8080/kobo/{TOKEN}/download/{SHERLOCK_HOLMES}/epub")
+        );
+        assert_eq!(
+            sherlock_holmes["ReadingState"]["StatusInfo"]["Status"],
+            "ReadyToRead"
+        );
+
+        assert_eq!(sync(&app, false).await, (vec![], false));
+
+        // A book is edited, and another loses its EPUB file, so it can't be synced.
+        dir.edit_library(&format!(
+            "UPDATE books SET last_modified = '2030-01-01 00:00:00+00:00'
+                WHERE uuid = '{SHERLOCK_HOLMES}';
+            DELETE FROM data WHERE book = (SELECT id FROM books WHERE uuid = '{SECOND_TREATISE}');"
+        ));
+
+        let (items, has_more) = sync(&app, false).await;
+        assert_eq!(
+            summary(&items),
+            [
+                ("ChangedEntitlement", SHERLOCK_HOLMES),
+                ("Removed", SECOND_TREATISE)
+            ]
+        );
+        assert!(!has_more);
+
+        assert_eq!(sync(&app, false).await, (vec![], false));
+
+        // A full sync sends every book again.
+        let (items, _) = sync(&app, true).await;
+        assert_eq!(items.len(), 5);
+    }
+
+    #[actix_web::test]
+    async fn updates_reading_states() {
+        let dir = TestDir::new("reading-state");
+        let app = dir.app().await;
+        let uri = format!("/kobo/{TOKEN}/v1/library/{SHERLOCK_HOLMES}/state");
+        let (app, uri) = (&app, uri.as_str());
+
+        let get_state = || async move {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let [state]: [Value; 1] = test::read_body_json(res).await;
+            state
+        };
+        let put_state = |state: Value| async move {
+            let req = test::TestRequest::put()
+                .uri(uri)
+                .set_json(json!({ "ReadingStates": [state] }))
+                .to_request();
+            let res = test::call_service(app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            test::read_body_json::<Value, _>(res).await
+        };
+
+        let state = get_state().await;
+        assert_eq!(state["EntitlementId"], SHERLOCK_HOLMES);
+        assert_eq!(state["StatusInfo"]["Status"], "ReadyToRead");
+
+        let res = put_state(json!({
+            "EntitlementId": "another book",
+            "CurrentBookmark": { "ProgressPercent": 42 },
+            "StatusInfo": { "Status": "Reading" },
+        }))
+        .await;
+        assert_eq!(res["RequestResult"], "Success");
+        assert_eq!(res["UpdateResults"][0]["EntitlementId"], SHERLOCK_HOLMES);
+        assert_eq!(
+            res["UpdateResults"][0]["CurrentBookmarkResult"]["Result"],
+            "Success"
+        );
+
+        // Updates are merged into the state.
+        put_state(json!({ "Statistics": { "SpentReadingMinutes": 5 } })).await;
+
+        let state = get_state().await;
+        assert_eq!(state["EntitlementId"], SHERLOCK_HOLMES);
+        assert_eq!(state["CurrentBookmark"]["ProgressPercent"], 42);
+        assert_eq!(state["StatusInfo"]["Status"], "Reading");
+        assert_eq!(state["Statistics"]["SpentReadingMinutes"], 5);
+
+        // The state is synced with the book.
+        let (items, _) = sync(app, true).await;
+        let item = items
+            .iter()
+            .map(|item| &item["NewEntitlement"])
+            .find(|item| item["BookEntitlement"]["Id"] == SHERLOCK_HOLMES)
+            .unwrap();
+        assert_eq!(item["ReadingState"]["StatusInfo"]["Status"], "Reading");
+    }
+
+    #[actix_web::test]
+    async fn only_serves_synced_books() {
+        let dir = TestDir::new("synced-books");
+        dir.copy_book("Arthur Conan Doyle/The Adventures of Sherlock Holmes (4)");
+        dir.copy_book("John Locke/Second Treatise of Government (2)");
+        let app = dir
+            .app_with_device(format!("{TOKEN}:Sample:author:Doyle"))
+            .await;
+
+        let (items, _) = sync(&app, true).await;
+        assert_eq!(summary(&items), [("NewEntitlement", SHERLOCK_HOLMES)]);
+
+        for (uuid, status) in [
+            (SHERLOCK_HOLMES, StatusCode::OK),
+            (SECOND_TREATISE, StatusCode::NOT_FOUND),
+        ] {
+            for req in [
+                test::TestRequest::get().uri(&format!("/kobo/{TOKEN}/v1/library/{uuid}/metadata")),
+                test::TestRequest::get().uri(&format!("/kobo/{TOKEN}/v1/library/{uuid}/state")),
+                test::TestRequest::put()
+                    .uri(&format!("/kobo/{TOKEN}/v1/library/{uuid}/state"))
+                    .set_json(json!({ "ReadingStates": [{}] })),
+                test::TestRequest::get().uri(&format!("/kobo/{TOKEN}/download/{uuid}/epub")),
+                test::TestRequest::get()
+                    .uri(&format!("/kobo/{TOKEN}/{uuid}/100/100/false/image.jpg")),
+                test::TestRequest::get()
+                    .uri(&format!("/kobo/{TOKEN}/{uuid}/100/100/80/false/image.jpg")),
+            ] {
+                let req = req.to_request();
+                let uri = req.uri().to_string();
+                assert_eq!(
+                    test::call_service(&app, req).await.status(),
+                    status,
+                    "{uri}"
+                );
+            }
+        }
+    }
+}
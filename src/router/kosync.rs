@@ -0,0 +1,326 @@
+//! The HTTP API of the KOReader progress sync server, which is compatible with the
+//! official one. Users point KOReader's "Progress sync" plugin to this scope as a custom
+//! sync server.
+//!
+//! If the catalog has users, only they may register, as the catalog shows each of them
+//! the progress of the kosync user of the same name. Registration is then disabled by
+//! default, since anyone could register the name of a user who hasn't yet.
+//!
+//! See <https://github.com/koreader/koreader-sync-server>.
+
+use actix_web::{
+    HttpRequest, HttpResponse, Responder, ResponseError, get,
+    http::{StatusCode, header::ContentType},
+    post, put, web,
+};
+use compact_str::CompactString;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::auth::ClientAddr;
+use crate::{
+    errors::AppError,
+    kosync::{Kosync, Progress},
+    users::Users,
+};
+
+pub const COMMON_ROUTE: &str = "/kosync";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_user)
+        .service(authorize)
+        .service(update_progress)
+        .service(get_progress)
+        .service(healthcheck);
+}
+
+/// The errors of the API, which are serialized with the codes KOReader expects.
+#[derive(Debug, thiserror::Error)]
+enum KosyncError {
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Username is already registered.")]
+    UserExists,
+    #[error("Invalid request")]
+    InvalidRequest,
+    #[error("Field 'document' not provided.")]
+    DocumentMissing,
+    #[error("User registration is disabled.")]
+    RegistrationDisabled,
+    #[error(transparent)]
+    App(#[from] AppError),
+}
+
+impl KosyncError {
+    fn code(&self) -> u16 {
+        match self {
+            Self::Unauthorized => 2001,
+            Self::UserExists => 2002,
+            Self::InvalidRequest => 2003,
+            Self::DocumentMissing => 2004,
+            Self::RegistrationDisabled => 2005,
+            Self::App(_) => 2000,
+        }
+    }
+}
+
+impl ResponseError for KosyncError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            // The official server responds with these codes, so KOReader expects them.
+            Self::UserExists | Self::RegistrationDisabled => StatusCode::PAYMENT_REQUIRED,
+            Self::InvalidRequest | Self::DocumentMissing => StatusCode::FORBIDDEN,
+            Self::App(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        })
+    }
+}
+
+impl From<async_sqlite::Error> for KosyncError {
+    fn from(err: async_sqlite::Error) -> Self {
+        Self::App(err.into())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: u16,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    username: Option<CompactString>,
+    /// The MD5 hash of the user's password.
+    password: Option<CompactString>,
+}
+
+#[post("/users/create")]
+async fn create_user(
+    kosync: web::Data<Kosync>,
+    users: Option<web::Data<Users>>,
+    client: ClientAddr,
+    body: web::Bytes,
+) -> Result<impl Responder, KosyncError> {
+    if !kosync.registration_enabled() {
+        return Err(KosyncError::RegistrationDisabled);
+    }
+
+    let Ok(Credentials {
+        username: Some(username),
+        password: Some(key),
+    }) = serde_json::from_slice(&body)
+    else {
+        return Err(KosyncError::InvalidRequest);
+    };
+
+    if !is_valid_field(&username) || !is_valid_field(&key) {
+        return Err(KosyncError::InvalidRequest);
+    }
+
+    if users.is_some_and(|users| !users.contains(&username)) {
+        warn!(
+            target: "seshat::security",
+            user = username.as_str(),
+            client = client.0.as_str(),
+            "Rejected the kosync registration of a user who isn't a catalog user"
+        );
+
+        return Err(KosyncError::RegistrationDisabled);
+    }
+
+    if !kosync.create_user(&username, &key).await? {
+        return Err(KosyncError::UserExists);
+    }
+
+    info!("Registered the kosync user \"{username}\"");
+
+    Ok(HttpResponse::Created().json(serde_json::json!({ "username": username })))
+}
+
+#[get("/users/auth")]
+async fn authorize(
+    kosync: web::Data<Kosync>,
+    req: HttpRequest,
+) -> Result<impl Responder, KosyncError> {
+    authenticate(&kosync, &req).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "authorized": "OK" })))
+}
+
+#[derive(Deserialize)]
+struct UpdateProgressBody {
+    document: Option<CompactString>,
+    progress: Option<CompactString>,
+    percentage: Option<f64>,
+    device: Option<CompactString>,
+    device_id: Option<CompactString>,
+}
+
+#[put("/syncs/progress")]
+async fn update_progress(
+    kosync: web::Data<Kosync>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> Result<impl Responder, KosyncError> {
+    let username = authenticate(&kosync, &req).await?;
+    let Ok(body) = serde_json::from_slice::<UpdateProgressBody>(&body) else {
+        return Err(KosyncError::InvalidRequest);
+    };
+
+    let Some(document) = body.document.filter(|document| is_valid_field(document)) else {
+        return Err(KosyncError::DocumentMissing);
+    };
+    let (Some(progress), Some(percentage), Some(device)) =
+        (body.progress, body.percentage, body.device)
+    else {
+        return Err(KosyncError::InvalidRequest);
+    };
+
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+
+    kosync
+        .update_progress(Progress {
+            document: document.clone(),
+            device_id: body.device_id.unwrap_or_default(),
+            username,
+            progress,
+            percentage,
+            device,
+            timestamp,
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "document": document,
+        "timestamp": timestamp,
+    })))
+}
+
+#[get("/syncs/progress/{document}")]
+async fn get_progress(
+    kosync: web::Data<Kosync>,
+    req: HttpRequest,
+    document: web::Path<String>,
+) -> Result<impl Responder, KosyncError> {
+    let username = authenticate(&kosync, &req).await?;
+
+    // KOReader expects an empty object if the document has no progress.
+    Ok(
+        HttpResponse::Ok().json(match kosync.find_progress(&username, &document).await? {
+            Some(progress) => serde_json::json!({
+                "document": progress.document,
+                "progress": progress.progress,
+                "percentage": progress.percentage,
+                "device": progress.device,
+                "device_id": progress.device_id,
+                "timestamp": progress.timestamp,
+            }),
+            None => serde_json::json!({}),
+        }),
+    )
+}
+
+#[get("/healthcheck")]
+async fn healthcheck() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .body(r#"{"state":"OK"}"#)
+}
+
+/// Authenticates the user with the `x-auth-user` and `x-auth-key` headers, and returns
+/// their username.
+async fn authenticate(kosync: &Kosync, req: &HttpRequest) -> Result<CompactString, KosyncError> {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| is_valid_field(value))
+    };
+
+    let (Some(username), Some(key)) = (header("x-auth-user"), header("x-auth-key")) else {
+        return Err(KosyncError::Unauthorized);
+    };
+
+    if !kosync.authenticate(username, key).await? {
+        return Err(KosyncError::Unauthorized);
+    }
+
+    Ok(username.into())
+}
+
+/// Usernames, keys and documents must not be empty, and can't contain colons.
+fn is_valid_field(value: &str) -> bool {
+    !value.is_empty() && !value.contains(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use actix_web::{App, test};
+
+    use super::*;
+    use crate::users::{self, LoginLinks};
+
+    /// A directory with a sync database, which is removed when it's dropped.
+    struct TestDir(PathBuf);
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn only_registers_catalog_users() {
+        let dir = TestDir(
+            std::env::temp_dir().join(format!("seshat-kosync-{}-router", std::process::id())),
+        );
+        std::fs::create_dir_all(&dir.0).unwrap();
+
+        let users_path = dir.0.join("users.txt");
+        let hash = users::hash_password("secret").unwrap();
+        std::fs::write(&users_path, format!("alice:{hash}\n")).unwrap();
+        let users = Users::open(&users_path, LoginLinks::default()).unwrap();
+        let kosync = Kosync::open(dir.0.join("kosync.db"), true, false)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(users))
+                .app_data(web::Data::new(kosync))
+                .service(web::scope(COMMON_ROUTE).configure(configure)),
+        )
+        .await;
+
+        for (username, status) in [
+            ("bob", StatusCode::PAYMENT_REQUIRED),
+            ("alice", StatusCode::CREATED),
+            ("alice", StatusCode::PAYMENT_REQUIRED),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/kosync/users/create")
+                .set_json(serde_json::json!({ "username": username, "password": "key" }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+
+        for (key, status) in [("key", StatusCode::OK), ("kex", StatusCode::UNAUTHORIZED)] {
+            let req = test::TestRequest::get()
+                .uri("/kosync/users/auth")
+                .insert_header(("x-auth-user", "alice"))
+                .insert_header(("x-auth-key", key))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+    }
+}
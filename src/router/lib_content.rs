@@ -1,18 +1,90 @@
 #![allow(clippy::literal_string_with_formatting_args, reason = "False positive")]
 
-use std::path::PathBuf;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use actix_files::NamedFile;
 use actix_web::{Responder, get, web};
 use serde::Deserialize;
 use tokio::fs;
 
-use crate::{errors::AppError, library::Libraries};
+use crate::{
+    epubs::Epubs,
+    errors::AppError,
+    file_names::{self, FileNameTemplate},
+    library::{Data, FullBook, Library},
+    router::auth::{ClientAddr, UserLibraries},
+};
 
 pub const COMMON_ROUTE: &str = "/lib-content";
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
-    cfg.service(file_handler);
+    cfg.service(book_cover)
+        .service(book_format)
+        .service(file_handler);
+}
+
+#[get(r"/{lib_name}/books/{book_id:\d+}/cover")]
+async fn book_cover(
+    libraries: UserLibraries,
+    client: ClientAddr,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, book_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(cover) = lib.find_cover(book_id).await? else {
+        return Err(AppError::file_not_found());
+    };
+
+    let cover_path = resolve(
+        lib,
+        &Path::new(cover.path.as_str()).join("cover.jpg"),
+        &client,
+    )
+    .await?;
+
+    Ok(tokio::task::spawn_blocking(move || NamedFile::open(cover_path)).await??)
+}
+
+#[get(r"/{lib_name}/books/{book_id:\d+}/{format}")]
+async fn book_format(
+    libraries: UserLibraries,
+    file_name_template: web::Data<FileNameTemplate>,
+    epubs: Option<web::Data<Epubs>>,
+    client: ClientAddr,
+    path: web::Path<(String, i64, String)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, book_id, format) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(mut book) = lib.find_book(book_id).await? else {
+        return Err(AppError::BookNotFound);
+    };
+    let Some(data_index) = book
+        .data
+        .iter()
+        .position(|data| data.format.eq_ignore_ascii_case(&format))
+    else {
+        return Err(AppError::file_not_found());
+    };
+
+    let data = book.data.swap_remove(data_index);
+    let file_path = resolve(lib, &book.file_path(&data), &client).await?;
+
+    serve_book_file(
+        lib,
+        book,
+        data,
+        file_path,
+        &file_name_template,
+        epubs.as_ref().map(|epubs| epubs.get_ref()),
+    )
+    .await
 }
 
 #[derive(Deserialize)]
@@ -21,33 +93,345 @@ struct FileHandlerPath {
     lib_name: String,
 }
 
+/// Serves the files of the library by their path. Only the files of the books' formats
+/// and their covers are allowed, since the rest, such as "metadata.db" and the OPF files,
+/// may contain data that isn't meant to be shared.
 #[get("/{lib_name}/{file_path:.*}")]
 async fn file_handler(
     path: web::Path<FileHandlerPath>,
-    libraries: web::Data<Libraries>,
+    libraries: UserLibraries,
+    file_name_template: web::Data<FileNameTemplate>,
+    epubs: Option<web::Data<Epubs>>,
+    client: ClientAddr,
 ) -> crate::Result<impl Responder> {
     let Some(lib) = libraries.get(&path.lib_name) else {
         return Err(AppError::LibraryNotFound);
     };
 
-    let file_path = lib.root_path().join(&path.file_path);
-    let real_path = fs::canonicalize(file_path).await?;
+    let real_path = resolve(lib, &path.file_path, &client).await?;
+    let relative_real_path = real_path
+        .strip_prefix(lib.root_path())
+        .expect("resolved paths are in the library");
 
-    let Ok(relative_real_path) = real_path.strip_prefix(lib.root_path()) else {
-        // Client attempted to access a file outside of the library's root path.
-        return Err(AppError::file_not_found());
+    if let Some((book, data)) = book_file(lib, relative_real_path).await? {
+        return serve_book_file(
+            lib,
+            book,
+            data,
+            real_path,
+            &file_name_template,
+            epubs.as_ref().map(|epubs| epubs.get_ref()),
+        )
+        .await;
+    }
+
+    if is_cover(lib, relative_real_path).await? {
+        return Ok(tokio::task::spawn_blocking(move || NamedFile::open(real_path)).await??);
+    }
+
+    Err(denied(
+        lib,
+        &path.file_path,
+        &client,
+        "not a book file or cover",
+    ))
+}
+
+/// Returns the canonical path of the file at the path, relative to the library's root.
+/// Paths that lead outside of the library, through `..` components or symlinks, are
+/// denied.
+pub(super) async fn resolve(
+    lib: &Library,
+    relative_path: &Path,
+    client: &ClientAddr,
+) -> crate::Result<PathBuf> {
+    let real_path = match fs::canonicalize(lib.root_path().join(relative_path)).await {
+        Ok(real_path) => real_path,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(AppError::file_not_found()),
+        Err(err) => return Err(err.into()),
     };
 
-    if relative_real_path.components().nth(1).is_none() {
-        // Calibre library books are organized in folders.
-        // The root path contains metadata which we don't want to share.
-        return Err(AppError::file_not_found());
+    if !real_path.starts_with(lib.root_path()) {
+        return Err(denied(lib, relative_path, client, "outside of the library"));
     }
 
-    if fs::metadata(&real_path).await?.is_dir() {
-        // Directories aren't supported.
-        return Err(AppError::file_not_found());
+    Ok(real_path)
+}
+
+/// Logs the denied attempt to access the file at the path, relative to the library's
+/// root, and returns the error that the client receives. It's indistinguishable from a
+/// missing file, so that clients can't probe for the files of the library.
+fn denied(lib: &Library, relative_path: &Path, client: &ClientAddr, reason: &str) -> AppError {
+    warn!(
+        target: "seshat::security",
+        lib_name = %lib.name(),
+        path = ?relative_path,
+        client = client.0.as_str(),
+        reason,
+        "Denied access to a library file"
+    );
+
+    AppError::file_not_found()
+}
+
+/// Serves the book's file in the given format, which is at the path, named after the
+/// template. EPUB files have the current metadata of the book embedded, if enabled.
+pub(super) async fn serve_book_file(
+    lib: &Library,
+    book: FullBook,
+    data: Data,
+    original_path: PathBuf,
+    file_name_template: &FileNameTemplate,
+    epubs: Option<&Epubs>,
+) -> crate::Result<NamedFile> {
+    let file_name = file_name_template.render(&book, &data);
+    let path = match epubs {
+        Some(epubs) if data.format == "epub" => match epubs.get(lib, book, &data).await {
+            Ok(path) => path,
+            Err(err) => {
+                warn!("Failed to embed the metadata of {original_path:?}: {err}");
+                original_path
+            }
+        },
+        _ => original_path,
+    };
+    let file = tokio::task::spawn_blocking(move || NamedFile::open(path)).await??;
+
+    Ok(file.set_content_disposition(file_names::attachment(&file_name)))
+}
+
+/// Returns the book whose file is at the path, relative to the library's root, along with
+/// the file's data. Files that aren't the file of a book in one of its formats, such as
+/// covers, are skipped.
+async fn book_file(lib: &Library, relative_path: &Path) -> crate::Result<Option<(FullBook, Data)>> {
+    let (Some(book_path), Some(file_stem), Some(ext)) = (
+        relative_path.parent().and_then(Path::to_str),
+        relative_path.file_stem().and_then(|stem| stem.to_str()),
+        relative_path.extension().and_then(|ext| ext.to_str()),
+    ) else {
+        return Ok(None);
+    };
+
+    let Some(book_id) = lib.find_book_id_by_path(book_path).await? else {
+        return Ok(None);
+    };
+    let Some(mut book) = lib.find_book(book_id).await? else {
+        return Ok(None);
+    };
+    let Some(data_index) = book
+        .data
+        .iter()
+        .position(|data| data.format.eq_ignore_ascii_case(ext) && data.file_name == file_stem)
+    else {
+        return Ok(None);
+    };
+
+    let data = book.data.swap_remove(data_index);
+
+    Ok(Some((book, data)))
+}
+
+/// Returns whether the file at the path, relative to the library's root, is the cover of
+/// a book.
+async fn is_cover(lib: &Library, relative_path: &Path) -> crate::Result<bool> {
+    let (Some(book_path), Some("cover.jpg")) = (
+        relative_path.parent().and_then(Path::to_str),
+        relative_path.file_name().and_then(|name| name.to_str()),
+    ) else {
+        return Ok(false);
+    };
+
+    let Some(book_id) = lib.find_book_id_by_path(book_path).await? else {
+        return Ok(false);
+    };
+
+    Ok(lib.find_cover(book_id).await?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use actix_web::{
+        App,
+        http::{StatusCode, header},
+        middleware::from_fn,
+        test,
+    };
+    use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+    use clap::Parser as _;
+    use tracing_subscriber::{Layer, layer::SubscriberExt as _};
+
+    use super::*;
+    use crate::{
+        Cli,
+        library::Libraries,
+        router::authenticate,
+        users::{self, LoginLinks, Users},
+    };
+
+    const SHERLOCK_HOLMES: &str = "Arthur Conan Doyle/The Adventures of Sherlock Holmes (4)";
+    const SECOND_TREATISE: &str = "John Locke/Second Treatise of Government (2)";
+    const DRACULA: &str = "Bram Stoker/Dracula (6)";
+
+    /// A copy of some of the sample library, next to a file outside of it, in a directory
+    /// that is removed when it's dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("seshat-lib-content-{}", std::process::id()));
+            let (from, to) = (Path::new("data/Library"), path.join("Library"));
+
+            for file in [
+                "metadata.db",
+                ".calnotes/notes.db",
+                &format!(
+                    "{SHERLOCK_HOLMES}/The Adventures of Sherlock Holmes - Arthur Conan Doyle.epub"
+                ),
+                &format!("{SHERLOCK_HOLMES}/cover.jpg"),
+                &format!("{SHERLOCK_HOLMES}/metadata.opf"),
+                &format!("{DRACULA}/Dracula - Bram Stoker.epub"),
+                &format!("{DRACULA}/cover.jpg"),
+            ] {
+                std::fs::create_dir_all(to.join(file).parent().unwrap()).unwrap();
+                std::fs::copy(from.join(file), to.join(file)).unwrap();
+            }
+
+            // The cover of a book is a symlink to a file outside of the library.
+            std::fs::write(path.join("secret.txt"), "A secret").unwrap();
+            std::fs::create_dir_all(to.join(SECOND_TREATISE)).unwrap();
+            std::os::unix::fs::symlink(
+                path.join("secret.txt"),
+                to.join(SECOND_TREATISE).join("cover.jpg"),
+            )
+            .unwrap();
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
     }
 
-    Ok(tokio::task::spawn_blocking(move || NamedFile::open(real_path)).await??)
+    /// Counts the security events.
+    #[derive(Clone, Default)]
+    struct SecurityEvents(Arc<AtomicUsize>);
+
+    impl<S: tracing::Subscriber> Layer<S> for SecurityEvents {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if event.metadata().target() == "seshat::security" {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn basic(name: &str) -> (header::HeaderName, String) {
+        (
+            header::AUTHORIZATION,
+            format!("Basic {}", BASE64.encode(format!("{name}:secret"))),
+        )
+    }
+
+    #[actix_web::test]
+    async fn only_serves_book_files_and_covers() {
+        let events = SecurityEvents::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(events.clone()));
+
+        let dir = TestDir::new();
+        let users_path = dir.0.join("users.txt");
+        let hash = users::hash_password("secret").unwrap();
+        std::fs::write(
+            &users_path,
+            format!("alice:{hash}\nbob:{hash}::not author:Stoker\n"),
+        )
+        .unwrap();
+        let users = Users::open(&users_path, LoginLinks::default()).unwrap();
+
+        let mut cli = Cli::parse_from([
+            "seshat".as_ref(),
+            "--lib:name".as_ref(),
+            "Sample".as_ref(),
+            "--lib:path".as_ref(),
+            dir.0.join("Library").as_os_str(),
+        ]);
+        let mut libraries = Libraries::from_cli(&mut cli).await.unwrap();
+        libraries.restrict_to_users(&users).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(users))
+                .app_data(web::Data::new(libraries))
+                .app_data(web::Data::new(cli.download_name))
+                .wrap(from_fn(authenticate))
+                .service(web::scope(COMMON_ROUTE).configure(configure)),
+        )
+        .await;
+
+        let sherlock_holmes_epub = format!(
+            "{SHERLOCK_HOLMES}/The Adventures of Sherlock Holmes - Arthur Conan Doyle.epub"
+        );
+        let dracula_epub = format!("{DRACULA}/Dracula - Bram Stoker.epub");
+
+        for (user, path, served) in [
+            ("alice", sherlock_holmes_epub.as_str(), true),
+            ("alice", &format!("{SHERLOCK_HOLMES}/cover.jpg"), true),
+            ("alice", &dracula_epub, true),
+            ("alice", &format!("{SHERLOCK_HOLMES}/metadata.opf"), false),
+            ("alice", "metadata.db", false),
+            ("alice", ".calnotes/notes.db", false),
+            ("alice", &format!("{SECOND_TREATISE}/cover.jpg"), false),
+            // The books that are hidden from the user are denied like any other file.
+            ("bob", &dracula_epub, false),
+            ("bob", &format!("{DRACULA}/cover.jpg"), false),
+            ("bob", &sherlock_holmes_epub, true),
+        ] {
+            let events_before = events.0.load(Ordering::Relaxed);
+            let req = test::TestRequest::get()
+                .uri(&format!("/lib-content/Sample/{}", path.replace(' ', "%20")))
+                .insert_header(basic(user))
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            let events = events.0.load(Ordering::Relaxed) - events_before;
+
+            match served {
+                true => {
+                    assert_eq!(res.status(), StatusCode::OK, "{user}: {path}");
+                    assert_eq!(events, 0, "{user}: {path}");
+                }
+                false => {
+                    assert_eq!(res.status(), StatusCode::NOT_FOUND, "{user}: {path}");
+                    assert_eq!(events, 1, "{user}: {path}");
+                }
+            }
+        }
+
+        // The routes by ID don't serve hidden books either.
+        for uri in [
+            "/lib-content/Sample/books/6/epub",
+            "/lib-content/Sample/books/6/cover",
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header(basic("bob"))
+                .to_request();
+            assert_eq!(
+                test::call_service(&app, req).await.status(),
+                StatusCode::NOT_FOUND,
+                "{uri}"
+            );
+        }
+    }
 }
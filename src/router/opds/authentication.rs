@@ -0,0 +1,92 @@
+//! The OPDS Authentication document, which tells clients how users log in, so that they
+//! can show a proper login screen instead of a bare prompt.
+//!
+//! See <https://drafts.opds.io/authentication-for-opds-1.0>.
+
+use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
+use const_format::concatcp;
+use serde::Serialize;
+
+use super::{COMMON_ROUTE, FEED_TITLE};
+use crate::{errors::AppError, users::Users};
+
+pub const MEDIA_TYPE: &str = "application/opds-authentication+json";
+
+/// The route of the document, which is exempt from authentication.
+pub const ROUTE: &str = concatcp!(COMMON_ROUTE, "/authentication");
+
+#[derive(Debug, Serialize)]
+struct AuthenticationDocument<'a> {
+    id: String,
+    title: &'static str,
+    description: &'static str,
+    links: Vec<Link<'a>>,
+    authentication: Vec<Authentication>,
+}
+
+#[derive(Debug, Serialize)]
+struct Link<'a> {
+    rel: &'static str,
+    href: &'a str,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Authentication {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    labels: Labels,
+}
+
+#[derive(Debug, Serialize)]
+struct Labels {
+    login: &'static str,
+    password: &'static str,
+}
+
+#[get("/authentication")]
+pub(super) async fn authentication_document(
+    req: HttpRequest,
+    users: Option<web::Data<Users>>,
+) -> crate::Result<impl Responder> {
+    let Some(users) = users else {
+        return Err(AppError::file_not_found());
+    };
+
+    let conn = req.connection_info();
+    let login_links = users.login_links();
+    let mut links = vec![];
+
+    if let Some(logo) = &login_links.logo {
+        links.push(Link {
+            rel: "logo",
+            href: logo,
+            kind: mime_guess::from_path(logo).first_raw(),
+        });
+    }
+
+    if let Some(help) = &login_links.help {
+        links.push(Link {
+            rel: "help",
+            href: help,
+            kind: Some(mime::TEXT_HTML.as_ref()),
+        });
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(MEDIA_TYPE)
+        .json(AuthenticationDocument {
+            id: format!("{}://{}{ROUTE}", conn.scheme(), conn.host()),
+            title: FEED_TITLE,
+            description: "Log in with the username and the password of your account",
+            authentication: vec![Authentication {
+                kind: "http://opds-spec.org/auth/basic",
+                labels: Labels {
+                    login: "Username",
+                    password: "Password",
+                },
+            }],
+            links,
+        }))
+}
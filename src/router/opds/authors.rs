@@ -0,0 +1,193 @@
+use std::num::NonZeroUsize;
+
+use actix_web::{HttpResponse, Responder, get, web};
+use compact_str::{CompactString, format_compact};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ExploreCatalogQuery, FEED_AUTHOR, FEED_TITLE, fetch_page, links, models, page_size,
+    pagination_links,
+};
+use crate::{
+    errors::AppError,
+    library::{BookFilter, OrderBooksBy},
+    router::auth::{UserKosync, UserLibraries},
+    utils::HttpResponseBuilderExt as _,
+};
+
+#[derive(Default, Serialize, Deserialize)]
+pub(super) struct AuthorsQuery {
+    /// The initial letter of the sort names of the listed authors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub letter: Option<CompactString>,
+    pub offset: Option<usize>,
+    pub limit: Option<NonZeroUsize>,
+}
+
+#[get("/{lib_name}/authors")]
+pub(super) async fn author_list(
+    query: web::Query<AuthorsQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let AuthorsQuery {
+        letter,
+        offset,
+        limit,
+    } = query.into_inner();
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+
+    let Some(letter) = letter else {
+        let entries = lib
+            .fetch_author_initials()
+            .await?
+            .into_iter()
+            .map(|initial| {
+                models::Entry::subsection(
+                    format_compact!("{}:authors:{}", lib.acquisition_feed_id(), initial.letter),
+                    initial.letter.clone(),
+                    match initial.author_count {
+                        1 => CompactString::const_new("1 author"),
+                        n => format_compact!("{n} authors"),
+                    },
+                    lib.updated_at(),
+                    models::Link {
+                        href: links::author_list(
+                            lib,
+                            &AuthorsQuery {
+                                letter: Some(initial.letter),
+                                ..Default::default()
+                            },
+                        ),
+                        kind: models::LinkType::Navigation.as_str(),
+                        rel: None,
+                    },
+                )
+            })
+            .collect();
+
+        return HttpResponse::Ok().xml(&models::Feed {
+            id: format_compact!("{}:authors", lib.acquisition_feed_id()),
+            title: format_compact!("Authors | {lib_name} | {FEED_TITLE}"),
+            subtitle: Some(format_compact!(
+                "Exploring the authors of the \"{lib_name}\" library"
+            )),
+            updated: lib.updated_at(),
+            authors: vec![FEED_AUTHOR],
+            entries,
+            links,
+            ..Default::default()
+        });
+    };
+
+    let letter = letter.to_uppercase();
+    let offset = offset.unwrap_or(0);
+    let limit = page_size(limit);
+
+    let total = lib.count_authors(&letter).await?;
+    let (authors, has_next_page) = lib.fetch_authors(&letter, limit, offset).await?;
+    let entries = authors
+        .into_iter()
+        .map(|author| {
+            models::Entry::subsection(
+                format_compact!("{}:author-{}", lib.acquisition_feed_id(), author.id),
+                author.name,
+                super::book_count(author.book_count),
+                lib.updated_at(),
+                models::Link {
+                    href: links::author(lib.name(), author.id, &ExploreCatalogQuery::default()),
+                    kind: models::LinkType::Acquisition.as_str(),
+                    rel: None,
+                },
+            )
+        })
+        .collect();
+
+    links.extend(pagination_links(
+        total,
+        offset,
+        limit,
+        has_next_page,
+        |offset| {
+            links::author_list(
+                lib,
+                &AuthorsQuery {
+                    letter: Some(letter.clone()),
+                    offset: Some(offset),
+                    limit: Some(limit),
+                },
+            )
+        },
+    ));
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: format_compact!("{}:authors:{letter}", lib.acquisition_feed_id()),
+        title: format_compact!("Authors: {letter} | {lib_name} | {FEED_TITLE}"),
+        subtitle: Some(format_compact!(
+            "Exploring the authors of the \"{lib_name}\" library starting with \"{letter}\""
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries,
+        links,
+        ..Default::default()
+    })
+}
+
+#[get("/{lib_name}/authors/{author_id}")]
+pub(super) async fn author_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, author_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(author) = lib.find_author(author_id).await? else {
+        return Err(AppError::AuthorNotFound);
+    };
+
+    let page = fetch_page(
+        lib,
+        kosync.as_ref(),
+        &query,
+        &BookFilter::Author(author.id),
+        OrderBooksBy::Title,
+        |query| links::author(lib.name(), author.id, query),
+    )
+    .await?;
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+
+    if let Some(link) = author.link {
+        links.push(models::Link {
+            rel: Some(models::LinkRel::Related.as_str()),
+            kind: "text/html",
+            href: link,
+        });
+    }
+
+    links.extend(page.links);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: format_compact!("{}:author-{}", lib.acquisition_feed_id(), author.id),
+        title: format_compact!("{} | {lib_name} | {FEED_TITLE}", author.name),
+        subtitle: Some(author.note.unwrap_or_else(|| {
+            format_compact!(
+                "Exploring the books of {} in the \"{lib_name}\" library",
+                author.name
+            )
+        })),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,68 @@
+use actix_files::NamedFile;
+use actix_web::{HttpResponse, Responder, get, web};
+
+use super::{
+    XMLNS_ATOM, XMLNS_DC, XMLNS_DCTERMS, add_reading_progress, book_entry, book_files_of, models,
+};
+use crate::{
+    errors::AppError,
+    router::auth::{UserKosync, UserLibraries},
+    thumbnails::Thumbnails,
+    utils::HttpResponseBuilderExt as _,
+};
+
+/// Returns the complete entry of a book.
+///
+/// See <https://specs.opds.io/opds-1.2#512-partial-and-complete-catalog-entries>.
+#[get("/{lib_name}/books/{book_id}")]
+pub(super) async fn book(
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, book_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(book) = lib.find_book(book_id).await? else {
+        return Err(AppError::BookNotFound);
+    };
+
+    let files = book_files_of(lib.root_path(), &book);
+    let mut entry = book_entry(lib.name(), book, true);
+
+    if let Some(kosync) = &kosync {
+        add_reading_progress(kosync, &mut entry, files).await?;
+    }
+
+    HttpResponse::Ok().xml_with_root_as(
+        models::LinkType::Entry.as_str(),
+        "entry",
+        &models::EntryDocument {
+            xmlns: XMLNS_ATOM,
+            xmlns_dc: XMLNS_DC,
+            xmlns_dcterms: XMLNS_DCTERMS,
+            entry,
+        },
+    )
+}
+
+/// Returns a thumbnail of the book's cover, which is generated on demand.
+#[get("/{lib_name}/books/{book_id}/thumbnail")]
+pub(super) async fn book_thumbnail(
+    libraries: UserLibraries,
+    thumbnails: web::Data<Thumbnails>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, book_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(cover) = lib.find_cover(book_id).await? else {
+        return Err(AppError::file_not_found());
+    };
+
+    let thumbnail_path = thumbnails.get(lib, &cover).await?;
+
+    Ok(tokio::task::spawn_blocking(move || NamedFile::open(thumbnail_path)).await??)
+}
@@ -0,0 +1,249 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use compact_str::{CompactString, format_compact};
+
+use super::{
+    ExploreCatalogQuery, FEED_AUTHOR, FEED_TITLE, PageQuery, fetch_page, links, models, page_size,
+    pagination_links,
+};
+use crate::{
+    errors::AppError,
+    library::{CategoryKind, Library},
+    router::auth::{UserKosync, UserLibraries},
+    utils::{HttpResponseBuilderExt as _, language_name},
+};
+
+#[get("/{lib_name}/series")]
+pub(super) async fn series_list(
+    query: web::Query<PageQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    category_list(CategoryKind::Series, &query, &libraries, &lib_name).await
+}
+
+#[get("/{lib_name}/series/{series_id}")]
+pub(super) async fn series_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    category_books(
+        CategoryKind::Series,
+        &query,
+        &libraries,
+        kosync.as_ref(),
+        &path.0,
+        path.1,
+    )
+    .await
+}
+
+#[get("/{lib_name}/publishers")]
+pub(super) async fn publisher_list(
+    query: web::Query<PageQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    category_list(CategoryKind::Publisher, &query, &libraries, &lib_name).await
+}
+
+#[get("/{lib_name}/publishers/{publisher_id}")]
+pub(super) async fn publisher_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    category_books(
+        CategoryKind::Publisher,
+        &query,
+        &libraries,
+        kosync.as_ref(),
+        &path.0,
+        path.1,
+    )
+    .await
+}
+
+#[get("/{lib_name}/languages")]
+pub(super) async fn language_list(
+    query: web::Query<PageQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    category_list(CategoryKind::Language, &query, &libraries, &lib_name).await
+}
+
+#[get("/{lib_name}/languages/{language_id}")]
+pub(super) async fn language_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    category_books(
+        CategoryKind::Language,
+        &query,
+        &libraries,
+        kosync.as_ref(),
+        &path.0,
+        path.1,
+    )
+    .await
+}
+
+/// Returns the title of the catalog section that lists the categories.
+fn section_title(kind: CategoryKind) -> &'static str {
+    match kind {
+        CategoryKind::Series => "Series",
+        CategoryKind::Publisher => "Publishers",
+        CategoryKind::Language => "Languages",
+    }
+}
+
+/// Returns the name of the category, as presented to the user.
+fn display_name(kind: CategoryKind, name: CompactString) -> CompactString {
+    match kind {
+        CategoryKind::Language => language_name(&name).map_or(name, CompactString::const_new),
+        CategoryKind::Series | CategoryKind::Publisher => name,
+    }
+}
+
+fn category_feed_id(lib: &Library, kind: CategoryKind, id: i64) -> CompactString {
+    format_compact!(
+        "{}:{}-{id}",
+        lib.acquisition_feed_id(),
+        links::category_path(kind)
+    )
+}
+
+async fn category_list(
+    kind: CategoryKind,
+    query: &PageQuery,
+    libraries: &UserLibraries,
+    lib_name: &str,
+) -> crate::Result<HttpResponse> {
+    let Some(lib) = libraries.get(lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = page_size(query.limit);
+
+    let total = lib.count_categories(kind).await?;
+    let (categories, has_next_page) = lib.fetch_categories(kind, limit, offset).await?;
+    let mut entries: Vec<_> = categories
+        .into_iter()
+        .map(|category| {
+            models::Entry::subsection(
+                category_feed_id(lib, kind, category.id),
+                display_name(kind, category.name),
+                super::book_count(category.book_count),
+                lib.updated_at(),
+                models::Link {
+                    href: links::category(lib, kind, category.id, &ExploreCatalogQuery::default()),
+                    kind: models::LinkType::Acquisition.as_str(),
+                    rel: None,
+                },
+            )
+        })
+        .collect();
+
+    if let CategoryKind::Language = kind {
+        // Languages are sorted by their codes, which differ from their names.
+        entries.sort_by(|a, b| a.title.cmp(&b.title));
+    }
+
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+
+    links.extend(pagination_links(
+        total,
+        offset,
+        limit,
+        has_next_page,
+        |offset| {
+            links::categories(
+                lib,
+                kind,
+                &PageQuery {
+                    offset: Some(offset),
+                    limit: Some(limit),
+                },
+            )
+        },
+    ));
+
+    let section_title = section_title(kind);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: format_compact!(
+            "{}:{}",
+            lib.acquisition_feed_id(),
+            links::category_path(kind)
+        ),
+        title: format_compact!("{section_title} | {lib_name} | {FEED_TITLE}"),
+        subtitle: Some(format_compact!(
+            "Exploring the {} of the \"{lib_name}\" library",
+            section_title.to_lowercase()
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries,
+        links,
+        ..Default::default()
+    })
+}
+
+async fn category_books(
+    kind: CategoryKind,
+    query: &ExploreCatalogQuery,
+    libraries: &UserLibraries,
+    kosync: Option<&UserKosync>,
+    lib_name: &str,
+    category_id: i64,
+) -> crate::Result<HttpResponse> {
+    let Some(lib) = libraries.get(lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(category) = lib.find_category(kind, category_id).await? else {
+        return Err(match kind {
+            CategoryKind::Series => AppError::SeriesNotFound,
+            CategoryKind::Publisher => AppError::PublisherNotFound,
+            CategoryKind::Language => AppError::LanguageNotFound,
+        });
+    };
+
+    let page = fetch_page(
+        lib,
+        kosync,
+        query,
+        &kind.filter(category.id),
+        kind.order_books_by(),
+        |query| links::category(lib, kind, category.id, query),
+    )
+    .await?;
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+    links.extend(page.links);
+
+    let name = display_name(kind, category.name);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: category_feed_id(lib, kind, category.id),
+        title: format_compact!("{name} | {lib_name} | {FEED_TITLE}"),
+        subtitle: Some(format_compact!(
+            "Exploring the \"{name}\" {} of the \"{lib_name}\" library",
+            match kind {
+                CategoryKind::Series => "series",
+                CategoryKind::Publisher => "publisher",
+                CategoryKind::Language => "language",
+            }
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
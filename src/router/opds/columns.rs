@@ -0,0 +1,160 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use compact_str::{CompactString, format_compact};
+
+use super::{
+    ExploreCatalogQuery, FEED_AUTHOR, FEED_TITLE, PageQuery, fetch_page, links, models, page_size,
+    pagination_links, rating_stars,
+};
+use crate::{
+    errors::AppError,
+    library::{CustomColumn, CustomColumnKind, Library, OrderBooksBy},
+    router::auth::{UserKosync, UserLibraries},
+    utils::HttpResponseBuilderExt as _,
+};
+
+#[get("/{lib_name}/columns/{label}")]
+pub(super) async fn custom_column_values(
+    query: web::Query<PageQuery>,
+    libraries: UserLibraries,
+    path: web::Path<(String, String)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, label) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let column = find_browseable_column(lib, &label)?;
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = page_size(query.limit);
+
+    let total = lib.count_custom_column_values(column).await?;
+    let (values, has_next_page) = lib
+        .fetch_custom_column_values(column, limit, offset)
+        .await?;
+    let entries = values
+        .into_iter()
+        .map(|value| {
+            models::Entry::subsection(
+                column_feed_id(lib, column, Some(value.id)),
+                display_value(column, value.name),
+                super::book_count(value.book_count),
+                lib.updated_at(),
+                models::Link {
+                    href: links::custom_column_value(
+                        lib,
+                        &column.label,
+                        value.id,
+                        &ExploreCatalogQuery::default(),
+                    ),
+                    kind: models::LinkType::Acquisition.as_str(),
+                    rel: None,
+                },
+            )
+        })
+        .collect();
+
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+
+    links.extend(pagination_links(
+        total,
+        offset,
+        limit,
+        has_next_page,
+        |offset| {
+            links::custom_column(
+                lib,
+                &column.label,
+                &PageQuery {
+                    offset: Some(offset),
+                    limit: Some(limit),
+                },
+            )
+        },
+    ));
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: column_feed_id(lib, column, None),
+        title: format_compact!("{} | {lib_name} | {FEED_TITLE}", column.name),
+        subtitle: Some(format_compact!(
+            "Exploring the books of the \"{lib_name}\" library by {}",
+            column.name
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries,
+        links,
+        ..Default::default()
+    })
+}
+
+#[get("/{lib_name}/columns/{label}/{value_id}")]
+pub(super) async fn custom_column_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, label, value_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let column = find_browseable_column(lib, &label)?;
+    let Some(value) = lib.find_custom_column_value(column, value_id).await? else {
+        return Err(AppError::CustomColumnValueNotFound);
+    };
+
+    let page = fetch_page(
+        lib,
+        kosync.as_ref(),
+        &query,
+        &column.filter(value.id),
+        OrderBooksBy::Title,
+        |query| links::custom_column_value(lib, &column.label, value.id, query),
+    )
+    .await?;
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+    links.extend(page.links);
+
+    let value_name = display_value(column, value.name);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: column_feed_id(lib, column, Some(value.id)),
+        title: format_compact!("{}: {value_name} | {lib_name} | {FEED_TITLE}", column.name),
+        subtitle: Some(format_compact!(
+            "Exploring the books of the \"{lib_name}\" library whose {} is \"{value_name}\"",
+            column.name
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
+
+/// Finds a custom column that the operator allowed to be browsed.
+fn find_browseable_column<'a>(lib: &'a Library, label: &str) -> crate::Result<&'a CustomColumn> {
+    lib.find_custom_column(label)
+        .filter(|column| column.browseable)
+        .ok_or(AppError::CustomColumnNotFound)
+}
+
+/// Returns the value of the custom column, as presented to the user.
+fn display_value(column: &CustomColumn, name: CompactString) -> CompactString {
+    match column.kind {
+        CustomColumnKind::Rating => name.parse().map_or(name, rating_stars),
+        _ => name,
+    }
+}
+
+fn column_feed_id(lib: &Library, column: &CustomColumn, value_id: Option<i64>) -> CompactString {
+    match value_id {
+        Some(value_id) => format_compact!(
+            "{}:columns-{}-{value_id}",
+            lib.acquisition_feed_id(),
+            column.label
+        ),
+        None => format_compact!("{}:columns-{}", lib.acquisition_feed_id(), column.label),
+    }
+}
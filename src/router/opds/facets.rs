@@ -0,0 +1,223 @@
+use compact_str::CompactString;
+use serde::{Deserialize, Serialize};
+
+use super::models;
+use crate::{
+    library::{BookFilter, Facet, Library, OrderBooksBy},
+    utils::language_name,
+};
+
+/// The facets that narrow down an acquisition feed.
+///
+/// See <https://specs.opds.io/opds-1.2#4-facets>.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(super) struct Facets {
+    #[serde(rename = "sort", skip_serializing_if = "Option::is_none")]
+    pub order_by: Option<OrderBooksBy>,
+    /// The ISO 639 code of the language books are written in.
+    #[serde(rename = "lang", skip_serializing_if = "Option::is_none")]
+    pub language: Option<CompactString>,
+    /// The format books are available in, in lowercase.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<CompactString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover: Option<CoverFacet>,
+    /// A query in Calibre's search language that books must match, e.g. `rating:>=4`.
+    #[serde(rename = "filter", skip_serializing_if = "Option::is_none")]
+    pub query: Option<CompactString>,
+}
+
+// `Option<bool>` can't be deserialized from a flattened query string, as its values are
+// always strings.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum CoverFacet {
+    With,
+    Without,
+}
+
+impl CoverFacet {
+    /// Returns the value of [`Facet::Cover`] that corresponds to this one.
+    fn as_value(self) -> &'static str {
+        match self {
+            Self::With => "true",
+            Self::Without => "false",
+        }
+    }
+
+    fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "true" => Some(Self::With),
+            "false" => Some(Self::Without),
+            _ => None,
+        }
+    }
+}
+
+const FACETS: [Facet; 3] = [Facet::Language, Facet::Format, Facet::Cover];
+
+impl Facets {
+    /// Narrows down the filter of a feed with the query, if any. Unlike the other facets,
+    /// it's applied before the facet links are counted.
+    pub fn apply_query(&self, lib: &Library, filter: &BookFilter) -> crate::Result<BookFilter> {
+        Ok(match &self.query {
+            Some(query) => BookFilter::And(vec![filter.clone(), lib.parse_query(query)?]),
+            None => filter.clone(),
+        })
+    }
+
+    /// Narrows down the filter of a feed with the selected facets.
+    pub fn narrow(&self, filter: &BookFilter) -> BookFilter {
+        self.narrow_except(filter, None)
+    }
+
+    /// Same as [`Facets::narrow`], but ignores the selected value of `except`.
+    fn narrow_except(&self, filter: &BookFilter, except: Option<Facet>) -> BookFilter {
+        let mut filters = vec![filter.clone()];
+
+        for facet in FACETS {
+            if Some(facet) == except {
+                continue;
+            }
+
+            if let Some(value) = self.get(facet) {
+                filters.push(match facet {
+                    Facet::Language => BookFilter::LanguageCode(value.into()),
+                    Facet::Format => BookFilter::Format(value.into()),
+                    Facet::Cover => BookFilter::HasCover(value == "true"),
+                });
+            }
+        }
+
+        match filters.len() {
+            1 => filters.swap_remove(0),
+            _ => BookFilter::And(filters),
+        }
+    }
+
+    fn get(&self, facet: Facet) -> Option<&str> {
+        match facet {
+            Facet::Language => self.language.as_deref(),
+            Facet::Format => self.format.as_deref(),
+            Facet::Cover => self.cover.map(CoverFacet::as_value),
+        }
+    }
+
+    fn with(&self, facet: Facet, value: Option<CompactString>) -> Self {
+        let mut facets = self.clone();
+
+        match facet {
+            Facet::Language => facets.language = value,
+            Facet::Format => facets.format = value,
+            Facet::Cover => facets.cover = value.and_then(|v| CoverFacet::from_value(&v)),
+        }
+
+        facets
+    }
+}
+
+/// Builds the facet links of an acquisition feed. `filter` is the filter of the feed
+/// before any facet is applied, `total` is the number of books after they are applied,
+/// and `href` returns the link of the feed with the given facets.
+pub(super) async fn facet_links(
+    lib: &Library,
+    filter: &BookFilter,
+    facets: &Facets,
+    default_order_by: OrderBooksBy,
+    total: usize,
+    href: impl Fn(Facets) -> CompactString,
+) -> crate::Result<Vec<models::FacetLink>> {
+    let mut links = vec![];
+    let mut sort_options = vec![
+        (OrderBooksBy::DateAdded, "Newest"),
+        (OrderBooksBy::Title, "Title"),
+        (OrderBooksBy::Author, "Author"),
+    ];
+
+    match default_order_by {
+        OrderBooksBy::SeriesIndex => {
+            sort_options.insert(0, (OrderBooksBy::SeriesIndex, "Series Order"));
+        }
+        OrderBooksBy::Relevance => sort_options.insert(0, (OrderBooksBy::Relevance, "Relevance")),
+        _ => {}
+    }
+
+    let order_by = facets.order_by.unwrap_or(default_order_by);
+
+    for (option, title) in sort_options {
+        links.push(facet_link(
+            href(Facets {
+                order_by: Some(option),
+                ..facets.clone()
+            }),
+            title.into(),
+            "Sort By",
+            order_by == option,
+            total,
+        ));
+    }
+
+    for facet in FACETS {
+        // The counts of a group take into account the values selected in the other ones.
+        let filter = facets.narrow_except(filter, Some(facet));
+        let values = lib.count_facet_values(facet, &filter).await?;
+        let selected = facets.get(facet);
+
+        // A group with a single value would not narrow down anything.
+        if values.len() < 2 && selected.is_none() {
+            continue;
+        }
+
+        let (group, any_title) = match facet {
+            Facet::Language => ("Language", "Any Language"),
+            Facet::Format => ("Format", "Any Format"),
+            Facet::Cover => ("Cover", "With or Without Cover"),
+        };
+
+        links.push(facet_link(
+            href(facets.with(facet, None)),
+            any_title.into(),
+            group,
+            selected.is_none(),
+            lib.count_books(&filter).await?,
+        ));
+
+        for value in values {
+            let title = match facet {
+                Facet::Language => language_name(&value.value)
+                    .map_or_else(|| value.value.clone(), CompactString::const_new),
+                Facet::Format => value.value.to_uppercase(),
+                Facet::Cover if value.value == "true" => CompactString::const_new("With Cover"),
+                Facet::Cover => CompactString::const_new("Without Cover"),
+            };
+
+            links.push(facet_link(
+                href(facets.with(facet, Some(value.value.clone()))),
+                title,
+                group,
+                selected == Some(value.value.as_str()),
+                value.book_count,
+            ));
+        }
+    }
+
+    Ok(links)
+}
+
+fn facet_link(
+    href: CompactString,
+    title: CompactString,
+    group: &'static str,
+    active: bool,
+    count: usize,
+) -> models::FacetLink {
+    models::FacetLink {
+        rel: models::LinkRel::Facet.as_str(),
+        kind: models::LinkType::Acquisition.as_str(),
+        href,
+        title,
+        group,
+        active,
+        count,
+    }
+}
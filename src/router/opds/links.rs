@@ -2,13 +2,15 @@ use compact_str::{CompactString, format_compact};
 use percent_encoding::{NON_ALPHANUMERIC, percent_encode};
 
 use super::{
-    super::lib_content::COMMON_ROUTE as LIB_CONTENT_ROOT, COMMON_ROUTE as OPDS_ROOT,
-    ExploreCatalogQuery,
+    super::{auth::signed, lib_content::COMMON_ROUTE as LIB_CONTENT_ROOT},
+    COMMON_ROUTE as OPDS_ROOT, ExploreCatalogQuery, PageQuery, SearchMode, SearchQuery,
+    authors::AuthorsQuery,
+    tags::TagsQuery,
 };
-use crate::library::{Data, FullBook, Library};
+use crate::library::{CategoryKind, Data, FullBook, Library, SavedQueryKind};
 
 #[inline(always)]
-fn enc(s: &str) -> percent_encoding::PercentEncode<'_> {
+pub(super) fn enc(s: &str) -> percent_encoding::PercentEncode<'_> {
     percent_encode(s.as_bytes(), NON_ALPHANUMERIC)
 }
 
@@ -27,33 +29,195 @@ pub fn explore_lib(lib: &Library, order_by: Option<&str>) -> CompactString {
     link
 }
 
-pub fn explore_lib_with_query(query: ExploreCatalogQuery, lib: &Library) -> CompactString {
-    let mut link = format_compact!("{OPDS_ROOT}/{}/explore", enc(lib.name()));
+pub fn explore_lib_with_query(lib: &Library, query: &ExploreCatalogQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/explore", enc(lib.name())),
+        query,
+    )
+}
+
+pub fn opensearch(lib: &Library, mode: SearchMode) -> CompactString {
+    let link = format_compact!("{OPDS_ROOT}/{}/opensearch.xml", enc(lib.name()));
+
+    match mode {
+        SearchMode::Metadata => link,
+        SearchMode::Fts => link + "?mode=fts",
+    }
+}
 
-    if query.limit.is_some() || query.offset.is_some() || query.order_by.is_some() {
-        let query = serde_urlencoded::ser::to_string(&query).expect("failed to serialize query");
+/// Returns the search URL template, as described by the OpenSearch specification.
+pub fn search_template(lib: &Library, mode: SearchMode) -> CompactString {
+    let template = format_compact!("{OPDS_ROOT}/{}/search?q={{searchTerms}}", enc(lib.name()));
 
-        link.push('?');
+    match mode {
+        SearchMode::Metadata => template,
+        SearchMode::Fts => template + "&mode=fts",
+    }
+}
+
+pub fn search_lib_with_query(
+    lib: &Library,
+    search_query: &SearchQuery,
+    query: &ExploreCatalogQuery,
+) -> CompactString {
+    with_query(
+        with_query(
+            format_compact!("{OPDS_ROOT}/{}/search", enc(lib.name())),
+            search_query,
+        ),
+        query,
+    )
+}
+
+pub fn author_list(lib: &Library, query: &AuthorsQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/authors", enc(lib.name())),
+        query,
+    )
+}
+
+pub fn author(lib_name: &str, author_id: i64, query: &ExploreCatalogQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/authors/{author_id}", enc(lib_name)),
+        query,
+    )
+}
+
+/// Returns the path segment of the catalog section that lists the categories.
+pub fn category_path(kind: CategoryKind) -> &'static str {
+    match kind {
+        CategoryKind::Series => "series",
+        CategoryKind::Publisher => "publishers",
+        CategoryKind::Language => "languages",
+    }
+}
+
+pub fn categories(lib: &Library, kind: CategoryKind, query: &PageQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/{}", enc(lib.name()), category_path(kind)),
+        query,
+    )
+}
+
+pub fn category(
+    lib: &Library,
+    kind: CategoryKind,
+    category_id: i64,
+    query: &ExploreCatalogQuery,
+) -> CompactString {
+    with_query(
+        format_compact!(
+            "{OPDS_ROOT}/{}/{}/{category_id}",
+            enc(lib.name()),
+            category_path(kind)
+        ),
+        query,
+    )
+}
+
+pub fn custom_column(lib: &Library, label: &str, query: &PageQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/columns/{}", enc(lib.name()), enc(label)),
+        query,
+    )
+}
+
+pub fn custom_column_value(
+    lib: &Library,
+    label: &str,
+    value_id: i64,
+    query: &ExploreCatalogQuery,
+) -> CompactString {
+    with_query(
+        format_compact!(
+            "{OPDS_ROOT}/{}/columns/{}/{value_id}",
+            enc(lib.name()),
+            enc(label)
+        ),
+        query,
+    )
+}
+
+/// Returns the path segment of the catalog section that lists the books matching the
+/// saved queries.
+pub fn saved_query_path(kind: SavedQueryKind) -> &'static str {
+    match kind {
+        SavedQueryKind::VirtualLibrary => "virtual-libraries",
+        SavedQueryKind::SavedSearch => "saved-searches",
+    }
+}
+
+pub fn saved_query(
+    lib: &Library,
+    kind: SavedQueryKind,
+    name: &str,
+    query: &ExploreCatalogQuery,
+) -> CompactString {
+    with_query(
+        format_compact!(
+            "{OPDS_ROOT}/{}/{}/{}",
+            enc(lib.name()),
+            saved_query_path(kind),
+            enc(name)
+        ),
+        query,
+    )
+}
+
+pub fn tag_list(lib: &Library, query: &TagsQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/tags", enc(lib.name())),
+        query,
+    )
+}
+
+pub fn tag(lib: &Library, tag_id: i64, query: &ExploreCatalogQuery) -> CompactString {
+    with_query(
+        format_compact!("{OPDS_ROOT}/{}/tags/{tag_id}", enc(lib.name())),
+        query,
+    )
+}
+
+/// Appends the query string to the link, unless it's empty.
+pub(super) fn with_query<T: serde::Serialize>(mut link: CompactString, query: &T) -> CompactString {
+    let query = serde_urlencoded::ser::to_string(query).expect("failed to serialize query");
+
+    if !query.is_empty() {
+        link.push(if link.contains('?') { '&' } else { '?' });
         link.push_str(&query);
     }
 
     link
 }
 
+pub fn book(lib_name: &str, book_id: i64) -> CompactString {
+    format_compact!("{OPDS_ROOT}/{}/books/{book_id}", enc(lib_name))
+}
+
+/// Returns the link to the thumbnail of the book's cover, which is signed if signed links
+/// are enabled.
+pub fn book_thumbnail(lib_name: &str, book_id: i64) -> CompactString {
+    signed(format_compact!(
+        "{OPDS_ROOT}/{}/books/{book_id}/thumbnail",
+        enc(lib_name)
+    ))
+}
+
+/// Returns the link to the book's file, which is signed if signed links are enabled.
 pub fn download_book(lib_name: &str, book: &FullBook, data: &Data) -> CompactString {
-    format_compact!(
-        "{LIB_CONTENT_ROOT}/{lib_name}/{path}/{file_name}.{file_format}",
-        file_name = enc(&data.file_name),
-        file_format = enc(&data.format),
-        lib_name = enc(lib_name),
-        path = enc(&book.path),
-    )
+    signed(format_compact!(
+        "{LIB_CONTENT_ROOT}/{}/books/{}/{}",
+        enc(lib_name),
+        book.id,
+        enc(&data.format),
+    ))
 }
 
+/// Returns the link to the book's cover, which is signed if signed links are enabled.
 pub fn book_cover(lib_name: &str, book: &FullBook) -> CompactString {
-    format_compact!(
-        "{LIB_CONTENT_ROOT}/{lib_name}/{path}/cover.jpg",
-        lib_name = enc(lib_name),
-        path = enc(&book.path),
-    )
+    signed(format_compact!(
+        "{LIB_CONTENT_ROOT}/{}/books/{}/cover",
+        enc(lib_name),
+        book.id
+    ))
 }
@@ -1,7 +1,17 @@
+pub mod authentication;
+mod authors;
+mod books;
+mod categories;
+mod columns;
+mod facets;
 mod links;
 mod models;
+mod saved_queries;
+mod tags;
+pub mod ui;
+pub mod v2;
 
-use std::num::NonZeroUsize;
+use std::{num::NonZeroUsize, path::PathBuf};
 
 use actix_web::{HttpResponse, Responder, get, web};
 use compact_str::{CompactString, format_compact};
@@ -10,13 +20,23 @@ use time::OffsetDateTime;
 
 use crate::{
     errors::AppError,
-    library::{Libraries, Library, OrderBooksBy},
-    utils::HttpResponseBuilderExt as _,
+    kosync::Progress,
+    library::{
+        BookFilter, CategoryKind, CustomValue, FullBook, Library, OrderBooksBy, SavedQueryKind,
+    },
+    router::auth::{LinkContext, UserKosync, UserLibraries},
+    users::Users,
+    utils::{HttpResponseBuilderExt as _, language_tag},
 };
 
 pub const COMMON_ROUTE: &str = "/opds";
 
 const XMLNS_ATOM: &str = "http://www.w3.org/2005/Atom";
+const XMLNS_OPDS: &str = "http://opds-spec.org/2010/catalog";
+const XMLNS_THR: &str = "http://purl.org/syndication/thread/1.0";
+const XMLNS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const XMLNS_DCTERMS: &str = "http://purl.org/dc/terms/";
+const XMLNS_OPENSEARCH: &str = "http://a9.com/-/spec/opensearch/1.1/";
 const FEED_TITLE: &str = "Seshat – OPDS Catalog";
 const FEED_AUTHOR: models::Author = models::Author {
     uri: Some(CompactString::const_new("https://github.com/thunder04")),
@@ -25,12 +45,34 @@ const FEED_AUTHOR: models::Author = models::Author {
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(root)
+        .service(authentication::authentication_document)
         .service(library_root)
-        .service(explore_catalog);
+        .service(explore_catalog)
+        .service(opensearch_description)
+        .service(search)
+        .service(books::book)
+        .service(books::book_thumbnail)
+        .service(authors::author_list)
+        .service(authors::author_books)
+        .service(categories::series_list)
+        .service(categories::series_books)
+        .service(categories::publisher_list)
+        .service(categories::publisher_books)
+        .service(categories::language_list)
+        .service(categories::language_books)
+        .service(tags::tag_list)
+        .service(tags::tag_books)
+        .service(columns::custom_column_values)
+        .service(columns::custom_column_books)
+        .service(saved_queries::virtual_library_books)
+        .service(saved_queries::saved_search_books);
 }
 
 #[get("")]
-async fn root(libraries: web::Data<Libraries>) -> crate::Result<impl Responder> {
+async fn root(
+    libraries: UserLibraries,
+    users: Option<web::Data<Users>>,
+) -> crate::Result<impl Responder> {
     let mut updated_at = OffsetDateTime::UNIX_EPOCH;
     let entries = libraries
         .get_all()
@@ -39,40 +81,54 @@ async fn root(libraries: web::Data<Libraries>) -> crate::Result<impl Responder>
                 updated_at = lib.updated_at();
             }
 
-            models::Entry {
-                id: lib.acquisition_feed_id().into(),
-                title: lib.name().into(),
-                updated: lib.updated_at(),
-                authors: vec![],
-                categories: vec![],
-                content: Some(models::Content {
-                    value: format_compact!("Explore the \"{}\" library", lib.name()),
-                    kind: models::ContentKind::Text,
-                }),
-                links: vec![models::Link {
+            models::Entry::subsection(
+                lib.acquisition_feed_id().into(),
+                lib.name().into(),
+                format_compact!("Explore the \"{}\" library", lib.name()),
+                lib.updated_at(),
+                models::Link {
                     kind: models::LinkType::Acquisition.as_str(),
                     href: links::lib_root(lib),
                     rel: None,
-                }],
-            }
+                },
+            )
         })
         .collect();
 
     HttpResponse::Ok().xml(&models::Feed {
-        xmlns: XMLNS_ATOM,
         id: CompactString::const_new("urn:seshat:root"),
         title: CompactString::const_new(FEED_TITLE),
         subtitle: Some(CompactString::const_new("Explore available libraries")),
         updated: updated_at,
         authors: vec![FEED_AUTHOR],
-        links: vec![models::Link::start()],
+        links: vec![
+            models::Link::start(),
+            models::Link {
+                href: v2::links::root(),
+                kind: v2::MEDIA_TYPE,
+                rel: Some(models::LinkRel::Alternate.as_str()),
+            },
+            models::Link {
+                href: ui::links::root(),
+                kind: mime::TEXT_HTML.as_ref(),
+                rel: Some(models::LinkRel::Alternate.as_str()),
+            },
+        ]
+        .into_iter()
+        .chain(users.is_some().then(|| models::Link {
+            href: CompactString::const_new(authentication::ROUTE),
+            kind: authentication::MEDIA_TYPE,
+            rel: Some(models::LinkRel::AuthenticationDocument.as_str()),
+        }))
+        .collect(),
         entries,
+        ..Default::default()
     })
 }
 
 #[get("/{lib_name}")]
 async fn library_root(
-    libraries: web::Data<Libraries>,
+    libraries: UserLibraries,
     lib_name: web::Path<String>,
 ) -> crate::Result<impl Responder> {
     let Some(lib) = libraries.get(&lib_name) else {
@@ -80,151 +136,710 @@ async fn library_root(
     };
 
     HttpResponse::Ok().xml(&models::Feed {
-        xmlns: XMLNS_ATOM,
         id: lib.acquisition_feed_id().into(),
         title: format_compact!("{lib_name} | {FEED_TITLE}"),
         subtitle: Some(format_compact!("Exploring the \"{lib_name}\" library")),
         updated: lib.updated_at(),
         authors: vec![FEED_AUTHOR],
-        links: vec![models::Link::start()],
+        links: search_links(lib)
+            .into_iter()
+            .chain([
+                models::Link::start(),
+                models::Link {
+                    href: v2::links::lib_root(lib),
+                    kind: v2::MEDIA_TYPE,
+                    rel: Some(models::LinkRel::Alternate.as_str()),
+                },
+                models::Link {
+                    href: ui::links::lib_root(lib),
+                    kind: mime::TEXT_HTML.as_ref(),
+                    rel: Some(models::LinkRel::Alternate.as_str()),
+                },
+            ])
+            .collect(),
         entries: [
             models::LibraryRootEntry {
+                href: links::explore_lib(lib, None),
+                kind: models::LinkType::Acquisition,
                 description: "View books",
                 title: "View Books",
                 link_rel: None,
-                sort_by: None,
             },
             models::LibraryRootEntry {
+                href: links::explore_lib(lib, Some("date_added")),
                 link_rel: Some(models::LinkRel::SortNew),
+                kind: models::LinkType::Acquisition,
                 description: "View new books",
-                sort_by: Some("date_added"),
                 title: "View New Books",
             },
             models::LibraryRootEntry {
+                href: links::explore_lib(lib, Some("title")),
+                kind: models::LinkType::Acquisition,
                 description: "View books sorted by title",
                 title: "View Books by Title",
-                sort_by: Some("title"),
                 link_rel: None,
             },
             models::LibraryRootEntry {
+                href: links::explore_lib(lib, Some("author")),
+                kind: models::LinkType::Acquisition,
                 description: "View books sorted by author",
                 title: "View Books by Author",
-                sort_by: Some("author"),
+                link_rel: None,
+            },
+            models::LibraryRootEntry {
+                href: links::author_list(lib, &authors::AuthorsQuery::default()),
+                kind: models::LinkType::Navigation,
+                description: "View books grouped by author",
+                title: "View Authors",
+                link_rel: None,
+            },
+            models::LibraryRootEntry {
+                href: links::categories(lib, CategoryKind::Series, &PageQuery::default()),
+                kind: models::LinkType::Navigation,
+                description: "View books grouped by series",
+                title: "View Series",
+                link_rel: None,
+            },
+            models::LibraryRootEntry {
+                href: links::tag_list(lib, &tags::TagsQuery::default()),
+                kind: models::LinkType::Navigation,
+                description: "View books grouped by tag",
+                title: "View Tags",
+                link_rel: None,
+            },
+            models::LibraryRootEntry {
+                href: links::categories(lib, CategoryKind::Publisher, &PageQuery::default()),
+                kind: models::LinkType::Navigation,
+                description: "View books grouped by publisher",
+                title: "View Publishers",
+                link_rel: None,
+            },
+            models::LibraryRootEntry {
+                href: links::categories(lib, CategoryKind::Language, &PageQuery::default()),
+                kind: models::LinkType::Navigation,
+                description: "View books grouped by language",
+                title: "View Languages",
                 link_rel: None,
             },
         ]
         .into_iter()
         .map(|e| (lib, e).into())
+        .chain(
+            lib.custom_columns()
+                .iter()
+                .filter(|column| column.browseable)
+                .map(|column| {
+                    models::Entry::subsection(
+                        format_compact!("{}:columns-{}", lib.acquisition_feed_id(), column.label),
+                        format_compact!("View {}", column.name),
+                        format_compact!("View books grouped by {}", column.name),
+                        lib.updated_at(),
+                        models::Link {
+                            href: links::custom_column(lib, &column.label, &PageQuery::default()),
+                            kind: models::LinkType::Navigation.as_str(),
+                            rel: None,
+                        },
+                    )
+                }),
+        )
+        .chain(saved_queries::saved_query_entries(
+            lib,
+            SavedQueryKind::VirtualLibrary,
+        ))
+        .chain(saved_queries::saved_query_entries(
+            lib,
+            SavedQueryKind::SavedSearch,
+        ))
         .collect(),
+        ..Default::default()
     })
 }
 
-#[derive(Serialize, Deserialize)]
+/// The query of an acquisition feed.
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct ExploreCatalogQuery {
-    #[serde(rename = "sort")]
-    order_by: Option<OrderBooksBy>,
+    #[serde(flatten)]
+    facets: facets::Facets,
+    #[serde(skip_serializing_if = "Option::is_none")]
     offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     limit: Option<NonZeroUsize>,
 }
 
 #[get("/{lib_name}/explore")]
 async fn explore_catalog(
     query: web::Query<ExploreCatalogQuery>,
-    libraries: web::Data<Libraries>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
     lib_name: web::Path<String>,
 ) -> crate::Result<impl Responder> {
     let Some(lib) = libraries.get(&lib_name) else {
         return Err(AppError::LibraryNotFound);
     };
-    let lib_len = lib.len().await?;
 
-    let order_by = query.order_by.unwrap_or(OrderBooksBy::DateAdded);
-    let offset = query.offset.unwrap_or(0);
-    let limit = query
-        .limit
+    let page = fetch_page(
+        lib,
+        kosync.as_ref(),
+        &query,
+        &BookFilter::All,
+        OrderBooksBy::DateAdded,
+        |query| links::explore_lib_with_query(lib, query),
+    )
+    .await?;
+    let mut links = search_links(lib);
+    links.extend([
+        models::Link::start(),
+        models::Link {
+            href: v2::links::explore_lib(lib, &query),
+            kind: v2::MEDIA_TYPE,
+            rel: Some(models::LinkRel::Alternate.as_str()),
+        },
+    ]);
+    links.extend(page.links);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: lib.acquisition_feed_id().into(),
+        title: format_compact!("{lib_name} | {FEED_TITLE}"),
+        subtitle: Some(format_compact!("Exploring the \"{lib_name}\" library")),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PageQuery {
+    offset: Option<usize>,
+    limit: Option<NonZeroUsize>,
+}
+
+#[derive(Deserialize)]
+struct OpenSearchQuery {
+    #[serde(default)]
+    mode: SearchMode,
+}
+
+#[get("/{lib_name}/opensearch.xml")]
+async fn opensearch_description(
+    query: web::Query<OpenSearchQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let (short_name, description) = match query.mode {
+        SearchMode::Metadata => (
+            lib.name().into(),
+            format_compact!("Search the \"{lib_name}\" library with Calibre's search syntax"),
+        ),
+        SearchMode::Fts if lib.has_full_text_search() => (
+            format_compact!("{lib_name} (Full Text)"),
+            format_compact!("Search the text of the books of the \"{lib_name}\" library"),
+        ),
+        SearchMode::Fts => return Err(AppError::FullTextSearchUnavailable),
+    };
+
+    HttpResponse::Ok().xml_as(
+        models::LinkType::Search.as_str(),
+        &models::OpenSearchDescription {
+            xmlns: XMLNS_OPENSEARCH,
+            input_encoding: "UTF-8",
+            output_encoding: "UTF-8",
+            urls: vec![models::OpenSearchUrl {
+                kind: models::LinkType::Acquisition.as_str(),
+                template: links::search_template(lib, query.mode),
+            }],
+            short_name,
+            description,
+        },
+    )
+}
+
+/// What the books are searched by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SearchMode {
+    /// The title, authors, tags and series of the books.
+    #[default]
+    Metadata,
+    /// The text of the books, which is indexed by Calibre in `full-text-search.db`.
+    Fts,
+}
+
+impl SearchMode {
+    fn is_metadata(&self) -> bool {
+        *self == Self::Metadata
+    }
+
+    /// Returns the order in which the results are listed by default.
+    fn order_books_by(&self) -> OrderBooksBy {
+        match self {
+            Self::Metadata => OrderBooksBy::Title,
+            Self::Fts => OrderBooksBy::Relevance,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SearchQuery {
+    q: CompactString,
+    #[serde(default, skip_serializing_if = "SearchMode::is_metadata")]
+    mode: SearchMode,
+}
+
+#[get("/{lib_name}/search")]
+async fn search(
+    search_query: web::Query<SearchQuery>,
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let q = &search_query.q;
+    let filter = match search_query.mode {
+        SearchMode::Metadata => lib.parse_query(q)?,
+        SearchMode::Fts => match lib.full_text_search(q).await? {
+            Some(book_ids) => BookFilter::Ids(book_ids),
+            None => return Err(AppError::FullTextSearchUnavailable),
+        },
+    };
+    let mut page = fetch_page(
+        lib,
+        kosync.as_ref(),
+        &query,
+        &filter,
+        search_query.mode.order_books_by(),
+        |query| links::search_lib_with_query(lib, &search_query, query),
+    )
+    .await?;
+
+    if search_query.mode == SearchMode::Fts {
+        let snippets = lib.fetch_full_text_snippets(q, &page.book_ids).await?;
+
+        for (entry, book_id) in page.entries.iter_mut().zip(&page.book_ids) {
+            if let Some(snippet) = snippets.get(book_id) {
+                prepend_content(entry, &highlight_snippet(snippet));
+            }
+        }
+    }
+
+    let mut links = search_links(lib);
+    links.push(models::Link::start());
+    links.extend(page.links);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: format_compact!("{}:search", lib.acquisition_feed_id()),
+        title: format_compact!("Search: {q} | {lib_name} | {FEED_TITLE}"),
+        subtitle: Some(format_compact!(
+            "Found {} books matching \"{q}\" in the \"{lib_name}\" library",
+            page.total
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
+
+/// Returns the links to the OpenSearch descriptions of the library. The text of the books
+/// can only be searched if Calibre has indexed it.
+fn search_links(lib: &Library) -> Vec<models::Link> {
+    let mut links = vec![models::Link::search(lib)];
+
+    if lib.has_full_text_search() {
+        links.push(models::Link::full_text_search(lib));
+    }
+
+    links
+}
+
+/// Converts an excerpt of a book's text into HTML, emphasizing the matched terms.
+fn highlight_snippet(snippet: &str) -> CompactString {
+    let escaped = quick_xml::escape::escape(snippet)
+        .replace('\x01', "<b>")
+        .replace('\x02', "</b>");
+
+    format_compact!("<blockquote>{escaped}</blockquote>")
+}
+
+/// Clamps the requested page size to the limits supported by the library.
+fn page_size(limit: Option<NonZeroUsize>) -> NonZeroUsize {
+    limit
         .unwrap_or(Library::DEFAULT_PAGE_SIZE)
-        .clamp(Library::MIN_PAGE_SIZE, Library::MAX_PAGE_SIZE);
+        .clamp(Library::MIN_PAGE_SIZE, Library::MAX_PAGE_SIZE)
+}
+
+/// Describes the number of books of a catalog section.
+fn book_count(count: usize) -> CompactString {
+    match count {
+        1 => CompactString::const_new("1 book"),
+        n => format_compact!("{n} books"),
+    }
+}
+
+/// A page of an acquisition feed.
+struct Page {
+    entries: Vec<models::Entry>,
+    /// The IDs of the books of the entries, in the same order.
+    book_ids: Vec<i64>,
+    /// The pagination links.
+    links: Vec<models::Link>,
+    facets: Vec<models::FacetLink>,
+    /// The number of books across all pages.
+    total: usize,
+}
+
+/// Fetches the page of an acquisition feed that lists the books matching the filter,
+/// narrowed down by the facets of the query. `href` returns the link of the feed with
+/// the given query.
+async fn fetch_page(
+    lib: &Library,
+    kosync: Option<&UserKosync>,
+    query: &ExploreCatalogQuery,
+    filter: &BookFilter,
+    default_order_by: OrderBooksBy,
+    href: impl Fn(&ExploreCatalogQuery) -> CompactString,
+) -> crate::Result<Page> {
+    let order_by = query.facets.order_by.unwrap_or(default_order_by);
+    let offset = query.offset.unwrap_or(0);
+    let limit = page_size(query.limit);
+
+    let filter = &query.facets.apply_query(lib, filter)?;
+    let narrowed_filter = query.facets.narrow(filter);
+    let total = lib.count_books(&narrowed_filter).await?;
+    let (entries, book_ids, has_next_page) =
+        fetch_entries(lib, kosync, limit, offset, order_by, &narrowed_filter).await?;
+
+    let links = pagination_links(total, offset, limit, has_next_page, |offset| {
+        href(&ExploreCatalogQuery {
+            facets: query.facets.clone(),
+            offset: Some(offset),
+            limit: Some(limit),
+        })
+    });
+    let facets = facets::facet_links(
+        lib,
+        filter,
+        &query.facets,
+        default_order_by,
+        total,
+        |facets| {
+            href(&ExploreCatalogQuery {
+                limit: query.limit,
+                offset: None,
+                facets,
+            })
+        },
+    )
+    .await?;
+
+    Ok(Page {
+        entries,
+        book_ids,
+        links,
+        facets,
+        total,
+    })
+}
 
-    let ((entries, lib_name), has_next_page) = lib
+/// Fetches a page of books that match the filter and converts them into acquisition feed
+/// entries, along with the IDs of the books. Returns `true` if there is a next page.
+async fn fetch_entries(
+    lib: &Library,
+    kosync: Option<&UserKosync>,
+    limit: NonZeroUsize,
+    offset: usize,
+    order_by: OrderBooksBy,
+    filter: &BookFilter,
+) -> crate::Result<(Vec<models::Entry>, Vec<i64>, bool)> {
+    let root_path = lib.root_path().to_owned();
+    let link_context = LinkContext::current();
+    let ((mut entries, book_ids, book_files, _), has_next_page) = lib
         .fetch_books(
             limit,
             offset,
             order_by,
-            (vec![], lib_name.into_inner()),
-            move |(mut acc, lib_name), book| {
-                let id = book.uri();
-                let links = book
-                    .data
-                    .iter()
-                    .map(|data| models::Link {
-                        rel: Some(models::LinkRel::Acquisition.as_str()),
-                        href: links::download_book(&lib_name, &book, data),
-                        kind: mime_guess::from_ext(&data.format)
-                            .first_raw()
-                            .unwrap_or("*/*"),
-                    })
-                    .chain(book.has_cover.then(|| models::Link {
-                        rel: Some(models::LinkRel::Image.as_str()),
-                        href: links::book_cover(&lib_name, &book),
-                        kind: mime::JPEG.as_str(),
-                    }))
-                    .collect();
-                let authors = book
-                    .authors
-                    .into_iter()
-                    .map(|name| models::Author { name, uri: None })
-                    .collect();
-                let categories = book
-                    .tags
-                    .into_iter()
-                    .map(|term| models::Category { term })
-                    .collect();
-
-                acc.push(models::Entry {
-                    title: book.title,
-                    updated: book.last_modified_at,
-                    content: book.content.map(|content| models::Content {
-                        kind: models::ContentKind::Html,
-                        value: content,
-                    }),
-                    categories,
-                    authors,
-                    links,
-                    id,
-                });
-
-                (acc, lib_name)
+            filter,
+            (vec![], vec![], vec![], CompactString::from(lib.name())),
+            move |(mut entries, mut book_ids, mut book_files, lib_name), book| {
+                book_ids.push(book.id);
+                book_files.push(book_files_of(&root_path, &book));
+                entries.push(link_context.scope(|| book_entry(&lib_name, book, false)));
+                (entries, book_ids, book_files, lib_name)
             },
         )
         .await?;
-    let mut links = vec![
-        models::Link::start(),
-        models::Link {
-            kind: models::LinkType::Navigation.as_str(),
-            rel: Some(models::LinkRel::First.as_str()),
-            href: links::explore_lib_with_query(
-                ExploreCatalogQuery {
-                    order_by: Some(order_by),
-                    limit: Some(limit),
-                    offset: Some(0),
-                },
-                lib,
-            ),
-        },
-    ];
 
-    if lib_len > limit.get() {
+    if let Some(kosync) = kosync {
+        for (entry, files) in entries.iter_mut().zip(book_files) {
+            add_reading_progress(kosync, entry, files).await?;
+        }
+    }
+
+    Ok((entries, book_ids, has_next_page))
+}
+
+/// Returns the absolute paths of the book's files.
+fn book_files_of(root_path: &std::path::Path, book: &FullBook) -> Vec<PathBuf> {
+    book.data
+        .iter()
+        .map(|data| root_path.join(book.file_path(data)))
+        .collect()
+}
+
+/// Prepends the most recent reading progress that the user synced with KOReader for any
+/// of the book's files to its entry.
+async fn add_reading_progress(
+    kosync: &UserKosync,
+    entry: &mut models::Entry,
+    files: Vec<PathBuf>,
+) -> crate::Result<()> {
+    if let Some(progress) = kosync.find_latest_progress(files).await? {
+        prepend_content(entry, &reading_progress(&progress));
+    }
+
+    Ok(())
+}
+
+fn reading_progress(progress: &Progress) -> CompactString {
+    let mut details = format_compact!(
+        "{}, on {}",
+        quick_xml::escape::escape(progress.username.as_str()),
+        quick_xml::escape::escape(progress.device.as_str())
+    );
+
+    if let Ok(synced_at) = OffsetDateTime::from_unix_timestamp(progress.timestamp) {
+        details += &format_compact!(", {}", format_date(synced_at));
+    }
+
+    format_compact!(
+        "<p>Reading progress: {:.0}% ({details})</p>",
+        (progress.percentage * 100.0).clamp(0.0, 100.0)
+    )
+}
+
+/// Prepends HTML to the content of the entry.
+fn prepend_content(entry: &mut models::Entry, html: &str) {
+    match &mut entry.content {
+        Some(content) => content.value.insert_str(0, html),
+        None => {
+            entry.content = Some(models::Content {
+                kind: models::ContentKind::Html,
+                value: html.into(),
+            })
+        }
+    }
+}
+
+/// Converts a book into an entry. Partial entries, which are listed in acquisition feeds,
+/// link to the complete one, which also describes the publisher, rating and identifiers.
+fn book_entry(lib_name: &str, book: FullBook, complete: bool) -> models::Entry {
+    let id = book.uri();
+    let mut links: Vec<_> = book
+        .data
+        .iter()
+        .map(|data| models::Link {
+            rel: Some(models::LinkRel::Acquisition.as_str()),
+            href: links::download_book(lib_name, &book, data),
+            kind: mime_guess::from_ext(&data.format)
+                .first_raw()
+                .unwrap_or("*/*"),
+        })
+        .collect();
+
+    if book.has_cover {
+        links.push(models::Link {
+            rel: Some(models::LinkRel::Image.as_str()),
+            href: links::book_cover(lib_name, &book),
+            kind: mime::IMAGE_JPEG.as_ref(),
+        });
+        links.push(models::Link {
+            rel: Some(models::LinkRel::Thumbnail.as_str()),
+            href: links::book_thumbnail(lib_name, book.id),
+            kind: mime::IMAGE_JPEG.as_ref(),
+        });
+    }
+
+    if !complete {
+        links.push(models::Link {
+            rel: Some(models::LinkRel::Alternate.as_str()),
+            href: links::book(lib_name, book.id),
+            kind: models::LinkType::Entry.as_str(),
+        });
+    }
+
+    let authors = book
+        .authors
+        .into_iter()
+        .map(|author| models::Author {
+            uri: Some(links::author(
+                lib_name,
+                author.id,
+                &ExploreCatalogQuery::default(),
+            )),
+            name: author.name,
+        })
+        .collect();
+    let categories = book
+        .tags
+        .into_iter()
+        .map(|term| models::Category { term })
+        .collect();
+
+    let mut details = CompactString::default();
+
+    if let Some(series) = book.series {
+        details += &format_compact!(
+            "<p>Book {} of {}</p>",
+            series.index,
+            quick_xml::escape::escape(series.name.as_str())
+        );
+    }
+
+    for custom_value in &book.custom_values {
+        let value = match &custom_value.value {
+            CustomValue::Text(values) => quick_xml::escape::escape(values.join(", "))
+                .into_owned()
+                .into(),
+            CustomValue::Bool(true) => CompactString::const_new("Yes"),
+            CustomValue::Bool(false) => CompactString::const_new("No"),
+            CustomValue::Rating(rating) => rating_stars(*rating),
+            CustomValue::Date(date) => format_date(*date),
+        };
+
+        details += &format_compact!(
+            "<p>{}: {value}</p>",
+            quick_xml::escape::escape(custom_value.name.as_str())
+        );
+    }
+
+    if complete {
+        if let Some(publisher) = &book.publisher {
+            details += &format_compact!(
+                "<p>Published by {}</p>",
+                quick_xml::escape::escape(publisher.as_str())
+            );
+        }
+
+        if let Some(rating) = book.rating {
+            details += &format_compact!("<p>Rated {}</p>", rating_stars(rating));
+        }
+
+        if !book.identifiers.is_empty() {
+            details += "<p>Identifiers: ";
+
+            for (i, identifier) in book.identifiers.iter().enumerate() {
+                if i > 0 {
+                    details += ", ";
+                }
+
+                details += &format_compact!(
+                    "{}:{}",
+                    quick_xml::escape::escape(identifier.kind.as_str()),
+                    quick_xml::escape::escape(identifier.value.as_str())
+                );
+            }
+
+            details += "</p>";
+        }
+    }
+
+    let content = match (details.is_empty(), book.content) {
+        (true, content) => content,
+        (false, content) => {
+            if let Some(content) = content {
+                details.push_str(&content);
+            }
+
+            Some(details)
+        }
+    };
+
+    // Calibre stores the year 101 when the publication date is unknown.
+    let issued = book
+        .published_at
+        .filter(|date| date.year() > 101)
+        .map(format_date);
+    let identifiers = book
+        .identifiers
+        .into_iter()
+        .filter(|identifier| identifier.kind == "isbn")
+        .map(|identifier| format_compact!("urn:isbn:{}", identifier.value))
+        .chain(std::iter::once(id.clone()).filter(|id| id.starts_with("urn:uuid:")))
+        .collect();
+
+    models::Entry {
+        languages: book.languages.into_iter().map(language_tag).collect(),
+        publisher: book.publisher,
+        title: book.title,
+        updated: book.last_modified_at,
+        identifiers,
+        issued,
+        content: content.map(|value| models::Content {
+            kind: models::ContentKind::Html,
+            value,
+        }),
+        categories,
+        authors,
+        links,
+        id,
+    }
+}
+
+/// Renders a rating from 1 to 10 as stars. Each star counts as 2 points.
+fn rating_stars(rating: u8) -> CompactString {
+    let mut stars: CompactString = std::iter::repeat_n('★', usize::from(rating / 2)).collect();
+
+    if rating % 2 == 1 {
+        stars.push('½');
+    }
+
+    stars
+}
+
+/// Formats the date part of a timestamp as `YYYY-MM-DD`.
+fn format_date(date: OffsetDateTime) -> CompactString {
+    let date = date.date();
+
+    format_compact!(
+        "{:04}-{:02}-{:02}",
+        date.year(),
+        u8::from(date.month()),
+        date.day()
+    )
+}
+
+/// Builds the `first`, `last`, `previous` and `next` links of a paginated feed. `href`
+/// returns the link of the page that starts at the given offset.
+fn pagination_links(
+    total: usize,
+    offset: usize,
+    limit: NonZeroUsize,
+    has_next_page: bool,
+    href: impl Fn(usize) -> CompactString,
+) -> Vec<models::Link> {
+    let mut links = vec![models::Link {
+        kind: models::LinkType::Navigation.as_str(),
+        rel: Some(models::LinkRel::First.as_str()),
+        href: href(0),
+    }];
+
+    if total > limit.get() {
         links.push(models::Link {
             kind: models::LinkType::Navigation.as_str(),
             rel: Some(models::LinkRel::Last.as_str()),
-            href: links::explore_lib_with_query(
-                ExploreCatalogQuery {
-                    offset: Some(lib_len.saturating_sub(limit.get())),
-                    order_by: Some(order_by),
-                    limit: Some(limit),
-                },
-                lib,
-            ),
+            href: href(total.saturating_sub(limit.get())),
         });
     }
 
@@ -232,14 +847,7 @@ async fn explore_catalog(
         links.push(models::Link {
             kind: models::LinkType::Navigation.as_str(),
             rel: Some(models::LinkRel::Previous.as_str()),
-            href: links::explore_lib_with_query(
-                ExploreCatalogQuery {
-                    offset: Some(offset.saturating_sub(limit.get())),
-                    order_by: Some(order_by),
-                    limit: Some(limit),
-                },
-                lib,
-            ),
+            href: href(offset.saturating_sub(limit.get())),
         });
     }
 
@@ -247,25 +855,9 @@ async fn explore_catalog(
         links.push(models::Link {
             kind: models::LinkType::Navigation.as_str(),
             rel: Some(models::LinkRel::Next.as_str()),
-            href: links::explore_lib_with_query(
-                ExploreCatalogQuery {
-                    offset: Some(offset + limit.get()),
-                    order_by: Some(order_by),
-                    limit: Some(limit),
-                },
-                lib,
-            ),
+            href: href(offset + limit.get()),
         });
     }
 
-    HttpResponse::Ok().xml(&models::Feed {
-        xmlns: XMLNS_ATOM,
-        id: lib.acquisition_feed_id().into(),
-        title: format_compact!("{lib_name} | {FEED_TITLE}"),
-        subtitle: Some(format_compact!("Exploring the \"{lib_name}\" library")),
-        updated: lib.updated_at(),
-        authors: vec![FEED_AUTHOR],
-        entries,
-        links,
-    })
+    links
 }
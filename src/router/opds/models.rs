@@ -2,41 +2,46 @@ use compact_str::CompactString;
 use serde::Serialize;
 use time::{OffsetDateTime, serde::rfc3339};
 
+use super::SearchMode;
 use crate::library::Library;
 
 pub struct LibraryRootEntry {
     pub title: &'static str,
     pub description: &'static str,
-    pub sort_by: Option<&'static str>,
+    pub href: CompactString,
+    pub kind: LinkType,
     pub link_rel: Option<LinkRel>,
 }
 
 impl From<(&Library, LibraryRootEntry)> for Entry {
     fn from((lib, e): (&Library, LibraryRootEntry)) -> Self {
-        Self {
-            id: lib.acquisition_feed_id().into(),
-            updated: lib.updated_at(),
-            title: e.title.into(),
-            categories: vec![],
-            authors: vec![],
-            content: Some(Content {
-                value: e.description.into(),
-                kind: ContentKind::Text,
-            }),
-            links: vec![Link {
-                href: super::links::explore_lib(lib, e.sort_by),
-                kind: LinkType::Acquisition.as_str(),
+        Self::subsection(
+            lib.acquisition_feed_id().into(),
+            e.title.into(),
+            e.description.into(),
+            lib.updated_at(),
+            Link {
+                href: e.href,
+                kind: e.kind.as_str(),
                 rel: e.link_rel.map(|x| x.as_str()),
-            }],
-        }
+            },
+        )
     }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename = "feed", rename_all = "kebab-case")]
 pub struct Feed {
-    #[serde(rename = "@xlmns")]
+    #[serde(rename = "@xmlns")]
     pub xmlns: &'static str,
+    #[serde(rename = "@xmlns:opds")]
+    pub xmlns_opds: &'static str,
+    #[serde(rename = "@xmlns:thr")]
+    pub xmlns_thr: &'static str,
+    #[serde(rename = "@xmlns:dc")]
+    pub xmlns_dc: &'static str,
+    #[serde(rename = "@xmlns:dcterms")]
+    pub xmlns_dcterms: &'static str,
 
     pub id: CompactString,
     pub title: CompactString,
@@ -49,10 +54,32 @@ pub struct Feed {
     pub authors: Vec<Author>,
     #[serde(rename = "link", skip_serializing_if = "Vec::is_empty")]
     pub links: Vec<Link>,
+    #[serde(rename = "link", skip_serializing_if = "Vec::is_empty")]
+    pub facets: Vec<FacetLink>,
     #[serde(rename = "entry", skip_serializing_if = "Vec::is_empty")]
     pub entries: Vec<Entry>,
 }
 
+impl Default for Feed {
+    fn default() -> Self {
+        Self {
+            xmlns: super::XMLNS_ATOM,
+            xmlns_opds: super::XMLNS_OPDS,
+            xmlns_thr: super::XMLNS_THR,
+            xmlns_dc: super::XMLNS_DC,
+            xmlns_dcterms: super::XMLNS_DCTERMS,
+            id: CompactString::default(),
+            title: CompactString::default(),
+            subtitle: None,
+            updated: OffsetDateTime::UNIX_EPOCH,
+            authors: vec![],
+            links: vec![],
+            facets: vec![],
+            entries: vec![],
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Entry {
@@ -69,6 +96,59 @@ pub struct Entry {
     pub content: Option<Content>,
     #[serde(rename = "link", skip_serializing_if = "Vec::is_empty")]
     pub links: Vec<Link>,
+
+    /// The publication date, formatted as `YYYY-MM-DD`.
+    #[serde(rename = "dcterms:issued", skip_serializing_if = "Option::is_none")]
+    pub issued: Option<CompactString>,
+    /// The BCP 47 tags of the languages the book is written in.
+    #[serde(rename = "dcterms:language", skip_serializing_if = "Vec::is_empty")]
+    pub languages: Vec<CompactString>,
+    #[serde(rename = "dcterms:publisher", skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<CompactString>,
+    /// URNs that identify the book, such as `urn:isbn:` and `urn:uuid:`.
+    #[serde(rename = "dcterms:identifier", skip_serializing_if = "Vec::is_empty")]
+    pub identifiers: Vec<CompactString>,
+}
+
+/// An [`Entry`] that is the root element of a document, i.e. a complete entry.
+#[derive(Debug, Serialize)]
+pub struct EntryDocument {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: &'static str,
+    #[serde(rename = "@xmlns:dc")]
+    pub xmlns_dc: &'static str,
+    #[serde(rename = "@xmlns:dcterms")]
+    pub xmlns_dcterms: &'static str,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+impl Entry {
+    /// Creates an entry that leads to another feed of the catalog.
+    pub fn subsection(
+        id: CompactString,
+        title: CompactString,
+        description: CompactString,
+        updated: OffsetDateTime,
+        link: Link,
+    ) -> Self {
+        Self {
+            categories: vec![],
+            authors: vec![],
+            content: Some(Content {
+                kind: ContentKind::Text,
+                value: description,
+            }),
+            links: vec![link],
+            issued: None,
+            languages: vec![],
+            publisher: None,
+            identifiers: vec![],
+            updated,
+            title,
+            id,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -89,7 +169,10 @@ pub struct Category {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Link {
-    #[serde(rename = "@href")]
+    #[serde(
+        rename = "@href",
+        serialize_with = "crate::router::auth::serialize_link"
+    )]
     pub href: CompactString,
     #[serde(rename = "@rel", skip_serializing_if = "Option::is_none")]
     pub rel: Option<&'static str>,
@@ -105,13 +188,53 @@ impl Link {
             rel: Some(LinkRel::Start.as_str()),
         }
     }
+
+    pub fn search(lib: &Library) -> Self {
+        Self {
+            href: super::links::opensearch(lib, SearchMode::Metadata),
+            kind: LinkType::Search.as_str(),
+            rel: Some(LinkRel::Search.as_str()),
+        }
+    }
+
+    pub fn full_text_search(lib: &Library) -> Self {
+        Self {
+            href: super::links::opensearch(lib, SearchMode::Fts),
+            kind: LinkType::Search.as_str(),
+            rel: Some(LinkRel::Search.as_str()),
+        }
+    }
+}
+
+/// A link to the same feed, narrowed down by a facet.
+///
+/// See <https://specs.opds.io/opds-1.2#4-facets>.
+#[derive(Debug, Serialize)]
+pub struct FacetLink {
+    #[serde(
+        rename = "@href",
+        serialize_with = "crate::router::auth::serialize_link"
+    )]
+    pub href: CompactString,
+    #[serde(rename = "@rel")]
+    pub rel: &'static str,
+    #[serde(rename = "@type")]
+    pub kind: &'static str,
+    #[serde(rename = "@title")]
+    pub title: CompactString,
+    #[serde(rename = "@opds:facetGroup")]
+    pub group: &'static str,
+    #[serde(rename = "@opds:activeFacet")]
+    pub active: bool,
+    #[serde(rename = "@thr:count")]
+    pub count: usize,
 }
 
 pub enum LinkType {
     Acquisition,
     Navigation,
-    // TODO: https://specs.opds.io/opds-1.2#3-search
-    // Search,
+    Entry,
+    Search,
 }
 
 impl LinkType {
@@ -119,33 +242,46 @@ impl LinkType {
         match self {
             Self::Acquisition => "application/atom+xml;profile=opds-catalog;kind=acquisition",
             Self::Navigation => "application/atom+xml;profile=opds-catalog;kind=navigation",
-            // Self::Search => "application/opensearchdescription+xml",
+            Self::Entry => "application/atom+xml;type=entry;profile=opds-catalog",
+            Self::Search => "application/opensearchdescription+xml",
         }
     }
 }
 
 pub enum LinkRel {
     Acquisition,
+    Alternate,
+    Facet,
     SortNew,
     Image,
+    Thumbnail,
+    Related,
+    Search,
     Start,
     First,
     Last,
     Next,
     Previous,
+    AuthenticationDocument,
 }
 
 impl LinkRel {
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Acquisition => "http://opds-spec.org/acquisition",
+            Self::Alternate => "alternate",
+            Self::Facet => "http://opds-spec.org/facet",
             Self::SortNew => "http://opds-spec.org/sort/new",
             Self::Image => "http://opds-spec.org/image",
+            Self::Thumbnail => "http://opds-spec.org/image/thumbnail",
+            Self::Related => "related",
+            Self::Search => "search",
             Self::Start => "start",
             Self::First => "first",
             Self::Last => "last",
             Self::Next => "next",
             Self::Previous => "previous",
+            Self::AuthenticationDocument => "http://opds-spec.org/auth/document",
         }
     }
 }
@@ -166,3 +302,29 @@ pub enum ContentKind {
     Text,
     Html,
 }
+
+/// See <https://github.com/dewitt/opensearch/blob/master/opensearch-1-1-draft-6.md>.
+#[derive(Debug, Serialize)]
+#[serde(rename = "OpenSearchDescription", rename_all = "PascalCase")]
+pub struct OpenSearchDescription {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: &'static str,
+
+    pub short_name: CompactString,
+    pub description: CompactString,
+    pub input_encoding: &'static str,
+    pub output_encoding: &'static str,
+    #[serde(rename = "Url")]
+    pub urls: Vec<OpenSearchUrl>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenSearchUrl {
+    #[serde(rename = "@type")]
+    pub kind: &'static str,
+    #[serde(
+        rename = "@template",
+        serialize_with = "crate::router::auth::serialize_link"
+    )]
+    pub template: CompactString,
+}
@@ -0,0 +1,146 @@
+use actix_web::{HttpResponse, Responder, get, web};
+use compact_str::{CompactString, format_compact};
+
+use super::{ExploreCatalogQuery, FEED_AUTHOR, FEED_TITLE, fetch_page, links, models};
+use crate::{
+    errors::AppError,
+    library::{Library, OrderBooksBy, SavedQuery, SavedQueryKind},
+    router::auth::{UserKosync, UserLibraries},
+    utils::{HttpResponseBuilderExt as _, hash_str},
+};
+
+#[get("/{lib_name}/virtual-libraries/{name}")]
+pub(super) async fn virtual_library_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, String)>,
+) -> crate::Result<impl Responder> {
+    saved_query_books(
+        SavedQueryKind::VirtualLibrary,
+        &query,
+        &libraries,
+        kosync.as_ref(),
+        &path.0,
+        &path.1,
+    )
+    .await
+}
+
+#[get("/{lib_name}/saved-searches/{name}")]
+pub(super) async fn saved_search_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, String)>,
+) -> crate::Result<impl Responder> {
+    saved_query_books(
+        SavedQueryKind::SavedSearch,
+        &query,
+        &libraries,
+        kosync.as_ref(),
+        &path.0,
+        &path.1,
+    )
+    .await
+}
+
+/// Converts the saved queries of the library into entries that lead to the books that
+/// match them.
+pub(super) fn saved_query_entries(lib: &Library, kind: SavedQueryKind) -> Vec<models::Entry> {
+    lib.saved_queries(kind)
+        .iter()
+        .map(|saved_query| {
+            models::Entry::subsection(
+                saved_query_feed_id(lib, kind, saved_query),
+                saved_query.name.clone(),
+                match kind {
+                    SavedQueryKind::VirtualLibrary => {
+                        format_compact!("View the \"{}\" virtual library", saved_query.name)
+                    }
+                    SavedQueryKind::SavedSearch => {
+                        format_compact!("View the books matching \"{}\"", saved_query.query)
+                    }
+                },
+                lib.updated_at(),
+                models::Link {
+                    href: links::saved_query(
+                        lib,
+                        kind,
+                        &saved_query.name,
+                        &ExploreCatalogQuery::default(),
+                    ),
+                    kind: models::LinkType::Acquisition.as_str(),
+                    rel: None,
+                },
+            )
+        })
+        .collect()
+}
+
+fn saved_query_feed_id(
+    lib: &Library,
+    kind: SavedQueryKind,
+    saved_query: &SavedQuery,
+) -> CompactString {
+    format_compact!(
+        "{}:{}-{}",
+        lib.acquisition_feed_id(),
+        links::saved_query_path(kind),
+        hash_str(&saved_query.name)
+    )
+}
+
+async fn saved_query_books(
+    kind: SavedQueryKind,
+    query: &ExploreCatalogQuery,
+    libraries: &UserLibraries,
+    kosync: Option<&UserKosync>,
+    lib_name: &str,
+    name: &str,
+) -> crate::Result<HttpResponse> {
+    let Some(lib) = libraries.get(lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(saved_query) = lib.find_saved_query(kind, name) else {
+        return Err(match kind {
+            SavedQueryKind::VirtualLibrary => AppError::VirtualLibraryNotFound,
+            SavedQueryKind::SavedSearch => AppError::SavedSearchNotFound,
+        });
+    };
+
+    let page = fetch_page(
+        lib,
+        kosync,
+        query,
+        &saved_query.filter,
+        OrderBooksBy::Title,
+        |query| links::saved_query(lib, kind, &saved_query.name, query),
+    )
+    .await?;
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+    links.extend(page.links);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: saved_query_feed_id(lib, kind, saved_query),
+        title: format_compact!("{name} | {lib_name} | {FEED_TITLE}"),
+        subtitle: Some(match kind {
+            SavedQueryKind::VirtualLibrary => {
+                format_compact!(
+                    "Exploring the \"{name}\" virtual library of the \"{lib_name}\" library"
+                )
+            }
+            SavedQueryKind::SavedSearch => format_compact!(
+                "Found {} books matching \"{}\" in the \"{lib_name}\" library",
+                page.total,
+                saved_query.query
+            ),
+        }),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,190 @@
+use std::num::NonZeroUsize;
+
+use actix_web::{HttpResponse, Responder, get, web};
+use compact_str::{CompactString, format_compact};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ExploreCatalogQuery, FEED_AUTHOR, FEED_TITLE, fetch_page, links, models, page_size,
+    pagination_links,
+};
+use crate::{
+    errors::AppError,
+    library::{BookFilter, OrderBooksBy},
+    router::auth::{UserKosync, UserLibraries},
+    utils::HttpResponseBuilderExt as _,
+};
+
+#[derive(Default, Serialize, Deserialize)]
+pub(super) struct TagsQuery {
+    /// The full name of the tag whose children are listed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<CompactString>,
+    pub offset: Option<usize>,
+    pub limit: Option<NonZeroUsize>,
+}
+
+#[get("/{lib_name}/tags")]
+pub(super) async fn tag_list(
+    query: web::Query<TagsQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let TagsQuery {
+        parent,
+        offset,
+        limit,
+    } = query.into_inner();
+    let offset = offset.unwrap_or(0);
+    let limit = page_size(limit);
+
+    let total = lib.count_tag_nodes(parent.as_deref()).await?;
+    let (nodes, has_next_page) = lib
+        .fetch_tag_nodes(parent.as_deref(), limit, offset)
+        .await?;
+    let mut entries = vec![];
+
+    // Books can be tagged with the parent itself, e.g. with `Fiction` and not only with
+    // `Fiction.Fantasy`.
+    if let Some(parent) = parent.as_deref()
+        && offset == 0
+        && let Some(tag) = lib.find_tag_by_name(parent).await?
+    {
+        entries.push(models::Entry::subsection(
+            format_compact!("{}:tag-{}", lib.acquisition_feed_id(), tag.id),
+            tag.name,
+            super::book_count(tag.book_count),
+            lib.updated_at(),
+            models::Link {
+                href: links::tag(lib, tag.id, &ExploreCatalogQuery::default()),
+                kind: models::LinkType::Acquisition.as_str(),
+                rel: None,
+            },
+        ));
+    }
+
+    entries.extend(nodes.into_iter().map(|node| {
+        let full_name = match parent.as_deref() {
+            Some(parent) => format_compact!("{parent}.{}", node.name),
+            None => node.name.clone(),
+        };
+        let link = match node.tag_id {
+            Some(tag_id) if !node.has_children => models::Link {
+                href: links::tag(lib, tag_id, &ExploreCatalogQuery::default()),
+                kind: models::LinkType::Acquisition.as_str(),
+                rel: None,
+            },
+
+            _ => models::Link {
+                href: links::tag_list(
+                    lib,
+                    &TagsQuery {
+                        parent: Some(full_name.clone()),
+                        ..Default::default()
+                    },
+                ),
+                kind: models::LinkType::Navigation.as_str(),
+                rel: None,
+            },
+        };
+
+        models::Entry::subsection(
+            format_compact!("{}:tags:{full_name}", lib.acquisition_feed_id()),
+            node.name,
+            super::book_count(node.book_count),
+            lib.updated_at(),
+            link,
+        )
+    }));
+
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+
+    links.extend(pagination_links(
+        total,
+        offset,
+        limit,
+        has_next_page,
+        |offset| {
+            links::tag_list(
+                lib,
+                &TagsQuery {
+                    parent: parent.clone(),
+                    offset: Some(offset),
+                    limit: Some(limit),
+                },
+            )
+        },
+    ));
+
+    let (id, title, subtitle) = match parent.as_deref() {
+        Some(parent) => (
+            format_compact!("{}:tags:{parent}", lib.acquisition_feed_id()),
+            format_compact!("{parent} | {lib_name} | {FEED_TITLE}"),
+            format_compact!("Exploring the \"{parent}\" tags of the \"{lib_name}\" library"),
+        ),
+
+        None => (
+            format_compact!("{}:tags", lib.acquisition_feed_id()),
+            format_compact!("Tags | {lib_name} | {FEED_TITLE}"),
+            format_compact!("Exploring the tags of the \"{lib_name}\" library"),
+        ),
+    };
+
+    HttpResponse::Ok().xml(&models::Feed {
+        subtitle: Some(subtitle),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries,
+        links,
+        title,
+        id,
+        ..Default::default()
+    })
+}
+
+#[get("/{lib_name}/tags/{tag_id}")]
+pub(super) async fn tag_books(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, tag_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(tag) = lib.find_tag(tag_id).await? else {
+        return Err(AppError::TagNotFound);
+    };
+
+    let page = fetch_page(
+        lib,
+        kosync.as_ref(),
+        &query,
+        &BookFilter::Tag(tag.id),
+        OrderBooksBy::Title,
+        |query| links::tag(lib, tag.id, query),
+    )
+    .await?;
+    let mut links = vec![models::Link::start(), models::Link::search(lib)];
+    links.extend(page.links);
+
+    HttpResponse::Ok().xml(&models::Feed {
+        id: format_compact!("{}:tag-{}", lib.acquisition_feed_id(), tag.id),
+        title: format_compact!("{} | {lib_name} | {FEED_TITLE}", tag.name),
+        subtitle: Some(format_compact!(
+            "Exploring the books tagged with \"{}\" in the \"{lib_name}\" library",
+            tag.name
+        )),
+        updated: lib.updated_at(),
+        authors: vec![FEED_AUTHOR],
+        entries: page.entries,
+        facets: page.facets,
+        links,
+        ..Default::default()
+    })
+}
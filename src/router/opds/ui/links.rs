@@ -0,0 +1,38 @@
+use compact_str::{CompactString, format_compact};
+
+use super::{
+    super::{ExploreCatalogQuery, SearchQuery, links::enc},
+    COMMON_ROUTE as UI_ROOT,
+};
+use crate::library::Library;
+
+pub fn root() -> CompactString {
+    CompactString::const_new(UI_ROOT)
+}
+
+pub fn lib_root(lib: &Library) -> CompactString {
+    format_compact!("{UI_ROOT}/{}", enc(lib.name()))
+}
+
+pub fn explore_lib(lib: &Library, query: &ExploreCatalogQuery) -> CompactString {
+    super::super::links::with_query(format_compact!("{}/explore", lib_root(lib)), query)
+}
+
+pub fn search_lib(
+    lib: &Library,
+    search_query: &SearchQuery,
+    query: &ExploreCatalogQuery,
+) -> CompactString {
+    super::super::links::with_query(
+        super::super::links::with_query(format_compact!("{}/search", lib_root(lib)), search_query),
+        query,
+    )
+}
+
+pub fn search_form(lib: &Library) -> CompactString {
+    format_compact!("{}/search", lib_root(lib))
+}
+
+pub fn book(lib_name: &str, book_id: i64) -> CompactString {
+    format_compact!("{UI_ROOT}/{}/books/{book_id}", enc(lib_name))
+}
@@ -0,0 +1,338 @@
+//! An HTML version of the catalog, for browsing it with a web browser, including the ones
+//! of e-ink readers. It's rendered from the same data as the OPDS catalog.
+
+pub(super) mod links;
+mod templates;
+
+use std::borrow::Cow;
+
+use actix_web::{HttpResponse, Responder, get, http::header::ContentType, web};
+use compact_str::{CompactString, format_compact};
+
+use super::{
+    ExploreCatalogQuery, SearchMode, SearchQuery, book_files_of, facets, highlight_snippet,
+    page_size, reading_progress,
+};
+use crate::{
+    errors::AppError,
+    library::{BookFilter, FullBook, Library, OrderBooksBy, SavedQueryKind},
+    router::auth::{UserKosync, UserLibraries},
+};
+
+pub const COMMON_ROUTE: &str = "/ui";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(root)
+        .service(library_root)
+        .service(explore_catalog)
+        .service(search)
+        .service(book_page);
+}
+
+fn html(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(body)
+}
+
+#[get("")]
+async fn root(libraries: UserLibraries) -> crate::Result<impl Responder> {
+    let mut items = vec![];
+
+    for lib in libraries.get_all() {
+        items.push((
+            Cow::Owned(format!(
+                "{} ({})",
+                lib.name(),
+                super::book_count(lib.len().await?)
+            )),
+            links::lib_root(lib),
+        ));
+    }
+
+    Ok(html(templates::layout(
+        "Libraries",
+        super::COMMON_ROUTE,
+        &[],
+        &templates::link_list(None, items),
+    )))
+}
+
+#[get("/{lib_name}")]
+async fn library_root(
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let explore = |title: &'static str, order_by: Option<OrderBooksBy>| {
+        (
+            Cow::Borrowed(title),
+            links::explore_lib(
+                lib,
+                &ExploreCatalogQuery {
+                    facets: facets::Facets {
+                        order_by,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ),
+        )
+    };
+
+    let mut body = templates::search_form(lib, "", SearchMode::Metadata);
+    body += &templates::link_list(
+        Some("Books"),
+        [
+            explore("View Books", None),
+            explore("View New Books", Some(OrderBooksBy::DateAdded)),
+            explore("View Books by Title", Some(OrderBooksBy::Title)),
+            explore("View Books by Author", Some(OrderBooksBy::Author)),
+        ],
+    );
+
+    for (kind, heading, field) in [
+        (SavedQueryKind::VirtualLibrary, "Virtual Libraries", "vl"),
+        (SavedQueryKind::SavedSearch, "Saved Searches", "search"),
+    ] {
+        let saved_queries = lib.saved_queries(kind);
+
+        if saved_queries.is_empty() {
+            continue;
+        }
+
+        body += &templates::link_list(
+            Some(heading),
+            saved_queries.iter().map(|saved_query| {
+                (
+                    Cow::Borrowed(saved_query.name.as_str()),
+                    links::explore_lib(
+                        lib,
+                        &ExploreCatalogQuery {
+                            facets: facets::Facets {
+                                query: Some(format_compact!(
+                                    "{field}:{}",
+                                    templates::quote(&saved_query.name)
+                                )),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        },
+                    ),
+                )
+            }),
+        );
+    }
+
+    Ok(html(templates::layout(
+        lib.name(),
+        &super::links::lib_root(lib),
+        &[],
+        &body,
+    )))
+}
+
+#[get("/{lib_name}/explore")]
+async fn explore_catalog(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let page = fetch_page(
+        lib,
+        &query,
+        &BookFilter::All,
+        OrderBooksBy::DateAdded,
+        |query| links::explore_lib(lib, query),
+    )
+    .await?;
+
+    let mut body = templates::search_form(lib, "", SearchMode::Metadata);
+
+    if let Some(filter) = &query.facets.query {
+        body += &format!(
+            "<p>Showing the books that match <code>{}</code></p>",
+            templates::esc(filter)
+        );
+    }
+
+    body += &page.html(lib.name(), &[]);
+
+    Ok(html(templates::layout(
+        "Books",
+        &super::links::explore_lib_with_query(lib, &query),
+        &[(lib.name(), links::lib_root(lib))],
+        &body,
+    )))
+}
+
+#[get("/{lib_name}/search")]
+async fn search(
+    search_query: web::Query<SearchQuery>,
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let q = &search_query.q;
+    let filter = match search_query.mode {
+        SearchMode::Metadata => lib.parse_query(q)?,
+        SearchMode::Fts => match lib.full_text_search(q).await? {
+            Some(book_ids) => BookFilter::Ids(book_ids),
+            None => return Err(AppError::FullTextSearchUnavailable),
+        },
+    };
+
+    let page = fetch_page(
+        lib,
+        &query,
+        &filter,
+        search_query.mode.order_books_by(),
+        |query| links::search_lib(lib, &search_query, query),
+    )
+    .await?;
+
+    let snippets = match search_query.mode {
+        SearchMode::Metadata => vec![],
+        SearchMode::Fts => {
+            let book_ids = page.books.iter().map(|book| book.id).collect::<Vec<_>>();
+            let snippets = lib.fetch_full_text_snippets(q, &book_ids).await?;
+
+            book_ids
+                .iter()
+                .map(|book_id| {
+                    snippets
+                        .get(book_id)
+                        .map(|snippet| highlight_snippet(snippet).into())
+                })
+                .collect()
+        }
+    };
+
+    let mut body = templates::search_form(lib, q, search_query.mode);
+    body += &page.html(lib.name(), &snippets);
+
+    Ok(html(templates::layout(
+        &format_compact!("Search: {q}"),
+        &super::links::search_lib_with_query(lib, &search_query, &query),
+        &[(lib.name(), links::lib_root(lib))],
+        &body,
+    )))
+}
+
+#[get("/{lib_name}/books/{book_id}")]
+async fn book_page(
+    libraries: UserLibraries,
+    kosync: Option<UserKosync>,
+    path: web::Path<(String, i64)>,
+) -> crate::Result<impl Responder> {
+    let (lib_name, book_id) = path.into_inner();
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+    let Some(book) = lib.find_book(book_id).await? else {
+        return Err(AppError::BookNotFound);
+    };
+
+    let progress = match &kosync {
+        Some(kosync) => kosync
+            .find_latest_progress(book_files_of(lib.root_path(), &book))
+            .await?
+            .map(|progress| reading_progress(&progress)),
+        None => None,
+    };
+
+    Ok(html(templates::layout(
+        &book.title,
+        &super::links::book(lib.name(), book.id),
+        &[(lib.name(), links::lib_root(lib))],
+        &templates::book_details(lib, &book, progress.as_deref()),
+    )))
+}
+
+/// A page of a list of books.
+struct Page {
+    books: Vec<FullBook>,
+    facets: String,
+    pagination: String,
+}
+
+impl Page {
+    fn html(&self, lib_name: &str, snippets: &[Option<String>]) -> String {
+        let mut html = self.facets.clone();
+        html += &templates::book_grid(lib_name, &self.books, snippets);
+        html += &self.pagination;
+        html
+    }
+}
+
+/// Fetches the page of the books matching the filter, narrowed down by the facets of the
+/// query. `href` returns the link of the page with the given query.
+async fn fetch_page(
+    lib: &Library,
+    query: &ExploreCatalogQuery,
+    filter: &BookFilter,
+    default_order_by: OrderBooksBy,
+    href: impl Fn(&ExploreCatalogQuery) -> CompactString,
+) -> crate::Result<Page> {
+    let order_by = query.facets.order_by.unwrap_or(default_order_by);
+    let offset = query.offset.unwrap_or(0);
+    let limit = page_size(query.limit);
+
+    let filter = &query.facets.apply_query(lib, filter)?;
+    let narrowed_filter = query.facets.narrow(filter);
+    let total = lib.count_books(&narrowed_filter).await?;
+    let (books, has_next_page) = lib
+        .fetch_books(
+            limit,
+            offset,
+            order_by,
+            &narrowed_filter,
+            vec![],
+            |mut books, book| {
+                books.push(book);
+                books
+            },
+        )
+        .await?;
+
+    let facet_links = facets::facet_links(
+        lib,
+        filter,
+        &query.facets,
+        default_order_by,
+        total,
+        |facets| {
+            href(&ExploreCatalogQuery {
+                limit: query.limit,
+                offset: None,
+                facets,
+            })
+        },
+    )
+    .await?;
+    let pagination =
+        templates::pagination(total, offset, books.len(), limit, has_next_page, |offset| {
+            href(&ExploreCatalogQuery {
+                facets: query.facets.clone(),
+                offset: Some(offset),
+                limit: query.limit,
+            })
+        });
+
+    Ok(Page {
+        facets: templates::facets(&facet_links),
+        books,
+        pagination,
+    })
+}
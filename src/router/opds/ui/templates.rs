@@ -0,0 +1,486 @@
+//! The HTML of the pages. It's written by hand, as the pages are simple, and must render
+//! on the limited browsers of e-ink readers: there's no JavaScript, and the styles avoid
+//! layouts that old engines don't support.
+
+use std::{borrow::Cow, fmt::Write as _, num::NonZeroUsize};
+
+use compact_str::CompactString;
+use quick_xml::escape::escape;
+
+use super::{
+    super::{
+        SearchMode, format_date,
+        links::{book_cover, book_thumbnail, download_book},
+        models::FacetLink,
+        rating_stars,
+    },
+    links,
+};
+use crate::{
+    library::{CustomValue, FullBook, Library},
+    router::auth::prefixed,
+    utils::language_name,
+};
+
+const STYLE: &str = "\
+body{font-family:sans-serif;line-height:1.4;color:#000;background:#fff;max-width:60em;margin:0 auto;padding:0 1em}\
+header{border-bottom:2px solid #000;padding:.5em 0;margin-bottom:1em}\
+a{color:#000}\
+h1{font-size:1.5em}\
+input,select,button{font-size:1em;border:2px solid #000;background:#fff;color:#000;padding:.2em}\
+.grid{margin:0 -.5em}\
+.card{display:inline-block;vertical-align:top;width:10em;margin:0 .5em 1.5em}\
+.card img,.no-cover{display:block;width:10em;height:15em;border:1px solid #000}\
+.no-cover{text-align:center;line-height:15em}\
+.title{font-weight:bold}\
+.button{display:inline-block;border:2px solid #000;padding:.1em .5em;margin:.3em .3em 0 0;text-decoration:none;font-weight:bold}\
+.facets p{margin:.3em 0}\
+.active{font-weight:bold;text-decoration:none}\
+.pagination{border-top:2px solid #000;padding:1em 0}\
+.book img{float:left;max-width:40%;margin:0 1em 1em 0;border:1px solid #000}\
+.details{overflow:hidden}\
+.description{clear:both}";
+
+pub(super) fn esc(s: &str) -> Cow<'_, str> {
+    escape(s)
+}
+
+/// Escapes the link for an attribute, prepending the prefix of the routes that the
+/// request was sent through, so that the pages of a device token keep linking to its
+/// routes.
+fn esc_link(link: &str) -> String {
+    esc(&prefixed(link)).into_owned()
+}
+
+/// Wraps the body of a page. `opds_href` is the link of the same page in the OPDS
+/// catalog, and `breadcrumbs` are the links of the pages above it.
+pub(super) fn layout(
+    title: &str,
+    opds_href: &str,
+    breadcrumbs: &[(&str, CompactString)],
+    body: &str,
+) -> String {
+    let mut html = String::with_capacity(body.len() + 2048);
+
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\
+        <html lang=\"en\">\
+        <head>\
+        <meta charset=\"utf-8\">\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+        <title>{title} | Seshat</title>\
+        <link rel=\"alternate\" type=\"application/atom+xml;profile=opds-catalog\" href=\"{opds_href}\">\
+        <style>{STYLE}</style>\
+        </head>\
+        <body>\
+        <header><a href=\"{root}\">Seshat</a>",
+        title = esc(title),
+        opds_href = esc_link(opds_href),
+        root = esc_link(&links::root()),
+    );
+
+    for (name, href) in breadcrumbs {
+        let _ = write!(html, " › <a href=\"{}\">{}</a>", esc_link(href), esc(name));
+    }
+
+    let _ = write!(
+        html,
+        "</header><main><h1>{}</h1>{body}</main></body></html>",
+        esc(title)
+    );
+
+    html
+}
+
+pub(super) fn search_form(lib: &Library, q: &str, mode: SearchMode) -> String {
+    let mut html = format!(
+        "<form action=\"{}\" method=\"get\"><p>\
+        <input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"e.g. author:Asimov\" aria-label=\"Search\"> ",
+        esc_link(&links::search_form(lib)),
+        esc(q),
+    );
+
+    if lib.has_full_text_search() {
+        let selected = |m| if mode == m { " selected" } else { "" };
+
+        let _ = write!(
+            html,
+            "<select name=\"mode\" aria-label=\"Search by\">\
+            <option value=\"metadata\"{}>Metadata</option>\
+            <option value=\"fts\"{}>Full text</option>\
+            </select> ",
+            selected(SearchMode::Metadata),
+            selected(SearchMode::Fts),
+        );
+    }
+
+    html.push_str("<button type=\"submit\">Search</button></p></form>");
+    html
+}
+
+/// A list of links, given as `(title, href)` pairs.
+pub(super) fn link_list<'a>(
+    heading: Option<&str>,
+    items: impl IntoIterator<Item = (Cow<'a, str>, CompactString)>,
+) -> String {
+    let mut html = String::new();
+
+    if let Some(heading) = heading {
+        let _ = write!(html, "<h2>{}</h2>", esc(heading));
+    }
+
+    html.push_str("<ul>");
+
+    for (title, href) in items {
+        let _ = write!(
+            html,
+            "<li><a href=\"{}\">{}</a></li>",
+            esc_link(&href),
+            esc(&title)
+        );
+    }
+
+    html.push_str("</ul>");
+    html
+}
+
+/// The facet links of a list of books, grouped by their `opds:facetGroup`.
+pub(super) fn facets(facet_links: &[FacetLink]) -> String {
+    let mut html = String::from("<div class=\"facets\">");
+    let mut group = "";
+
+    for link in facet_links {
+        if link.group != group {
+            if !group.is_empty() {
+                html.push_str("</p>");
+            }
+
+            group = link.group;
+            let _ = write!(html, "<p>{}: ", esc(group));
+        } else {
+            html.push_str(" · ");
+        }
+
+        if link.active {
+            let _ = write!(html, "<span class=\"active\">{}</span>", esc(&link.title));
+        } else {
+            let _ = write!(
+                html,
+                "<a href=\"{}\">{}</a> ({})",
+                esc_link(&link.href),
+                esc(&link.title),
+                link.count
+            );
+        }
+    }
+
+    if !group.is_empty() {
+        html.push_str("</p>");
+    }
+
+    html.push_str("</div>");
+    html
+}
+
+/// A grid of book covers, each followed by the title, the authors and download buttons.
+/// `snippets` are HTML excerpts of the books' text, in the same order.
+pub(super) fn book_grid(lib_name: &str, books: &[FullBook], snippets: &[Option<String>]) -> String {
+    if books.is_empty() {
+        return String::from("<p>No books were found.</p>");
+    }
+
+    let mut html = String::from("<div class=\"grid\">");
+
+    for (i, book) in books.iter().enumerate() {
+        let href = esc_link(&links::book(lib_name, book.id));
+
+        let _ = write!(html, "<div class=\"card\"><a href=\"{}\">", href);
+        cover(&mut html, lib_name, book, true);
+        let _ = write!(
+            html,
+            "</a><div class=\"title\"><a href=\"{}\">{}</a></div>",
+            href,
+            esc(&book.title)
+        );
+
+        if !book.authors.is_empty() {
+            let _ = write!(html, "<div>{}</div>", esc(&authors(book)));
+        }
+
+        if let Some(Some(snippet)) = snippets.get(i) {
+            html.push_str(snippet);
+        }
+
+        download_buttons(&mut html, lib_name, book, false);
+        html.push_str("</div>");
+    }
+
+    html.push_str("</div>");
+    html
+}
+
+/// The "previous" and "next" links of a list of books, and the range of books it shows.
+pub(super) fn pagination(
+    total: usize,
+    offset: usize,
+    shown: usize,
+    limit: NonZeroUsize,
+    has_next_page: bool,
+    href: impl Fn(usize) -> CompactString,
+) -> String {
+    let mut html = String::from("<p class=\"pagination\">");
+
+    if offset > 0 {
+        let _ = write!(
+            html,
+            "<a class=\"button\" href=\"{}\">‹ Previous</a> ",
+            esc_link(&href(offset.saturating_sub(limit.get())))
+        );
+    }
+
+    if shown > 0 {
+        let _ = write!(html, "Books {}–{} of {total} ", offset + 1, offset + shown);
+    }
+
+    if has_next_page {
+        let _ = write!(
+            html,
+            "<a class=\"button\" href=\"{}\">Next ›</a>",
+            esc_link(&href(offset + limit.get()))
+        );
+    }
+
+    html.push_str("</p>");
+    html
+}
+
+/// The complete details of a book. `progress` is the HTML of its reading progress.
+pub(super) fn book_details(lib: &Library, book: &FullBook, progress: Option<&str>) -> String {
+    let lib_name = lib.name();
+    let mut html = String::from("<div class=\"book\">");
+
+    if book.has_cover {
+        let _ = write!(
+            html,
+            "<a href=\"{}\">",
+            esc_link(&book_cover(lib_name, book))
+        );
+        cover(&mut html, lib_name, book, false);
+        html.push_str("</a>");
+    }
+
+    html.push_str("<div class=\"details\">");
+
+    if !book.authors.is_empty() {
+        html.push_str("<p>By ");
+
+        for (i, author) in book.authors.iter().enumerate() {
+            if i > 0 {
+                html.push_str(", ");
+            }
+
+            let _ = write!(
+                html,
+                "<a href=\"{}\">{}</a>",
+                esc_link(&filter_link(
+                    lib,
+                    &format!("author:{}", quote(&format!("={}", author.name)))
+                )),
+                esc(&author.name)
+            );
+        }
+
+        html.push_str("</p>");
+    }
+
+    if let Some(series) = &book.series {
+        let _ = write!(
+            html,
+            "<p>Book {} of <a href=\"{}\">{}</a></p>",
+            series.index,
+            esc_link(&filter_link(
+                lib,
+                &format!("series:{}", quote(&format!("={}", series.name)))
+            )),
+            esc(&series.name)
+        );
+    }
+
+    if let Some(progress) = progress {
+        html.push_str(progress);
+    }
+
+    download_buttons(&mut html, lib_name, book, true);
+    html.push_str("<dl>");
+
+    let mut field = |name: &str, value: &str| {
+        let _ = write!(html, "<dt>{}</dt><dd>{value}</dd>", esc(name));
+    };
+
+    if !book.tags.is_empty() {
+        let tags = book
+            .tags
+            .iter()
+            .map(|tag| {
+                format!(
+                    "<a href=\"{}\">{}</a>",
+                    esc_link(&filter_link(
+                        lib,
+                        &format!("tag:{}", quote(&format!("={tag}")))
+                    )),
+                    esc(tag)
+                )
+            })
+            .collect::<Vec<_>>();
+
+        field("Tags", &tags.join(", "));
+    }
+
+    if let Some(publisher) = &book.publisher {
+        field("Publisher", &esc(publisher));
+    }
+
+    // Calibre stores the year 101 when the publication date is unknown.
+    if let Some(published_at) = book.published_at.filter(|date| date.year() > 101) {
+        field("Published", &format_date(published_at));
+    }
+
+    if !book.languages.is_empty() {
+        let languages = book
+            .languages
+            .iter()
+            .map(|code| language_name(code).unwrap_or(code))
+            .collect::<Vec<_>>();
+
+        field("Languages", &esc(&languages.join(", ")));
+    }
+
+    if let Some(rating) = book.rating {
+        field("Rating", &rating_stars(rating));
+    }
+
+    for custom_value in &book.custom_values {
+        let value = match &custom_value.value {
+            CustomValue::Text(values) => esc(&values.join(", ")).into_owned().into(),
+            CustomValue::Bool(true) => CompactString::const_new("Yes"),
+            CustomValue::Bool(false) => CompactString::const_new("No"),
+            CustomValue::Rating(rating) => rating_stars(*rating),
+            CustomValue::Date(date) => format_date(*date),
+        };
+
+        field(&custom_value.name, &value);
+    }
+
+    if !book.identifiers.is_empty() {
+        let identifiers = book
+            .identifiers
+            .iter()
+            .map(|identifier| format!("{}:{}", identifier.kind, identifier.value))
+            .collect::<Vec<_>>();
+
+        field("Identifiers", &esc(&identifiers.join(", ")));
+    }
+
+    html.push_str("</dl></div>");
+
+    if let Some(content) = &book.content {
+        // The description is HTML that Calibre sanitizes.
+        let _ = write!(html, "<div class=\"description\">{content}</div>");
+    }
+
+    html.push_str("</div>");
+    html
+}
+
+fn cover(html: &mut String, lib_name: &str, book: &FullBook, thumbnail: bool) {
+    if !book.has_cover {
+        html.push_str("<span class=\"no-cover\">No cover</span>");
+        return;
+    }
+
+    let src = match thumbnail {
+        true => book_thumbnail(lib_name, book.id),
+        false => book_cover(lib_name, book),
+    };
+
+    let _ = write!(
+        html,
+        "<img src=\"{}\" alt=\"The cover of {}\">",
+        esc_link(&src),
+        esc(&book.title)
+    );
+}
+
+fn download_buttons(html: &mut String, lib_name: &str, book: &FullBook, with_size: bool) {
+    if book.data.is_empty() {
+        return;
+    }
+
+    html.push_str("<div>");
+
+    for data in &book.data {
+        let format = data.format.to_uppercase();
+
+        let _ = write!(
+            html,
+            "<a class=\"button\" href=\"{}\" download>",
+            esc_link(&download_book(lib_name, book, data))
+        );
+
+        if with_size {
+            let _ = write!(html, "Download {format} ({})", file_size(data.file_size));
+        } else {
+            html.push_str(&format);
+        }
+
+        html.push_str("</a>");
+    }
+
+    html.push_str("</div>");
+}
+
+fn authors(book: &FullBook) -> String {
+    book.authors
+        .iter()
+        .map(|author| author.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns the link of the library's books that match the query.
+fn filter_link(lib: &Library, query: &str) -> CompactString {
+    links::explore_lib(
+        lib,
+        &super::super::ExploreCatalogQuery {
+            facets: super::super::facets::Facets {
+                query: Some(query.into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+}
+
+/// Quotes a value of Calibre's search language.
+pub(super) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Formats a file size in bytes, e.g. `1.2 MB`.
+fn file_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
@@ -0,0 +1,16 @@
+use compact_str::{CompactString, format_compact};
+
+use super::{super::ExploreCatalogQuery, COMMON_ROUTE as OPDS2_ROOT};
+use crate::library::Library;
+
+pub fn root() -> CompactString {
+    CompactString::const_new(OPDS2_ROOT)
+}
+
+pub fn lib_root(lib: &Library) -> CompactString {
+    format_compact!("{OPDS2_ROOT}/{}", super::super::links::enc(lib.name()))
+}
+
+pub fn explore_lib(lib: &Library, query: &ExploreCatalogQuery) -> CompactString {
+    super::super::links::with_query(format_compact!("{}/explore", lib_root(lib)), query)
+}
@@ -0,0 +1,310 @@
+//! The OPDS 2.0 version of the catalog, which is serialized to JSON instead of Atom.
+//!
+//! See <https://drafts.opds.io/opds-2.0>.
+
+pub(super) mod links;
+mod models;
+
+use actix_web::{HttpResponse, Responder, get, web};
+use compact_str::{CompactString, format_compact};
+use time::OffsetDateTime;
+
+use super::{ExploreCatalogQuery, FEED_TITLE, facets, page_size, pagination_links};
+use crate::{
+    errors::AppError,
+    library::{BookFilter, FullBook, OrderBooksBy},
+    router::auth::{LinkContext, UserLibraries},
+    utils::language_tag,
+};
+
+pub const COMMON_ROUTE: &str = "/opds2";
+
+pub(super) const MEDIA_TYPE: &str = "application/opds+json";
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(root)
+        .service(library_root)
+        .service(explore_catalog);
+}
+
+#[get("")]
+async fn root(libraries: UserLibraries) -> crate::Result<impl Responder> {
+    let mut updated_at = OffsetDateTime::UNIX_EPOCH;
+    let navigation = libraries
+        .get_all()
+        .map(|lib| {
+            if lib.updated_at() > updated_at {
+                updated_at = lib.updated_at();
+            }
+
+            models::Link {
+                title: Some(lib.name().into()),
+                ..models::Link::new(links::lib_root(lib), MEDIA_TYPE, None)
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .content_type(MEDIA_TYPE)
+        .json(models::Feed {
+            metadata: models::FeedMetadata::new(
+                CompactString::const_new(FEED_TITLE),
+                CompactString::const_new("Explore available libraries"),
+                updated_at,
+            ),
+            links: vec![
+                models::Link::new(links::root(), MEDIA_TYPE, Some("self")),
+                models::Link::new(links::root(), MEDIA_TYPE, Some("start")),
+                models::Link::new(
+                    CompactString::const_new(super::COMMON_ROUTE),
+                    super::models::LinkType::Navigation.as_str(),
+                    Some(super::models::LinkRel::Alternate.as_str()),
+                ),
+            ],
+            navigation,
+            facets: vec![],
+            publications: vec![],
+        }))
+}
+
+#[get("/{lib_name}")]
+async fn library_root(
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let explore = |title: &str, order_by: Option<OrderBooksBy>, rel: Option<&'static str>| {
+        let query = ExploreCatalogQuery {
+            facets: facets::Facets {
+                order_by,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        models::Link {
+            title: Some(title.into()),
+            ..models::Link::new(links::explore_lib(lib, &query), MEDIA_TYPE, rel)
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(MEDIA_TYPE)
+        .json(models::Feed {
+            metadata: models::FeedMetadata::new(
+                format_compact!("{lib_name} | {FEED_TITLE}"),
+                format_compact!("Exploring the \"{lib_name}\" library"),
+                lib.updated_at(),
+            ),
+            links: vec![
+                models::Link::new(links::lib_root(lib), MEDIA_TYPE, Some("self")),
+                models::Link::new(links::root(), MEDIA_TYPE, Some("start")),
+                models::Link::new(
+                    super::links::lib_root(lib),
+                    super::models::LinkType::Navigation.as_str(),
+                    Some(super::models::LinkRel::Alternate.as_str()),
+                ),
+            ],
+            navigation: vec![
+                explore("View Books", None, None),
+                explore(
+                    "View New Books",
+                    Some(OrderBooksBy::DateAdded),
+                    Some(super::models::LinkRel::SortNew.as_str()),
+                ),
+                explore("View Books by Title", Some(OrderBooksBy::Title), None),
+                explore("View Books by Author", Some(OrderBooksBy::Author), None),
+            ],
+            facets: vec![],
+            publications: vec![],
+        }))
+}
+
+#[get("/{lib_name}/explore")]
+async fn explore_catalog(
+    query: web::Query<ExploreCatalogQuery>,
+    libraries: UserLibraries,
+    lib_name: web::Path<String>,
+) -> crate::Result<impl Responder> {
+    let Some(lib) = libraries.get(&lib_name) else {
+        return Err(AppError::LibraryNotFound);
+    };
+
+    let order_by = query.facets.order_by.unwrap_or(OrderBooksBy::DateAdded);
+    let offset = query.offset.unwrap_or(0);
+    let limit = page_size(query.limit);
+
+    let unfaceted_filter = query.facets.apply_query(lib, &BookFilter::All)?;
+    let filter = query.facets.narrow(&unfaceted_filter);
+    let total = lib.count_books(&filter).await?;
+    let link_context = LinkContext::current();
+    let ((publications, _), has_next_page) = lib
+        .fetch_books(
+            limit,
+            offset,
+            order_by,
+            &filter,
+            (vec![], CompactString::from(lib.name())),
+            move |(mut acc, lib_name), book| {
+                acc.push(link_context.scope(|| publication(&lib_name, book)));
+                (acc, lib_name)
+            },
+        )
+        .await?;
+
+    let mut links = vec![
+        models::Link::new(links::explore_lib(lib, &query), MEDIA_TYPE, Some("self")),
+        models::Link::new(links::root(), MEDIA_TYPE, Some("start")),
+        models::Link::new(
+            super::links::explore_lib_with_query(lib, &query),
+            super::models::LinkType::Acquisition.as_str(),
+            Some(super::models::LinkRel::Alternate.as_str()),
+        ),
+    ];
+
+    links.extend(
+        pagination_links(total, offset, limit, has_next_page, |offset| {
+            links::explore_lib(
+                lib,
+                &ExploreCatalogQuery {
+                    facets: query.facets.clone(),
+                    offset: Some(offset),
+                    limit: Some(limit),
+                },
+            )
+        })
+        .into_iter()
+        .map(|link| models::Link::new(link.href, MEDIA_TYPE, link.rel)),
+    );
+
+    let facet_links = facets::facet_links(
+        lib,
+        &unfaceted_filter,
+        &query.facets,
+        OrderBooksBy::DateAdded,
+        total,
+        |facets| {
+            links::explore_lib(
+                lib,
+                &ExploreCatalogQuery {
+                    limit: query.limit,
+                    offset: None,
+                    facets,
+                },
+            )
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(MEDIA_TYPE)
+        .json(models::Feed {
+            metadata: models::FeedMetadata {
+                number_of_items: Some(total),
+                items_per_page: Some(limit.get()),
+                current_page: Some(offset / limit.get() + 1),
+                ..models::FeedMetadata::new(
+                    format_compact!("{lib_name} | {FEED_TITLE}"),
+                    format_compact!("Exploring the \"{lib_name}\" library"),
+                    lib.updated_at(),
+                )
+            },
+            facets: group_facets(facet_links),
+            navigation: vec![],
+            publications,
+            links,
+        }))
+}
+
+/// Groups the facet links of the Atom catalog by their `opds:facetGroup`.
+fn group_facets(facet_links: Vec<super::models::FacetLink>) -> Vec<models::Facet> {
+    let mut facets: Vec<models::Facet> = vec![];
+
+    for facet_link in facet_links {
+        let link = models::Link {
+            title: Some(facet_link.title),
+            properties: Some(models::LinkProperties {
+                number_of_items: facet_link.count,
+            }),
+            ..models::Link::new(
+                facet_link.href,
+                MEDIA_TYPE,
+                facet_link.active.then_some("self"),
+            )
+        };
+
+        match facets.last_mut() {
+            Some(facet) if facet.metadata.title == facet_link.group => facet.links.push(link),
+            _ => facets.push(models::Facet {
+                metadata: models::FacetMetadata {
+                    title: facet_link.group,
+                },
+                links: vec![link],
+            }),
+        }
+    }
+
+    facets
+}
+
+fn publication(lib_name: &str, book: FullBook) -> models::Publication {
+    let identifier = book.uri();
+    let links = book
+        .data
+        .iter()
+        .map(|data| {
+            models::Link::new(
+                super::links::download_book(lib_name, &book, data),
+                mime_guess::from_ext(&data.format)
+                    .first_raw()
+                    .unwrap_or("*/*"),
+                Some(super::models::LinkRel::Acquisition.as_str()),
+            )
+        })
+        .collect();
+    let images = if book.has_cover {
+        vec![
+            models::Link::new(
+                super::links::book_cover(lib_name, &book),
+                mime::IMAGE_JPEG.as_ref(),
+                None,
+            ),
+            models::Link::new(
+                super::links::book_thumbnail(lib_name, book.id),
+                mime::IMAGE_JPEG.as_ref(),
+                Some(super::models::LinkRel::Thumbnail.as_str()),
+            ),
+        ]
+    } else {
+        vec![]
+    };
+
+    models::Publication {
+        metadata: models::PublicationMetadata {
+            kind: "http://schema.org/Book",
+            title: book.title,
+            author: book
+                .authors
+                .into_iter()
+                .map(|author| models::Contributor { name: author.name })
+                .collect(),
+            language: book.languages.into_iter().map(language_tag).collect(),
+            subject: book.tags,
+            modified: book.last_modified_at,
+            published: book.published_at,
+            description: book.content,
+            belongs_to: book.series.map(|series| models::BelongsTo {
+                series: vec![models::SeriesCollection {
+                    name: series.name,
+                    position: series.index,
+                }],
+            }),
+            identifier,
+        },
+        links,
+        images,
+    }
+}
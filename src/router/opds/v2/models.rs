@@ -0,0 +1,139 @@
+use compact_str::CompactString;
+use serde::Serialize;
+use time::{OffsetDateTime, serde::rfc3339};
+
+/// See <https://drafts.opds.io/opds-2.0#2-collections>.
+#[derive(Debug, Serialize)]
+pub struct Feed {
+    pub metadata: FeedMetadata,
+    pub links: Vec<Link>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub navigation: Vec<Link>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub facets: Vec<Facet>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub publications: Vec<Publication>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedMetadata {
+    pub title: CompactString,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<CompactString>,
+    #[serde(with = "rfc3339")]
+    pub modified: OffsetDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number_of_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items_per_page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_page: Option<usize>,
+}
+
+impl FeedMetadata {
+    pub fn new(title: CompactString, subtitle: CompactString, modified: OffsetDateTime) -> Self {
+        Self {
+            subtitle: Some(subtitle),
+            number_of_items: None,
+            items_per_page: None,
+            current_page: None,
+            modified,
+            title,
+        }
+    }
+}
+
+/// See <https://readium.org/webpub-manifest/#24-the-link-object>.
+#[derive(Debug, Serialize)]
+pub struct Link {
+    #[serde(serialize_with = "crate::router::auth::serialize_link")]
+    pub href: CompactString,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rel: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<CompactString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<LinkProperties>,
+}
+
+impl Link {
+    pub fn new(href: CompactString, kind: &'static str, rel: Option<&'static str>) -> Self {
+        Self {
+            title: None,
+            properties: None,
+            href,
+            kind,
+            rel,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkProperties {
+    pub number_of_items: usize,
+}
+
+/// See <https://drafts.opds.io/opds-2.0#24-facets>.
+#[derive(Debug, Serialize)]
+pub struct Facet {
+    pub metadata: FacetMetadata,
+    pub links: Vec<Link>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FacetMetadata {
+    pub title: &'static str,
+}
+
+/// See <https://drafts.opds.io/opds-2.0#51-opds-publication>.
+#[derive(Debug, Serialize)]
+pub struct Publication {
+    pub metadata: PublicationMetadata,
+    pub links: Vec<Link>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Link>,
+}
+
+/// See <https://readium.org/webpub-manifest/schema/metadata.schema.json>.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicationMetadata {
+    #[serde(rename = "@type")]
+    pub kind: &'static str,
+    pub identifier: CompactString,
+    pub title: CompactString,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub author: Vec<Contributor>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub language: Vec<CompactString>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subject: Vec<CompactString>,
+    #[serde(with = "rfc3339")]
+    pub modified: OffsetDateTime,
+    #[serde(with = "rfc3339::option", skip_serializing_if = "Option::is_none")]
+    pub published: Option<OffsetDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<CompactString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub belongs_to: Option<BelongsTo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Contributor {
+    pub name: CompactString,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BelongsTo {
+    pub series: Vec<SeriesCollection>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeriesCollection {
+    pub name: CompactString,
+    pub position: f64,
+}
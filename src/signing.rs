@@ -0,0 +1,67 @@
+//! Signed links, which let a client download a file without credentials until they
+//! expire. They're signed with HMAC-SHA3-256, using a key that is generated on startup,
+//! so the links also expire when the server restarts.
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac as _};
+use sha3::Sha3_256;
+use time::OffsetDateTime;
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+/// Signs the links to files and verifies their signatures.
+pub struct LinkSigner {
+    key: [u8; 32],
+    ttl: Duration,
+}
+
+impl LinkSigner {
+    /// Creates a signer of links that are valid for the given duration.
+    pub fn new(ttl: Duration) -> eyre::Result<Self> {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key)
+            .map_err(|err| eyre::eyre!("Failed to generate the key of signed links: {err}"))?;
+
+        Ok(Self { key, ttl })
+    }
+
+    /// Signs the link, which must not have a query string, by appending its expiry and
+    /// signature to it.
+    pub fn sign(&self, link: &str) -> String {
+        let expires = (OffsetDateTime::now_utc() + self.ttl).unix_timestamp();
+
+        format!(
+            "{link}?expires={expires}&signature={}",
+            self.signature(link, expires)
+        )
+    }
+
+    /// Returns `true` if the signature of the path is valid and it hasn't expired.
+    pub fn verify(&self, path: &str, expires: i64, signature: &str) -> bool {
+        let mut signature_buf = [0; 32];
+        let Ok(signature) = base16ct::lower::decode(signature, &mut signature_buf) else {
+            return false;
+        };
+
+        expires > OffsetDateTime::now_utc().unix_timestamp()
+            && self.mac(path, expires).verify_slice(signature).is_ok()
+    }
+
+    fn signature(&self, path: &str, expires: i64) -> String {
+        let signature = self.mac(path, expires).finalize().into_bytes();
+        let mut signature_buf = [0; 64];
+
+        base16ct::lower::encode_str(&signature, &mut signature_buf)
+            .expect("the buffer fits the signature")
+            .to_owned()
+    }
+
+    fn mac(&self, path: &str, expires: i64) -> HmacSha3 {
+        let mut mac = HmacSha3::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+}
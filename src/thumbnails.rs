@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use image::{ImageReader, codecs::jpeg::JpegEncoder};
+
+use crate::{
+    disk_cache::DiskCache,
+    library::{BookCover, Library},
+};
+
+const JPEG_QUALITY: u8 = 85;
+
+/// The bounding box that thumbnails are resized to fit in. It's parsed from strings such
+/// as `240x360`.
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for ThumbnailSize {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((width, height)) = s.split_once('x') else {
+            return Err("expected WIDTHxHEIGHT, e.g. 240x360");
+        };
+        let parse = |n: &str| n.parse().ok().filter(|&n| n > 0);
+
+        match (parse(width), parse(height)) {
+            (Some(width), Some(height)) => Ok(Self { width, height }),
+            _ => Err("the width and the height must be positive integers"),
+        }
+    }
+}
+
+/// Generates thumbnails of book covers and caches them on disk.
+pub struct Thumbnails {
+    cache: DiskCache,
+    size: ThumbnailSize,
+}
+
+impl Thumbnails {
+    pub fn new(cache_dir: PathBuf, size: ThumbnailSize) -> Self {
+        Self {
+            cache: DiskCache::new(cache_dir.join("thumbnails")),
+            size,
+        }
+    }
+
+    /// Returns the path of the cover's thumbnail. It's generated if it's not cached yet,
+    /// or if the book was modified since it was cached.
+    pub async fn get(&self, lib: &Library, cover: &BookCover) -> crate::Result<PathBuf> {
+        let ThumbnailSize { width, height } = self.size;
+        let file_name = format!(
+            "{}-{width}x{height}.jpg",
+            cover.last_modified_at.unix_timestamp()
+        );
+        let cover_path = lib.root_path().join(&*cover.path).join("cover.jpg");
+
+        self.cache
+            .get(lib, cover.book_id, &file_name, move |path| {
+                let thumbnail = ImageReader::new(BufReader::new(File::open(cover_path)?))
+                    .with_guessed_format()?
+                    .decode()?
+                    .thumbnail(width, height)
+                    .into_rgb8();
+
+                JpegEncoder::new_with_quality(BufWriter::new(File::create(path)?), JPEG_QUALITY)
+                    .encode_image(&thumbnail)?;
+
+                Ok(())
+            })
+            .await
+    }
+}
@@ -0,0 +1,452 @@
+//! The users who may access the catalog, which are read from a file of lines such as
+//! `alice:$argon2id$v=19$m=19456,t=2,p=1$...`, with the passwords hashed by Argon2 in the
+//! PHC string format. Empty lines and the ones starting with `#` are ignored.
+//!
+//! A line may go on with the comma-separated names of the libraries the user may access,
+//! and a query in Calibre's search language that restricts the books they may see, like
+//! Calibre's per-user search restriction, e.g.
+//! `bob:$argon2id$...:Books,Comics:not tag:adult`. Empty fields don't limit anything.
+//!
+//! The devices of the users are read from another file of lines such as
+//! `alice:Kobo Clara:TOKEN`, and each device authenticates with its own token, which is
+//! revoked by removing its line. The file is read again whenever it's modified, so that
+//! tokens are revoked without restarting the server.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher as _, PasswordVerifier as _,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use compact_str::CompactString;
+use eyre::{Context as _, bail};
+use parking_lot::{Mutex, RwLock};
+
+use crate::utils::hash_str;
+
+#[derive(Debug)]
+pub struct User {
+    pub name: CompactString,
+    password_hash: String,
+    /// The names of the libraries the user may access, or `None` if they may access
+    /// every library.
+    pub libraries: Option<Vec<CompactString>>,
+    /// The query that the books the user may see match, if they're restricted.
+    pub restriction: Option<CompactString>,
+}
+
+/// The links that OPDS clients show on their login screens.
+#[derive(Debug, Default)]
+pub struct LoginLinks {
+    /// The URL of the catalog's logo.
+    pub logo: Option<String>,
+    /// The URL of a web page that helps users log in.
+    pub help: Option<String>,
+}
+
+/// A device that authenticates as its user with a token, for the readers that can't send
+/// credentials.
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub user: CompactString,
+    pub name: CompactString,
+}
+
+pub struct Users {
+    users: HashMap<CompactString, User>,
+    devices: RwLock<Devices>,
+    login_links: LoginLinks,
+    /// The hashes of the credentials that were verified, by username. Argon2 is slow on
+    /// purpose, and clients send the credentials with every request, including the ones
+    /// of thumbnails.
+    verified: Mutex<HashMap<CompactString, String>>,
+    /// The hash that the passwords of unknown users are verified against, so that they
+    /// take as long to reject as wrong passwords, and usernames can't be probed.
+    dummy_password_hash: String,
+}
+
+impl Users {
+    /// Reads the users from the file at the path.
+    pub fn open(path: &Path, login_links: LoginLinks) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read the users file at {path:?}"))?;
+        let mut users = HashMap::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.splitn(4, ':');
+            let (Some(name), Some(password_hash)) = (fields.next(), fields.next()) else {
+                bail!(
+                    "Line {} of {path:?} isn't formatted as USERNAME:HASH[:LIBRARIES[:RESTRICTION]]",
+                    i + 1
+                );
+            };
+            let libraries = fields
+                .next()
+                .filter(|libraries| !libraries.trim().is_empty())
+                .map(|libraries| {
+                    libraries
+                        .split(',')
+                        .map(|name| name.trim().into())
+                        .collect()
+                });
+            let restriction = fields
+                .next()
+                .map(str::trim)
+                .filter(|restriction| !restriction.is_empty())
+                .map(Into::into);
+
+            if let Err(err) = PasswordHash::new(password_hash) {
+                bail!(
+                    "Line {} of {path:?} has an invalid password hash: {err}",
+                    i + 1
+                );
+            }
+
+            let user = User {
+                password_hash: password_hash.to_owned(),
+                name: name.into(),
+                restriction,
+                libraries,
+            };
+
+            if users.insert(user.name.clone(), user).is_some() {
+                bail!("Line {} of {path:?} repeats the user \"{name}\"", i + 1);
+            }
+        }
+
+        debug!("Read {} users from {path:?}", users.len());
+
+        Ok(Self {
+            dummy_password_hash: hash_password("")?,
+            verified: Mutex::default(),
+            devices: RwLock::default(),
+            login_links,
+            users,
+        })
+    }
+
+    /// Reads the devices of the users from the file at the path, which has lines
+    /// formatted as `USERNAME:DEVICE:TOKEN`.
+    pub fn read_devices(&mut self, path: &Path) -> eyre::Result<()> {
+        let devices = Devices {
+            modified_at: modified_at(path),
+            by_token: self.parse_devices(path)?,
+            path: Some(path.to_owned()),
+        };
+
+        debug!(
+            "Read {} device tokens from {path:?}",
+            devices.by_token.len()
+        );
+        *self.devices.get_mut() = devices;
+
+        Ok(())
+    }
+
+    fn parse_devices(&self, path: &Path) -> eyre::Result<HashMap<String, Device>> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read the device tokens file at {path:?}"))?;
+        let mut devices = HashMap::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.splitn(3, ':').map(str::trim);
+            let (Some(user), Some(name), Some(token)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                bail!(
+                    "Line {} of {path:?} isn't formatted as USERNAME:DEVICE:TOKEN",
+                    i + 1
+                );
+            };
+
+            if !self.users.contains_key(user) {
+                bail!("Line {} of {path:?} has an unknown user \"{user}\"", i + 1);
+            }
+
+            // The tokens are embedded in URLs, and they stand in for passwords.
+            if token.len() < 16 || !token.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                bail!(
+                    "Line {} of {path:?} has a token that isn't at least 16 letters and digits long",
+                    i + 1
+                );
+            }
+
+            let device = Device {
+                user: user.into(),
+                name: name.into(),
+            };
+
+            if devices.insert(token.to_owned(), device).is_some() {
+                bail!("Line {} of {path:?} repeats a token", i + 1);
+            }
+        }
+
+        Ok(devices)
+    }
+
+    /// Returns the device that the token belongs to.
+    pub fn find_device(&self, token: &str) -> Option<Device> {
+        self.reread_modified_devices();
+        self.devices.read().by_token.get(token).cloned()
+    }
+
+    /// Reads the devices again if their file was modified since it was last read. If it
+    /// can't be read, every token is rejected until it's fixed, rather than letting the
+    /// revoked ones through.
+    fn reread_modified_devices(&self) {
+        let Some(path) = self.devices.read().path.clone() else {
+            return;
+        };
+        let file_modified_at = modified_at(&path);
+        let mut devices = self.devices.write();
+
+        if devices.modified_at == file_modified_at {
+            return;
+        }
+
+        devices.modified_at = file_modified_at;
+        devices.by_token = match self.parse_devices(&path) {
+            Ok(by_token) => {
+                info!("Read {} device tokens from {path:?} again", by_token.len());
+                by_token
+            }
+            Err(err) => {
+                error!("Rejecting every device token: {err}");
+                HashMap::new()
+            }
+        };
+    }
+
+    pub fn login_links(&self) -> &LoginLinks {
+        &self.login_links
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.users.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    /// Returns the user if the password is theirs.
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<&User> {
+        let Some(user) = self.users.get(name) else {
+            let password_hash = PasswordHash::new(&self.dummy_password_hash).ok()?;
+            let _ = Argon2::default().verify_password(password.as_bytes(), &password_hash);
+
+            return None;
+        };
+        let credentials_hash = hash_str(&format!("{name}:{password}"));
+
+        if self.verified.lock().get(name) == Some(&credentials_hash) {
+            return Some(user);
+        }
+
+        let password_hash = PasswordHash::new(&user.password_hash).ok()?;
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .ok()?;
+        self.verified
+            .lock()
+            .insert(user.name.clone(), credentials_hash);
+
+        Some(user)
+    }
+}
+
+/// The devices of the users, along with the file they were read from.
+#[derive(Default)]
+struct Devices {
+    path: Option<PathBuf>,
+    /// When the file was last modified, as of when it was last read.
+    modified_at: Option<SystemTime>,
+    by_token: HashMap<String, Device>,
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Hashes the password with the default parameters of Argon2id, for the users file.
+pub fn hash_password(password: &str) -> eyre::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(err) => bail!("Failed to hash the password: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Writes the lines to a users file that is removed when it's dropped.
+    struct UsersFile(PathBuf);
+
+    impl UsersFile {
+        fn new(name: &str, lines: &[&str]) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("seshat-users-{}-{name}.txt", std::process::id()));
+            std::fs::write(&path, lines.join("\n")).unwrap();
+
+            Self(path)
+        }
+
+        fn open(&self) -> eyre::Result<Users> {
+            Users::open(&self.0, LoginLinks::default())
+        }
+    }
+
+    impl Drop for UsersFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_users() {
+        let hash = hash_password("secret").unwrap();
+        let file = UsersFile::new(
+            "valid",
+            &[
+                "# A comment",
+                "",
+                &format!("alice:{hash}"),
+                &format!("bob:{hash}:Books, Comics:not tag:adult"),
+                &format!("carol:{hash}::vl:Kids"),
+            ],
+        );
+        let users = file.open().unwrap();
+
+        let alice = users.authenticate("alice", "secret").unwrap();
+        assert_eq!(alice.libraries, None);
+        assert_eq!(alice.restriction, None);
+
+        let bob = users.authenticate("bob", "secret").unwrap();
+        assert_eq!(
+            bob.libraries.as_deref(),
+            Some(&["Books".into(), "Comics".into()][..])
+        );
+        assert_eq!(bob.restriction.as_deref(), Some("not tag:adult"));
+
+        let carol = users.authenticate("carol", "secret").unwrap();
+        assert_eq!(carol.libraries, None);
+        assert_eq!(carol.restriction.as_deref(), Some("vl:Kids"));
+
+        assert!(users.authenticate("alice", "wrong").is_none());
+        assert!(users.authenticate("dave", "secret").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_users_files() {
+        let hash = hash_password("secret").unwrap();
+        let error = |name: &str, lines: &[&str]| {
+            UsersFile::new(name, lines)
+                .open()
+                .err()
+                .unwrap()
+                .to_string()
+        };
+
+        assert!(error("malformed", &["alice"]).contains("Line 1"));
+        assert!(error("bad-hash", &["", "alice:not-a-hash"]).contains("Line 2"));
+        assert!(
+            error(
+                "duplicate",
+                &[&format!("alice:{hash}"), &format!("alice:{hash}")]
+            )
+            .contains("repeats the user \"alice\"")
+        );
+        assert!(Users::open(Path::new("/nonexistent/users.txt"), LoginLinks::default()).is_err());
+    }
+
+    #[test]
+    fn reads_devices() {
+        let hash = hash_password("secret").unwrap();
+        let file = UsersFile::new("devices-users", &[&format!("alice:{hash}")]);
+        let mut users = file.open().unwrap();
+        let devices = UsersFile::new(
+            "devices",
+            &["# Devices", "alice:Kobo Clara:abcdefghijklmnop0123"],
+        );
+
+        users.read_devices(&devices.0).unwrap();
+
+        let device = users.find_device("abcdefghijklmnop0123").unwrap();
+        assert_eq!(device.user, "alice");
+        assert_eq!(device.name, "Kobo Clara");
+        assert!(users.find_device("abcdefghijklmnop").is_none());
+
+        for (name, lines) in [
+            ("unknown-user", &["bob:Phone:abcdefghijklmnop4567"][..]),
+            ("short-token", &["alice:Phone:short"]),
+            (
+                "repeated-token",
+                &[
+                    "alice:Kobo Clara:abcdefghijklmnop0123",
+                    "alice:Phone:abcdefghijklmnop0123",
+                ],
+            ),
+            ("malformed-device", &["alice:abcdefghijklmnop4567"]),
+        ] {
+            let devices = UsersFile::new(name, lines);
+            assert!(users.read_devices(&devices.0).is_err(), "{lines:?}");
+        }
+    }
+
+    #[test]
+    fn rereads_modified_devices() {
+        let hash = hash_password("secret").unwrap();
+        let file = UsersFile::new("reread-users", &[&format!("alice:{hash}")]);
+        let mut users = file.open().unwrap();
+        let devices = UsersFile::new(
+            "reread",
+            &[
+                "alice:Kobo Clara:abcdefghijklmnop0123",
+                "alice:Phone:abcdefghijklmnop4567",
+            ],
+        );
+        users.read_devices(&devices.0).unwrap();
+
+        let modify = |contents: &str, seconds: u64| {
+            std::fs::write(&devices.0, contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&devices.0)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds))
+                .unwrap();
+        };
+
+        modify("alice:Phone:abcdefghijklmnop4567", 1);
+        assert!(users.find_device("abcdefghijklmnop0123").is_none());
+        assert!(users.find_device("abcdefghijklmnop4567").is_some());
+
+        // An invalid file doesn't let the revoked tokens through.
+        modify("alice:Phone", 2);
+        assert!(users.find_device("abcdefghijklmnop4567").is_none());
+    }
+}
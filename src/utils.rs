@@ -16,9 +16,41 @@ pub fn hash_str(str: &str) -> String {
     unsafe { String::from_utf8_unchecked(hash_buf) }
 }
 
+/// Returns the English name of a language, given its ISO 639 code, as stored by Calibre.
+pub fn language_name(lang_code: &str) -> Option<&'static str> {
+    isolang::Language::from_639_3(lang_code)
+        .or_else(|| isolang::Language::from_639_1(lang_code))
+        .map(|lang| lang.to_name())
+}
+
+/// Converts an ISO 639 code, as stored by Calibre, into a BCP 47 language tag, which
+/// prefers the two-letter codes where available.
+pub fn language_tag(lang_code: CompactString) -> CompactString {
+    isolang::Language::from_639_3(&lang_code)
+        .and_then(|lang| lang.to_639_1())
+        .map_or(lang_code, CompactString::const_new)
+}
+
 pub trait HttpResponseBuilderExt {
     /// Respond with an XML body.
     fn xml<T: serde::Serialize>(self, value: &T) -> crate::Result<HttpResponse<BoxBody>>;
+
+    /// Respond with an XML body of the given media type.
+    fn xml_as<T: serde::Serialize>(
+        self,
+        content_type: &'static str,
+        value: &T,
+    ) -> crate::Result<HttpResponse<BoxBody>>;
+
+    /// Respond with an XML body of the given media type, whose root element is named
+    /// `root_tag`. It's required by types that are serialized as maps, such as structs
+    /// with flattened fields.
+    fn xml_with_root_as<T: serde::Serialize>(
+        self,
+        content_type: &'static str,
+        root_tag: &'static str,
+        value: &T,
+    ) -> crate::Result<HttpResponse<BoxBody>>;
 }
 
 impl HttpResponseBuilderExt for HttpResponseBuilder {
@@ -27,6 +59,33 @@ impl HttpResponseBuilderExt for HttpResponseBuilder {
             .insert_header(actix_web::http::header::ContentType(mime::TEXT_XML))
             .body(quick_xml::se::to_string(value)?))
     }
+
+    fn xml_as<T: serde::Serialize>(
+        mut self,
+        content_type: &'static str,
+        value: &T,
+    ) -> crate::Result<HttpResponse<BoxBody>> {
+        Ok(self
+            .insert_header((actix_web::http::header::CONTENT_TYPE, content_type))
+            .body(quick_xml::se::to_string(value)?))
+    }
+
+    fn xml_with_root_as<T: serde::Serialize>(
+        mut self,
+        content_type: &'static str,
+        root_tag: &'static str,
+        value: &T,
+    ) -> crate::Result<HttpResponse<BoxBody>> {
+        let mut body = String::new();
+        value.serialize(quick_xml::se::Serializer::with_root(
+            &mut body,
+            Some(root_tag),
+        )?)?;
+
+        Ok(self
+            .insert_header((actix_web::http::header::CONTENT_TYPE, content_type))
+            .body(body))
+    }
 }
 
 /// A [`CompatString`] newtype for use with `rusqlite`.